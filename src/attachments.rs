@@ -0,0 +1,246 @@
+use std::ops::Range;
+
+use serde::Deserialize;
+use url::Url;
+
+use crate::{github::GithubApi, kanbanize::KanbanizeApi};
+
+/// What happens to images and files hosted by the tracker, which GitHub can't
+/// display without the tracker credentials.
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageMode {
+    /// Left as they are.
+    #[default]
+    Keep,
+    /// Replaced by a link named after the file, opened with a tracker login.
+    Link,
+    /// Downloaded and committed to the repository, see
+    /// [`GithubApi::upload_asset`].
+    Upload,
+}
+
+/// Markdown image or link.
+#[derive(Debug, PartialEq)]
+pub struct Reference {
+    /// Position of the whole reference in the markdown.
+    pub range: Range<usize>,
+    pub image: bool,
+    pub text: String,
+    pub url: Url,
+}
+
+impl Reference {
+    /// Name of the referenced file, from the link text or the URL.
+    pub fn file_name(&self) -> String {
+        if !self.text.trim().is_empty() {
+            return self.text.trim().to_string();
+        }
+        self.url
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .filter(|name| !name.is_empty())
+            .unwrap_or("attachment")
+            .to_string()
+    }
+
+    /// The same reference pointing to `url`.
+    fn with_url(&self, url: &Url) -> String {
+        format!(
+            "{}[{}]({})",
+            if self.image { "!" } else { "" },
+            self.text,
+            url
+        )
+    }
+
+    /// A plain link named after the file, images are not rendered.
+    fn as_link(&self) -> String {
+        format!("[{}]({})", self.file_name(), self.url)
+    }
+}
+
+/// Images and links with an absolute URL in `markdown`.
+pub fn references(markdown: &str) -> Vec<Reference> {
+    let re = regex::Regex::new(r#"(!?)\[([^\]]*)\]\(<?([^)\s>]+)>?(?:\s+"[^"]*")?\)"#).unwrap();
+    re.captures_iter(markdown)
+        .filter_map(|cap| {
+            Some(Reference {
+                range: cap.get(0)?.range(),
+                image: !cap[1].is_empty(),
+                text: cap[2].to_string(),
+                url: Url::parse(&cap[3]).ok()?,
+            })
+        })
+        .collect()
+}
+
+/// Rewrites the references to files hosted by `tracker` according to `mode`.
+/// Files that can't be uploaded fall back to a link.
+pub async fn rehost(
+    markdown: &str,
+    mode: ImageMode,
+    tracker: &dyn KanbanizeApi,
+    github: &dyn GithubApi,
+    repository: &str,
+    card_id: &str,
+) -> String {
+    if mode == ImageMode::Keep {
+        return markdown.to_string();
+    }
+
+    let mut result = String::with_capacity(markdown.len());
+    let mut end = 0;
+    for reference in references(markdown) {
+        if !tracker.is_attachment(&reference.url) {
+            continue;
+        }
+        let replacement = match mode {
+            ImageMode::Upload => {
+                match upload(&reference, tracker, github, repository, card_id).await {
+                    Ok(url) => reference.with_url(&url),
+                    Err(e) => {
+                        tracing::warn!("Could not upload {}: {}", reference.url, e);
+                        reference.as_link()
+                    }
+                }
+            }
+            _ => reference.as_link(),
+        };
+        result.push_str(&markdown[end..reference.range.start]);
+        result.push_str(&replacement);
+        end = reference.range.end;
+    }
+    result.push_str(&markdown[end..]);
+    result
+}
+
+async fn upload(
+    reference: &Reference,
+    tracker: &dyn KanbanizeApi,
+    github: &dyn GithubApi,
+    repository: &str,
+    card_id: &str,
+) -> Result<Url, Box<dyn std::error::Error>> {
+    // Files are named after their address, so they can be found again
+    // without downloading them
+    let path = format!(
+        "{}/{:016x}-{}",
+        card_id,
+        fnv1a(reference.url.as_str()),
+        asset_name(&reference.url)
+    );
+    if let Some(url) = github.find_asset(repository, &path).await? {
+        return Ok(url);
+    }
+    let content = tracker.download(&reference.url).await?;
+    github.upload_asset(repository, &path, &content).await
+}
+
+/// 64-bit FNV-1a hash, stable across releases unlike the standard hasher.
+fn fnv1a(value: &str) -> u64 {
+    value.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// File name of an asset, made safe to use in a repository path.
+fn asset_name(url: &Url) -> String {
+    url.path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .filter(|name| !name.is_empty())
+        .unwrap_or("attachment")
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '_' => c,
+            _ => '-',
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{github::MockGithubApi, kanbanize::MockKanbanizeApi};
+
+    const MARKDOWN: &str = "See ![](https://acme.kanbanize.com/files/screen%20shot.png) and [the log](https://acme.kanbanize.com/files/log.txt), not ![logo](https://example.com/logo.png).";
+
+    fn tracker() -> MockKanbanizeApi {
+        let mut tracker = MockKanbanizeApi::new();
+        tracker
+            .expect_is_attachment()
+            .returning(|url| url.host_str() == Some("acme.kanbanize.com"));
+        tracker
+    }
+
+    #[test]
+    fn test_references() {
+        let references = references(MARKDOWN);
+        assert_eq!(references.len(), 3);
+        assert!(references[0].image);
+        assert_eq!(references[0].file_name(), "screen%20shot.png");
+        assert!(!references[1].image);
+        assert_eq!(references[1].file_name(), "the log");
+        assert_eq!(references[2].url.as_str(), "https://example.com/logo.png");
+    }
+
+    #[tokio::test]
+    async fn test_rehost_link() {
+        let github = MockGithubApi::new();
+        assert_eq!(
+            rehost(MARKDOWN, ImageMode::Link, &tracker(), &github, "acme/web", "42").await,
+            "See [screen%20shot.png](https://acme.kanbanize.com/files/screen%20shot.png) and [the log](https://acme.kanbanize.com/files/log.txt), not ![logo](https://example.com/logo.png)."
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rehost_upload() {
+        let mut tracker = tracker();
+        tracker.expect_download().returning(|url| {
+            if url.path().ends_with(".png") {
+                Ok(vec![1, 2, 3])
+            } else {
+                Err("forbidden".into())
+            }
+        });
+        let mut github = MockGithubApi::new();
+        github.expect_find_asset().returning(|_, _| Ok(None));
+        github
+            .expect_upload_asset()
+            .withf(|repository, path, content| {
+                repository == "acme/web"
+                    && path == "42/7de503c79e3e781d-screen-20shot.png"
+                    && content == [1, 2, 3]
+            })
+            .return_once(|_, path, _| {
+                Ok(Url::parse(&format!(
+                    "https://github.com/acme/web/blob/gitkban-assets/{}?raw=true",
+                    path
+                ))?)
+            });
+
+        assert_eq!(
+            rehost(MARKDOWN, ImageMode::Upload, &tracker, &github, "acme/web", "42").await,
+            "See ![](https://github.com/acme/web/blob/gitkban-assets/42/7de503c79e3e781d-screen-20shot.png?raw=true) and [the log](https://acme.kanbanize.com/files/log.txt), not ![logo](https://example.com/logo.png)."
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rehost_uploaded() {
+        let mut tracker = tracker();
+        tracker.expect_download().never();
+        let mut github = MockGithubApi::new();
+        github.expect_find_asset().returning(|repository, path| {
+            Ok(Some(Url::parse(&format!(
+                "https://github.com/{}/blob/gitkban-assets/{}?raw=true",
+                repository, path
+            ))?))
+        });
+        github.expect_upload_asset().never();
+
+        assert_eq!(
+            rehost(MARKDOWN, ImageMode::Upload, &tracker, &github, "acme/web", "42").await,
+            "See ![](https://github.com/acme/web/blob/gitkban-assets/42/7de503c79e3e781d-screen-20shot.png?raw=true) and [the log](https://github.com/acme/web/blob/gitkban-assets/42/9d8cc8a5ed650f91-log.txt?raw=true), not ![logo](https://example.com/logo.png)."
+        );
+    }
+}
@@ -0,0 +1,285 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::kanbanize::Card;
+
+type Error = Box<dyn std::error::Error>;
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Change of a pull request body.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub timestamp: DateTime<Utc>,
+    /// API address of the pull request.
+    pub pull_request: Url,
+    /// Card the new body was built from, if any.
+    pub card_id: Option<String>,
+    pub revision: Option<String>,
+    pub previous_body: String,
+    pub body: String,
+    /// Number of the change this one undid, for `gitkban revert`.
+    #[serde(default)]
+    pub reverts: Option<usize>,
+}
+
+impl Entry {
+    pub fn new(pull_request: &Url, card: Option<&Card>, previous_body: &str, body: &str) -> Self {
+        Self {
+            timestamp: Utc::now(),
+            pull_request: pull_request.clone(),
+            card_id: card.map(|card| card.id.clone()),
+            revision: card.and_then(|card| card.revision.clone()),
+            previous_body: previous_body.to_string(),
+            body: body.to_string(),
+            reverts: None,
+        }
+    }
+}
+
+/// Append-only log of the pull request bodies written, one JSON entry per
+/// line.
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    path: PathBuf,
+}
+
+impl AuditLog {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+        }
+    }
+
+    pub fn append(&self, entry: &Entry) -> Result<()> {
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| format!("Could not open {}: {}", self.path.display(), e))?;
+        // A single write per entry keeps lines whole when processes append at once
+        file.write_all(format!("{}\n", serde_json::to_string(entry)?).as_bytes())?;
+        Ok(())
+    }
+
+    /// Entries of the pull request at `url`, oldest first. Both the GitHub
+    /// page and the API address of a pull request are accepted.
+    pub fn history(&self, url: &str) -> Result<Vec<Entry>> {
+        let key = pull_key(url).ok_or_else(|| format!("`{}` is not a pull request URL", url))?;
+        Ok(self
+            .entries()?
+            .into_iter()
+            .filter(|entry| pull_key(entry.pull_request.as_str()).as_ref() == Some(&key))
+            .collect())
+    }
+
+    /// API addresses of the pull requests whose latest change is a revert,
+    /// which gitkban leaves alone from then on.
+    pub fn reverted(&self) -> Result<HashSet<Url>> {
+        let mut latest = HashMap::new();
+        for entry in self.entries()? {
+            if let Some(key) = pull_key(entry.pull_request.as_str()) {
+                latest.insert(key, entry);
+            }
+        }
+        Ok(latest
+            .into_values()
+            .filter(|entry| entry.reverts.is_some())
+            .map(|entry| entry.pull_request)
+            .collect())
+    }
+
+    /// Every entry of the log, oldest first.
+    fn entries(&self) -> Result<Vec<Entry>> {
+        let content = match std::fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(format!("Could not read {}: {}", self.path.display(), e).into()),
+        };
+        let mut entries = Vec::new();
+        for (number, line) in content.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: Entry = serde_json::from_str(line).map_err(|e| {
+                format!(
+                    "Invalid entry on line {} of {}: {}",
+                    number + 1,
+                    self.path.display(),
+                    e
+                )
+            })?;
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+}
+
+/// Repository, lowercased, and number of a pull request from its page, such
+/// as `https://github.com/acme/web/pull/12`, or its API address.
+fn pull_key(url: &str) -> Option<(String, u64)> {
+    let url = Url::parse(url).ok()?;
+    let re = regex::Regex::new(r"^/(?:repos/)?([^/]+/[^/]+)/pulls?/(\d+)/?$").unwrap();
+    let captures = re.captures(url.path())?;
+    Some((captures[1].to_lowercase(), captures[2].parse().ok()?))
+}
+
+/// Numbered entries with the body before and after each change.
+pub fn render(entries: &[Entry]) -> String {
+    entries
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| {
+            let mut heading = format!(
+                "#{} {}",
+                index + 1,
+                entry.timestamp.to_rfc3339_opts(SecondsFormat::Secs, true)
+            );
+            match (&entry.card_id, &entry.revision) {
+                (Some(card), Some(revision)) => {
+                    heading.push_str(&format!(" card {} (revision {})", card, revision))
+                }
+                (Some(card), None) => heading.push_str(&format!(" card {}", card)),
+                (None, _) => {}
+            }
+            match entry.reverts {
+                Some(number) => heading.push_str(&format!(" reverting #{}", number)),
+                None if entry.card_id.is_none() => heading.push_str(" restored"),
+                None => {}
+            }
+            format!(
+                "{}\n--- before\n{}\n+++ after\n{}",
+                heading,
+                body_or_empty(&entry.previous_body),
+                body_or_empty(&entry.body)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+fn body_or_empty(body: &str) -> &str {
+    match body.trim_end() {
+        "" => "(empty)",
+        body => body,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(url: &str, card: Option<&Card>, previous_body: &str, body: &str) -> Entry {
+        let mut entry = Entry::new(&Url::parse(url).unwrap(), card, previous_body, body);
+        entry.timestamp = "2026-10-18T10:00:00Z".parse().unwrap();
+        entry
+    }
+
+    #[test]
+    fn test_history() -> testresult::TestResult {
+        let path = std::env::temp_dir().join("gitkban_test_audit.jsonl");
+        let _ = std::fs::remove_file(&path);
+        let log = AuditLog::new(&path);
+        assert!(log
+            .history("https://github.com/acme/web/pull/12")?
+            .is_empty());
+
+        let card = Card {
+            id: "4321".to_string(),
+            revision: Some("7".to_string()),
+            ..Default::default()
+        };
+        let first = entry(
+            "https://api.github.com/repos/acme/web/pulls/12",
+            Some(&card),
+            "",
+            "Card body",
+        );
+        log.append(&first)?;
+        log.append(&entry(
+            "https://api.github.com/repos/acme/web/pulls/13",
+            Some(&card),
+            "",
+            "Other body",
+        ))?;
+        let second = entry(
+            "https://api.github.com/repos/acme/web/pulls/12",
+            None,
+            "Card body",
+            "",
+        );
+        log.append(&second)?;
+
+        assert_eq!(
+            log.history("https://github.com/Acme/Web/pull/12")?,
+            vec![first.clone(), second.clone()]
+        );
+        assert_eq!(
+            log.history("https://api.github.com/repos/acme/web/pulls/12")?,
+            vec![first, second]
+        );
+        assert!(log.history("acme/web#12").is_err());
+        assert!(log.reverted()?.is_empty());
+
+        let revert = Entry {
+            reverts: Some(1),
+            ..entry(
+                "https://api.github.com/repos/acme/web/pulls/12",
+                Some(&card),
+                "",
+                "Card body",
+            )
+        };
+        log.append(&revert)?;
+        assert_eq!(
+            log.reverted()?,
+            HashSet::from([Url::parse(
+                "https://api.github.com/repos/acme/web/pulls/12"
+            )?])
+        );
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_render() {
+        let card = Card {
+            id: "4321".to_string(),
+            revision: Some("7".to_string()),
+            ..Default::default()
+        };
+        let entries = [
+            entry(
+                "https://api.github.com/repos/acme/web/pulls/12",
+                Some(&card),
+                "",
+                "Card body\n",
+            ),
+            entry(
+                "https://api.github.com/repos/acme/web/pulls/12",
+                None,
+                "Card body\n",
+                "",
+            ),
+            Entry {
+                reverts: Some(1),
+                ..entry(
+                    "https://api.github.com/repos/acme/web/pulls/12",
+                    Some(&card),
+                    "Edited\n",
+                    "",
+                )
+            },
+        ];
+        assert_eq!(
+            render(&entries),
+            "#1 2026-10-18T10:00:00Z card 4321 (revision 7)\n--- before\n(empty)\n+++ after\nCard body\n\n#2 2026-10-18T10:00:00Z restored\n--- before\nCard body\n+++ after\n(empty)\n\n#3 2026-10-18T10:00:00Z card 4321 (revision 7) reverting #1\n--- before\nEdited\n+++ after\n(empty)"
+        );
+    }
+}
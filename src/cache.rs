@@ -0,0 +1,620 @@
+use std::{
+    collections::HashMap,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{
+    kanbanize::{Card, KanbanizeApi, User},
+    metrics::Metrics,
+};
+
+type Error = Box<dyn std::error::Error>;
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Options of the card cache.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct CacheConfig {
+    /// Seconds a cached card is used without checking its revision.
+    pub ttl_seconds: u64,
+    /// Cards kept, the least recently checked ones are dropped first.
+    pub max_entries: usize,
+    /// Directory the cards are saved in, to keep them across restarts.
+    pub path: Option<PathBuf>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl_seconds: 300,
+            max_entries: 1000,
+            path: None,
+        }
+    }
+}
+
+/// Shortest time between two writes of the cache file, so that a polling
+/// cycle writes it once instead of once per card. The last changes are
+/// written when the tracker is dropped.
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    card: Card,
+    /// Seconds since the epoch when the card was last known to be current.
+    checked_at: u64,
+}
+
+/// Keeps the cards found by a tracker client. Cached cards are used as is
+/// until the TTL expires, then only their revision is fetched again, the whole
+/// card only when it changed.
+pub struct CachedTracker {
+    name: String,
+    inner: Box<dyn KanbanizeApi>,
+    config: CacheConfig,
+    metrics: Arc<Metrics>,
+    entries: Mutex<HashMap<String, Entry>>,
+    /// When the file was last written, and whether the cards changed since.
+    saved: Mutex<(Option<Instant>, bool)>,
+}
+
+impl CachedTracker {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(
+        name: &str,
+        inner: Box<dyn KanbanizeApi>,
+        config: CacheConfig,
+        metrics: Arc<Metrics>,
+    ) -> Box<dyn KanbanizeApi> {
+        let tracker = CachedTracker {
+            name: name.to_string(),
+            inner,
+            config,
+            metrics,
+            entries: Mutex::default(),
+            saved: Mutex::default(),
+        };
+        if let Some(file) = tracker.file() {
+            *tracker.entries.lock().unwrap() = load(&file);
+        }
+        Box::new(tracker)
+    }
+
+    fn file(&self) -> Option<PathBuf> {
+        self.config
+            .path
+            .as_ref()
+            .map(|dir| dir.join(format!("{}.json", self.name)))
+    }
+
+    /// Stores `card` as current, dropping the oldest cards past the maximum.
+    fn insert(&self, id: &str, card: Card) {
+        self.insert_all(vec![(id.to_string(), card)]);
+    }
+
+    /// Stores `cards` as current and saves the cache once, dropping the
+    /// oldest other cards past the maximum.
+    fn insert_all(&self, cards: Vec<(String, Card)>) {
+        if cards.is_empty() {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        let checked_at = now();
+        let ids: Vec<String> = cards.iter().map(|(id, _)| id.clone()).collect();
+        for (id, card) in cards {
+            entries.insert(id, Entry { card, checked_at });
+        }
+        while entries.len() > self.config.max_entries {
+            let Some(oldest) = entries
+                .iter()
+                .filter(|(cached, _)| !ids.contains(cached))
+                .min_by_key(|(_, entry)| entry.checked_at)
+                .map(|(id, _)| id.clone())
+            else {
+                break;
+            };
+            entries.remove(&oldest);
+        }
+        self.changed(&entries);
+    }
+
+    /// Drops the card `id`, also when it was looked up with another ID, such
+    /// as one with leading zeros.
+    fn invalidate(&self, id: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        let count = entries.len();
+        entries.retain(|cached, entry| cached != id && entry.card.id != id);
+        if entries.len() != count {
+            self.changed(&entries);
+        }
+    }
+
+    /// Saves the cards, unless the file was written less than
+    /// [`SAVE_INTERVAL`] ago.
+    fn changed(&self, entries: &HashMap<String, Entry>) {
+        let mut saved = self.saved.lock().unwrap();
+        match saved.0 {
+            Some(at) if at.elapsed() < SAVE_INTERVAL => saved.1 = true,
+            _ => {
+                self.save(entries);
+                *saved = (Some(Instant::now()), false);
+            }
+        }
+    }
+
+    fn save(&self, entries: &HashMap<String, Entry>) {
+        let Some(file) = self.file() else {
+            return;
+        };
+        let result = file
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .map_err(Error::from)
+            .and_then(|_| Ok(serde_json::to_vec(entries)?))
+            .and_then(|content| write_private(&file, &content));
+        if let Err(e) = result {
+            tracing::warn!("Could not save the card cache to {}: {}", file.display(), e);
+        }
+    }
+}
+
+impl Drop for CachedTracker {
+    fn drop(&mut self) {
+        if self.saved.lock().unwrap().1 {
+            self.save(&self.entries.lock().unwrap());
+        }
+    }
+}
+
+/// Replaces `file` with `content`, readable by the owner only since cards
+/// hold private content. A temporary file is renamed over it, so readers
+/// never see a partial file.
+fn write_private(file: &Path, content: &[u8]) -> Result<()> {
+    let temporary = file.with_extension("json.tmp");
+    let _ = std::fs::remove_file(&temporary);
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(&temporary)?.write_all(content)?;
+    std::fs::rename(&temporary, file)?;
+    Ok(())
+}
+
+/// Reads the cards saved in `file`, starting empty if there are none.
+fn load(file: &Path) -> HashMap<String, Entry> {
+    let content = match std::fs::read(file) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return HashMap::new(),
+        Err(e) => {
+            tracing::warn!("Could not read the card cache {}: {}", file.display(), e);
+            return HashMap::new();
+        }
+    };
+    serde_json::from_slice(&content).unwrap_or_else(|e| {
+        tracing::warn!("Ignoring invalid card cache {}: {}", file.display(), e);
+        HashMap::new()
+    })
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+#[async_trait::async_trait]
+impl KanbanizeApi for CachedTracker {
+    fn card_id_from_branch(&self, branch: &str) -> Option<String> {
+        self.inner.card_id_from_branch(branch)
+    }
+
+    fn branch_pattern(&self) -> &'static str {
+        self.inner.branch_pattern()
+    }
+
+    async fn find_by_id(&self, id: &str) -> Result<Card> {
+        let cached = self.entries.lock().unwrap().get(id).cloned();
+        if let Some(entry) = cached {
+            if now().saturating_sub(entry.checked_at) < self.config.ttl_seconds {
+                self.metrics.cache_lookup(&self.name, "hit");
+                return Ok(entry.card);
+            }
+            if let Some(revision) = entry.card.revision.as_ref() {
+                let current = match self.inner.revision(id).await {
+                    Ok(current) => current,
+                    Err(e) => {
+                        tracing::debug!("Could not check the revision of card {}: {}", id, e);
+                        None
+                    }
+                };
+                if current.as_ref() == Some(revision) {
+                    self.metrics.cache_lookup(&self.name, "revalidated");
+                    self.insert(id, entry.card.clone());
+                    return Ok(entry.card);
+                }
+            }
+        }
+
+        self.metrics.cache_lookup(&self.name, "miss");
+        let card = self.inner.find_by_id(id).await?;
+        self.insert(id, card.clone());
+        Ok(card)
+    }
+
+    /// Cards still within the TTL are taken from the cache. The revisions of
+    /// the expired ones are checked together, and only the cards that changed
+    /// or were not cached are fetched.
+    async fn find_many(&self, ids: &[String]) -> Result<HashMap<String, Card>> {
+        let mut cards = HashMap::new();
+        let mut expired = HashMap::new();
+        let mut missing = Vec::new();
+        {
+            let entries = self.entries.lock().unwrap();
+            for id in ids {
+                match entries.get(id) {
+                    Some(entry)
+                        if now().saturating_sub(entry.checked_at) < self.config.ttl_seconds =>
+                    {
+                        cards.insert(id.clone(), entry.card.clone());
+                    }
+                    Some(entry) if entry.card.revision.is_some() => {
+                        expired.insert(id.clone(), entry.card.clone());
+                    }
+                    _ => missing.push(id.clone()),
+                }
+            }
+        }
+        for _ in &cards {
+            self.metrics.cache_lookup(&self.name, "hit");
+        }
+
+        let mut revalidated = Vec::new();
+        if !expired.is_empty() {
+            let expired_ids: Vec<String> = expired.keys().cloned().collect();
+            let current = match self.inner.revisions(&expired_ids).await {
+                Ok(current) => current,
+                Err(e) => {
+                    tracing::debug!("Could not check the revisions of the cards: {}", e);
+                    HashMap::new()
+                }
+            };
+            for (id, card) in expired {
+                if current.get(&id) == card.revision.as_ref() {
+                    self.metrics.cache_lookup(&self.name, "revalidated");
+                    revalidated.push((id, card));
+                } else {
+                    missing.push(id);
+                }
+            }
+        }
+
+        let mut found = Vec::new();
+        if !missing.is_empty() {
+            missing.sort();
+            for _ in &missing {
+                self.metrics.cache_lookup(&self.name, "miss");
+            }
+            found = self.inner.find_many(&missing).await?.into_iter().collect();
+        }
+        for (id, card) in revalidated.iter().chain(&found) {
+            cards.insert(id.clone(), card.clone());
+        }
+        revalidated.extend(found);
+        self.insert_all(revalidated);
+        Ok(cards)
+    }
+
+    async fn revision(&self, id: &str) -> Result<Option<String>> {
+        self.inner.revision(id).await
+    }
+
+    async fn revisions(&self, ids: &[String]) -> Result<HashMap<String, String>> {
+        self.inner.revisions(ids).await
+    }
+
+    async fn search(&self, text: &str) -> Result<Vec<Card>> {
+        self.inner.search(text).await
+    }
+
+    async fn cards_in_column(&self, column: &str) -> Result<Vec<Card>> {
+        self.inner.cards_in_column(column).await
+    }
+
+    async fn move_card(&self, id: &str, column: &str) -> Result<()> {
+        let result = self.inner.move_card(id, column).await;
+        self.invalidate(id);
+        result
+    }
+
+    async fn complete_subtask(&self, card_id: &str, subtask_id: &str) -> Result<()> {
+        let result = self.inner.complete_subtask(card_id, subtask_id).await;
+        self.invalidate(card_id);
+        result
+    }
+
+    fn is_attachment(&self, url: &Url) -> bool {
+        self.inner.is_attachment(url)
+    }
+
+    async fn download(&self, url: &Url) -> Result<Vec<u8>> {
+        self.inner.download(url).await
+    }
+
+    async fn users(&self) -> Result<Vec<User>> {
+        self.inner.users().await
+    }
+
+    async fn check(&self) -> Result<()> {
+        self.inner.check().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::kanbanize::MockKanbanizeApi;
+
+    fn card(id: &str, revision: &str) -> Card {
+        Card {
+            id: id.to_string(),
+            title: format!("Card {}", id),
+            revision: Some(revision.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn config(ttl_seconds: u64) -> CacheConfig {
+        CacheConfig {
+            ttl_seconds,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hit() -> testresult::TestResult {
+        let metrics = Arc::new(Metrics::default());
+        let mut inner = MockKanbanizeApi::new();
+        inner
+            .expect_find_by_id()
+            .times(1)
+            .returning(|id| Ok(card(id, "1")));
+
+        let tracker = CachedTracker::new("main", Box::new(inner), config(300), metrics.clone());
+        assert_eq!(tracker.find_by_id("42").await?.title, "Card 42");
+        assert_eq!(tracker.find_by_id("42").await?.title, "Card 42");
+
+        let output = metrics.render()?;
+        assert!(output
+            .contains(r#"gitkban_tracker_cache_lookups_total{result="hit",tracker="main"} 1"#));
+        assert!(output
+            .contains(r#"gitkban_tracker_cache_lookups_total{result="miss",tracker="main"} 1"#));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_revalidate() -> testresult::TestResult {
+        let metrics = Arc::new(Metrics::default());
+        let mut inner = MockKanbanizeApi::new();
+        let mut sequence = mockall::Sequence::new();
+        inner
+            .expect_find_by_id()
+            .times(1)
+            .in_sequence(&mut sequence)
+            .returning(|id| Ok(card(id, "1")));
+        // Unchanged, the cached card is used
+        inner
+            .expect_revision()
+            .times(1)
+            .in_sequence(&mut sequence)
+            .returning(|_| Ok(Some("1".to_string())));
+        // Changed, the card is fetched again
+        inner
+            .expect_revision()
+            .times(1)
+            .in_sequence(&mut sequence)
+            .returning(|_| Ok(Some("2".to_string())));
+        inner
+            .expect_find_by_id()
+            .times(1)
+            .in_sequence(&mut sequence)
+            .returning(|id| Ok(card(id, "2")));
+
+        let tracker = CachedTracker::new("main", Box::new(inner), config(0), metrics.clone());
+        tracker.find_by_id("42").await?;
+        assert_eq!(
+            tracker.find_by_id("42").await?.revision.as_deref(),
+            Some("1")
+        );
+        assert_eq!(
+            tracker.find_by_id("42").await?.revision.as_deref(),
+            Some("2")
+        );
+
+        let output = metrics.render()?;
+        assert!(output.contains(
+            r#"gitkban_tracker_cache_lookups_total{result="revalidated",tracker="main"} 1"#
+        ));
+        assert!(output
+            .contains(r#"gitkban_tracker_cache_lookups_total{result="miss",tracker="main"} 2"#));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_find_many() -> testresult::TestResult {
+        let metrics = Arc::new(Metrics::default());
+        let mut inner = MockKanbanizeApi::new();
+        inner
+            .expect_find_by_id()
+            .times(1)
+            .returning(|id| Ok(card(id, "1")));
+        // Only the card missing from the cache is requested
+        inner
+            .expect_find_many()
+            .withf(|ids| ids == ["2".to_string()])
+            .times(1)
+            .returning(|ids| Ok(ids.iter().map(|id| (id.clone(), card(id, "1"))).collect()));
+
+        let tracker = CachedTracker::new("main", Box::new(inner), config(300), metrics.clone());
+        tracker.find_by_id("1").await?;
+        let cards = tracker
+            .find_many(&["1".to_string(), "2".to_string()])
+            .await?;
+        assert_eq!(cards.len(), 2);
+        // Both are cached now
+        tracker.find_by_id("2").await?;
+
+        let output = metrics.render()?;
+        assert!(output
+            .contains(r#"gitkban_tracker_cache_lookups_total{result="hit",tracker="main"} 2"#));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_find_many_revalidate() -> testresult::TestResult {
+        let metrics = Arc::new(Metrics::default());
+        let mut inner = MockKanbanizeApi::new();
+        let mut sequence = mockall::Sequence::new();
+        inner
+            .expect_find_many()
+            .times(1)
+            .in_sequence(&mut sequence)
+            .returning(|ids| Ok(ids.iter().map(|id| (id.clone(), card(id, "1"))).collect()));
+        // The expired cards are checked together
+        inner
+            .expect_revisions()
+            .withf(|ids| ids.len() == 3)
+            .times(1)
+            .in_sequence(&mut sequence)
+            .returning(|_| {
+                Ok(HashMap::from([
+                    ("1".to_string(), "1".to_string()),
+                    ("2".to_string(), "2".to_string()),
+                ]))
+            });
+        // Only the changed and the deleted cards are fetched again
+        inner
+            .expect_find_many()
+            .withf(|ids| ids == ["2".to_string(), "3".to_string()])
+            .times(1)
+            .in_sequence(&mut sequence)
+            .returning(|_| Ok(HashMap::from([("2".to_string(), card("2", "2"))])));
+
+        let tracker = CachedTracker::new("main", Box::new(inner), config(0), metrics.clone());
+        let ids = ["1", "2", "3"].map(String::from);
+        tracker.find_many(&ids).await?;
+        let cards = tracker.find_many(&ids).await?;
+        assert_eq!(cards.len(), 2);
+        assert_eq!(cards["1"].revision.as_deref(), Some("1"));
+        assert_eq!(cards["2"].revision.as_deref(), Some("2"));
+
+        let output = metrics.render()?;
+        assert!(output.contains(
+            r#"gitkban_tracker_cache_lookups_total{result="revalidated",tracker="main"} 1"#
+        ));
+        assert!(output
+            .contains(r#"gitkban_tracker_cache_lookups_total{result="miss",tracker="main"} 5"#));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_invalidate_and_evict() -> testresult::TestResult {
+        let mut inner = MockKanbanizeApi::new();
+        inner
+            .expect_find_by_id()
+            .times(4)
+            .returning(|id| Ok(card(id, "1")));
+        inner.expect_move_card().returning(|_, _| Ok(()));
+
+        let config = CacheConfig {
+            max_entries: 1,
+            ..config(300)
+        };
+        let tracker = CachedTracker::new(
+            "main",
+            Box::new(inner),
+            config,
+            Arc::new(Metrics::default()),
+        );
+        tracker.find_by_id("1").await?;
+        tracker.move_card("1", "7").await?;
+        tracker.find_by_id("1").await?;
+        // Card 2 takes the only entry
+        tracker.find_by_id("2").await?;
+        tracker.find_by_id("1").await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_invalidate_other_id() -> testresult::TestResult {
+        let mut inner = MockKanbanizeApi::new();
+        inner
+            .expect_find_by_id()
+            .times(2)
+            .returning(|_| Ok(card("123", "1")));
+        inner.expect_move_card().returning(|_, _| Ok(()));
+
+        let tracker = CachedTracker::new(
+            "main",
+            Box::new(inner),
+            config(300),
+            Arc::new(Metrics::default()),
+        );
+        tracker.find_by_id("0123").await?;
+        tracker.move_card("123", "7").await?;
+        tracker.find_by_id("0123").await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_persist() -> testresult::TestResult {
+        let dir = std::env::temp_dir().join("gitkban_test_cache");
+        let _ = std::fs::remove_dir_all(&dir);
+        let config = CacheConfig {
+            path: Some(dir.clone()),
+            ..config(300)
+        };
+
+        let mut inner = MockKanbanizeApi::new();
+        inner
+            .expect_find_by_id()
+            .times(2)
+            .returning(|id| Ok(card(id, "1")));
+        let tracker = CachedTracker::new(
+            "main",
+            Box::new(inner),
+            config.clone(),
+            Arc::new(Metrics::default()),
+        );
+        tracker.find_by_id("42").await?;
+        let file = dir.join("main.json");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&file)?.permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        // Saved again once the interval passed, or when the tracker is dropped
+        tracker.find_by_id("43").await?;
+        assert!(!std::fs::read_to_string(&file)?.contains("Card 43"));
+        drop(tracker);
+
+        // A new process starts with the saved cards
+        let tracker = CachedTracker::new(
+            "main",
+            Box::new(MockKanbanizeApi::new()),
+            config,
+            Arc::new(Metrics::default()),
+        );
+        assert_eq!(tracker.find_by_id("42").await?.title, "Card 42");
+        assert_eq!(tracker.find_by_id("43").await?.title, "Card 43");
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
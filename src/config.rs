@@ -0,0 +1,513 @@
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
+
+use serde::Deserialize;
+
+use crate::{
+    attachments::ImageMode,
+    cache::{CacheConfig, CachedTracker},
+    credentials::Credential,
+    fields::FieldConfig,
+    kanbanize::{Kanbanize, KanbanizeApi},
+    labels::LabelMapping,
+    linear::Linear,
+    metrics::{MeteredTracker, Metrics},
+    router::{repository_matches, Route, Router},
+    trello::Trello,
+    users::{PeopleConfig, UserMapping},
+};
+
+type Error = Box<dyn std::error::Error>;
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Configuration file, loaded from the path in `GITKBAN_CONFIG`.
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    pub trackers: HashMap<String, TrackerConfig>,
+    #[serde(default)]
+    pub routes: Vec<Route>,
+    pub default_tracker: Option<String>,
+    /// Per repository options, the first matching entry applies.
+    #[serde(default)]
+    pub repositories: Vec<RepositoryConfig>,
+    #[serde(default)]
+    pub users: UserMapping,
+    /// Caches the cards of every tracker when set.
+    pub cache: Option<CacheConfig>,
+    /// Columns or workflow states holding the cards under review, by tracker
+    /// name, checked by `gitkban report orphans`.
+    #[serde(default)]
+    pub review_columns: HashMap<String, Vec<String>>,
+    /// File every change of a pull request body is appended to, one JSON
+    /// entry per line.
+    pub audit_log: Option<PathBuf>,
+}
+
+/// Opt-in behaviour for the repositories matching `repository`.
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+pub struct RepositoryConfig {
+    /// `owner/repo`, or `owner/*` to match every repository of an owner.
+    pub repository: String,
+    /// Replaces the default GitHub title, see [`crate::logic::format_title`].
+    pub title_format: Option<String>,
+    /// Labels to apply from the card attributes.
+    pub labels: Option<LabelMapping>,
+    /// Assignees and reviewers to set from the card users.
+    pub people: Option<PeopleConfig>,
+    /// Reports a `gitkban/card-link` commit status on every pull request.
+    #[serde(default)]
+    pub enforce_card_link: bool,
+    /// Columns a linked card must not be in for the status to pass, by ID or
+    /// by name.
+    #[serde(default)]
+    pub disallowed_columns: Vec<String>,
+    /// Explains on the pull request why its card couldn't be found.
+    #[serde(default)]
+    pub comment_unresolved: bool,
+    #[serde(default)]
+    pub drafts: DraftMode,
+    /// Column, state or list the card moves to when its draft pull request
+    /// is marked ready for review, by ID or by name.
+    pub ready_column: Option<String>,
+    /// Finishes the card subtasks ticked in the pull request task list.
+    #[serde(default)]
+    pub sync_subtasks: bool,
+    /// Handling of the images and files hosted by the tracker.
+    #[serde(default)]
+    pub images: ImageMode,
+    /// Levels of linked cards listed in the body, parents of parents past
+    /// the first one. `0` lists none.
+    #[serde(default)]
+    pub related_depth: usize,
+    /// Custom fields added to the body, in this order.
+    #[serde(default)]
+    pub custom_fields: Vec<FieldConfig>,
+}
+
+/// How draft pull requests are handled.
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DraftMode {
+    /// Synced like any other pull request.
+    #[default]
+    Sync,
+    /// Left untouched until they are ready for review.
+    Skip,
+    /// Given a body linking the card, the full sync waits until they are
+    /// ready for review.
+    Minimal,
+}
+
+impl RepositoryConfig {
+    /// Whether pull requests that already have a body still need their card
+    /// looked up.
+    pub fn inspects_pulls_with_body(&self) -> bool {
+        self.enforce_card_link
+            || self.comment_unresolved
+            || self.sync_subtasks
+            || self.labels.is_some()
+            || self.people.is_some()
+            || self.ready_column.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum TrackerConfig {
+    Kanbanize {
+        base_path: String,
+        api_key: Credential,
+    },
+    Linear {
+        base_path: Option<String>,
+        api_key: Credential,
+    },
+    Trello {
+        base_path: Option<String>,
+        api_key: Credential,
+        token: Credential,
+    },
+}
+
+impl Config {
+    pub fn load(path: &str) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read config {}: {}", path, e))?;
+        Ok(toml::from_str(&content)?)
+    }
+
+    /// Builds the tracker registry. Without configured trackers, a single
+    /// tracker is built from the environment. Errors of every tracker are
+    /// reported together, one per line.
+    pub fn router(&self, metrics: &Arc<Metrics>) -> Result<Router> {
+        if self.trackers.is_empty() {
+            let tracker = TrackerConfig::from_env()?.build()?;
+            let router = Router::single(self.decorate("default", tracker, metrics));
+            self.validate_review_columns(&router)?;
+            return Ok(router);
+        }
+
+        let mut router = Router::new(self.default_tracker.clone());
+        let mut errors = vec![];
+        for (name, tracker) in self.trackers.iter() {
+            match tracker.build() {
+                Ok(tracker) => router.add_tracker(name, self.decorate(name, tracker, metrics)),
+                Err(e) => errors.extend(
+                    e.to_string()
+                        .lines()
+                        .map(|line| format!("tracker `{}`: {}", name, line)),
+                ),
+            }
+        }
+        if !errors.is_empty() {
+            errors.sort();
+            return Err(errors.join("\n").into());
+        }
+        for route in self.routes.iter() {
+            router.add_route(route.clone());
+        }
+        router.validate()?;
+        self.validate_review_columns(&router)?;
+        Ok(router)
+    }
+}
+
+impl Config {
+    fn validate_review_columns(&self, router: &Router) -> Result<()> {
+        match self
+            .review_columns
+            .keys()
+            .find(|name| router.get(name).is_none())
+        {
+            Some(name) => Err(format!("Unknown tracker `{}` in `review_columns`", name).into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Adds request metrics to a tracker client, and the cache in front of
+    /// them so that only the requests actually sent are measured.
+    fn decorate(
+        &self,
+        name: &str,
+        tracker: Box<dyn KanbanizeApi>,
+        metrics: &Arc<Metrics>,
+    ) -> Box<dyn KanbanizeApi> {
+        let tracker = MeteredTracker::new(name, tracker, metrics.clone());
+        match &self.cache {
+            Some(cache) => CachedTracker::new(name, tracker, cache.clone(), metrics.clone()),
+            None => tracker,
+        }
+    }
+}
+
+/// Finds the options of `repository`, if any entry matches it.
+pub fn repository_config<'a>(
+    repositories: &'a [RepositoryConfig],
+    repository: &str,
+) -> Option<&'a RepositoryConfig> {
+    repositories
+        .iter()
+        .find(|config| repository_matches(&config.repository, repository))
+}
+
+impl TrackerConfig {
+    /// Reads the tracker selected by `TRACKER` (Kanbanize by default).
+    /// Credentials are resolved when the tracker is built, see
+    /// [`credentials::from_env`](crate::credentials::from_env).
+    pub fn from_env() -> Result<Self> {
+        let tracker = std::env::var("TRACKER").unwrap_or_else(|_| "kanbanize".to_string());
+        Ok(match tracker.as_str() {
+            "kanbanize" => {
+                let api_key = Credential::env("KANBANIZE_API_KEY");
+                let Ok(base_path) = std::env::var("KANBANIZE_BASE_PATH") else {
+                    // Report a missing key along with the missing base path
+                    let mut error = "KANBANIZE_BASE_PATH must be set".to_string();
+                    if let Err(e) = api_key.resolve() {
+                        error = format!("{}\n{}", error, e);
+                    }
+                    return Err(error.into());
+                };
+                TrackerConfig::Kanbanize { base_path, api_key }
+            }
+            "linear" => TrackerConfig::Linear {
+                base_path: std::env::var("LINEAR_BASE_PATH").ok(),
+                api_key: Credential::env("LINEAR_API_KEY"),
+            },
+            "trello" => TrackerConfig::Trello {
+                base_path: std::env::var("TRELLO_BASE_PATH").ok(),
+                api_key: Credential::env("TRELLO_API_KEY"),
+                token: Credential::env("TRELLO_TOKEN"),
+            },
+            other => return Err(format!("Unknown TRACKER `{}`", other).into()),
+        })
+    }
+
+    /// Resolves the credentials and builds the client. Every credential
+    /// that can't be resolved is reported, one per line.
+    pub fn build(&self) -> Result<Box<dyn KanbanizeApi>> {
+        let mut errors = vec![];
+        let mut resolve = |field: &str, credential: &Credential| {
+            credential
+                .resolve()
+                .map_err(|e| errors.push(format!("{}: {}", field, e)))
+                .ok()
+        };
+        let tracker = match self {
+            TrackerConfig::Kanbanize { base_path, api_key } => {
+                resolve("api_key", api_key).map(|api_key| Kanbanize::new(base_path, &api_key))
+            }
+            TrackerConfig::Linear { base_path, api_key } => {
+                resolve("api_key", api_key).map(|api_key| {
+                    Linear::new(
+                        base_path.as_deref().unwrap_or("https://api.linear.app"),
+                        &api_key,
+                    )
+                })
+            }
+            TrackerConfig::Trello {
+                base_path,
+                api_key,
+                token,
+            } => {
+                let api_key = resolve("api_key", api_key);
+                let token = resolve("token", token);
+                api_key.zip(token).map(|(api_key, token)| {
+                    Trello::new(
+                        base_path.as_deref().unwrap_or("https://api.trello.com/1"),
+                        &api_key,
+                        &token,
+                    )
+                })
+            }
+        };
+        tracker.ok_or_else(|| errors.join("\n").into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{fields::FieldStyle, users::Role};
+
+    #[test]
+    fn test_parse_config() -> testresult::TestResult {
+        let config: Config = toml::from_str(
+            r#"
+            default_tracker = "main"
+            audit_log = "/var/log/gitkban/audit.jsonl"
+
+            [trackers.main]
+            kind = "kanbanize"
+            base_path = "https://main.kanbanize.com/api/v2"
+            api_key = "main_key"
+
+            [trackers.linear]
+            kind = "linear"
+            api_key = "linear_key"
+
+            [[routes]]
+            repository = "acme/*"
+            branch_prefix = "eng-"
+            tracker = "linear"
+
+            [[repositories]]
+            repository = "acme/web"
+            title_format = "[{custom_id}] {title}"
+            enforce_card_link = true
+            disallowed_columns = ["Backlog"]
+            comment_unresolved = true
+            drafts = "minimal"
+            ready_column = "In Review"
+            sync_subtasks = true
+            images = "upload"
+            related_depth = 2
+            custom_fields = [{ field = "QA notes", render = "section" }, { field = "12" }]
+
+            [repositories.labels]
+            priority = { "1" = "p1" }
+
+            [repositories.people]
+            watchers = "reviewer"
+
+            [users.logins]
+            "17" = "jane-gh"
+
+            [cache]
+            ttl_seconds = 60
+
+            [review_columns]
+            main = ["7", "8"]
+            "#,
+        )?;
+
+        assert_eq!(config.default_tracker, Some("main".to_string()));
+        assert_eq!(
+            config.trackers["linear"],
+            TrackerConfig::Linear {
+                base_path: None,
+                api_key: Credential::Value("linear_key".to_string())
+            }
+        );
+        assert_eq!(
+            config.routes,
+            vec![Route {
+                repository: Some("acme/*".to_string()),
+                branch_prefix: Some("eng-".to_string()),
+                tracker: "linear".to_string(),
+            }]
+        );
+
+        assert_eq!(
+            repository_config(&config.repositories, "Acme/Web"),
+            Some(&RepositoryConfig {
+                repository: "acme/web".to_string(),
+                title_format: Some("[{custom_id}] {title}".to_string()),
+                labels: Some(LabelMapping {
+                    priority: HashMap::from([("1".to_string(), "p1".to_string())]),
+                    ..Default::default()
+                }),
+                people: Some(PeopleConfig {
+                    watchers: Role::Reviewer,
+                    ..Default::default()
+                }),
+                enforce_card_link: true,
+                disallowed_columns: vec!["Backlog".to_string()],
+                comment_unresolved: true,
+                drafts: DraftMode::Minimal,
+                ready_column: Some("In Review".to_string()),
+                sync_subtasks: true,
+                images: ImageMode::Upload,
+                related_depth: 2,
+                custom_fields: vec![
+                    FieldConfig {
+                        field: "QA notes".to_string(),
+                        render: FieldStyle::Section,
+                    },
+                    FieldConfig {
+                        field: "12".to_string(),
+                        render: FieldStyle::Row,
+                    },
+                ],
+            })
+        );
+        assert_eq!(repository_config(&config.repositories, "acme/api"), None);
+        assert_eq!(config.users.logins["17"], "jane-gh");
+        assert_eq!(
+            config.cache,
+            Some(CacheConfig {
+                ttl_seconds: 60,
+                ..Default::default()
+            })
+        );
+
+        assert_eq!(config.review_columns["main"], vec!["7", "8"]);
+        assert_eq!(
+            config.audit_log,
+            Some(PathBuf::from("/var/log/gitkban/audit.jsonl"))
+        );
+
+        let router = config.router(&Arc::new(Metrics::default()))?;
+        assert_eq!(
+            router.resolve("acme/web", "eng-42").map(|(name, _)| name),
+            Some("linear")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_router_unknown_tracker() -> testresult::TestResult {
+        let config: Config = toml::from_str(
+            r#"
+            default_tracker = "jira"
+
+            [trackers.main]
+            kind = "kanbanize"
+            base_path = "https://main.kanbanize.com/api/v2"
+            api_key = "main_key"
+            "#,
+        )?;
+
+        assert_eq!(
+            config
+                .router(&Arc::new(Metrics::default()))
+                .err()
+                .unwrap()
+                .to_string(),
+            "Unknown tracker `jira`"
+        );
+
+        let config: Config = toml::from_str(
+            r#"
+            [trackers.main]
+            kind = "kanbanize"
+            base_path = "https://main.kanbanize.com/api/v2"
+            api_key = "main_key"
+
+            [review_columns]
+            jira = ["In Review"]
+            "#,
+        )?;
+        assert_eq!(
+            config
+                .router(&Arc::new(Metrics::default()))
+                .err()
+                .unwrap()
+                .to_string(),
+            "Unknown tracker `jira` in `review_columns`"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_tracker_credentials() -> testresult::TestResult {
+        let config: Config = toml::from_str(
+            r#"
+            [trackers.trello]
+            kind = "trello"
+            api_key = { env = "GITKBAN_TEST_TRELLO_API_KEY" }
+            token = { file = "/nonexistent/trello_token" }
+            "#,
+        )?;
+
+        assert_eq!(
+            config.trackers["trello"],
+            TrackerConfig::Trello {
+                base_path: None,
+                api_key: Credential::env("GITKBAN_TEST_TRELLO_API_KEY"),
+                token: Credential::File {
+                    file: "/nonexistent/trello_token".into()
+                }
+            }
+        );
+
+        // Both missing credentials are reported at once
+        std::env::remove_var("GITKBAN_TEST_TRELLO_API_KEY");
+        let error = config
+            .router(&Arc::new(Metrics::default()))
+            .err()
+            .unwrap()
+            .to_string();
+        let lines: Vec<&str> = error.lines().collect();
+        assert_eq!(lines.len(), 2, "{}", error);
+        assert!(lines[0]
+            .starts_with("tracker `trello`: api_key: GITKBAN_TEST_TRELLO_API_KEY must be set"));
+        assert!(lines[1].starts_with(
+            "tracker `trello`: token: could not read secret file /nonexistent/trello_token"
+        ));
+
+        std::env::set_var("GITKBAN_TEST_TRELLO_API_KEY", "trello_key");
+        let error = config
+            .router(&Arc::new(Metrics::default()))
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(
+            error.starts_with(
+                "tracker `trello`: token: could not read secret file /nonexistent/trello_token"
+            ),
+            "{}",
+            error
+        );
+        Ok(())
+    }
+}
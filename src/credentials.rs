@@ -0,0 +1,231 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use serde::Deserialize;
+
+use crate::redact;
+
+type Error = Box<dyn std::error::Error>;
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Directory where Docker and Kubernetes mount secrets.
+const SECRETS_DIR: &str = "/run/secrets";
+
+/// Source of a secret in the configuration file. A plain string is used as
+/// the secret itself, a table reads it from somewhere else:
+/// `{ env = "NAME" }`, `{ file = "/path" }` or `{ command = "pass show key" }`.
+#[derive(Clone, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum Credential {
+    Value(String),
+    Env {
+        env: String,
+    },
+    File {
+        file: PathBuf,
+    },
+    Command {
+        command: String,
+    },
+    #[cfg(feature = "keyring")]
+    Keyring {
+        keyring: String,
+        user: String,
+    },
+}
+
+impl fmt::Debug for Credential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Credential::Value(_) => write!(f, "Value([redacted])"),
+            Credential::Env { env } => write!(f, "Env({})", env),
+            Credential::File { file } => write!(f, "File({})", file.display()),
+            // Commands may carry a secret inline, such as a token passed as an argument
+            Credential::Command { .. } => write!(f, "Command([redacted])"),
+            #[cfg(feature = "keyring")]
+            Credential::Keyring { keyring, user } => write!(f, "Keyring({}/{})", keyring, user),
+        }
+    }
+}
+
+impl Credential {
+    pub fn env(name: &str) -> Self {
+        Credential::Env {
+            env: name.to_string(),
+        }
+    }
+
+    /// Reads the secret and registers it for log redaction. Errors name the
+    /// source of the secret, never its value.
+    pub fn resolve(&self) -> Result<String> {
+        let secret = match self {
+            Credential::Value(value) => value.clone(),
+            Credential::Env { env } => from_env(env)?,
+            Credential::File { file } => read_file(file)?,
+            Credential::Command { command } => run_command(command)?,
+            #[cfg(feature = "keyring")]
+            Credential::Keyring { keyring, user } => keyring::Entry::new(keyring, user)
+                .and_then(|entry| entry.get_password())
+                .map_err(|e| {
+                    format!(
+                        "could not read {}/{} from the keyring: {}",
+                        keyring, user, e
+                    )
+                })?,
+        };
+        if secret.is_empty() {
+            return Err(format!("{:?} is empty", self).into());
+        }
+        redact::register_secret(&secret);
+        Ok(secret)
+    }
+}
+
+/// Reads a secret named `name` from, in order: the `name` variable, the file
+/// in `name_FILE`, the output of `name_COMMAND`, a Docker secret in
+/// `/run/secrets/<name>` and, with the `keyring` feature, the
+/// `service/user` entry in `name_KEYRING`.
+pub fn from_env(name: &str) -> Result<String> {
+    if let Ok(value) = std::env::var(name) {
+        return Ok(value);
+    }
+    if let Ok(path) = std::env::var(format!("{}_FILE", name)) {
+        return read_file(&PathBuf::from(path));
+    }
+    if let Ok(command) = std::env::var(format!("{}_COMMAND", name)) {
+        return run_command(&command).map_err(|e| format!("{}_COMMAND: {}", name, e).into());
+    }
+    let docker_secret = PathBuf::from(SECRETS_DIR).join(name.to_lowercase());
+    if docker_secret.is_file() {
+        return read_file(&docker_secret);
+    }
+    #[cfg(feature = "keyring")]
+    if let Ok(entry) = std::env::var(format!("{}_KEYRING", name)) {
+        let (keyring, user) = entry
+            .split_once('/')
+            .ok_or_else(|| format!("{}_KEYRING must look like service/user", name))?;
+        return Credential::Keyring {
+            keyring: keyring.to_string(),
+            user: user.to_string(),
+        }
+        .resolve();
+    }
+
+    Err(format!(
+        "{} must be set (directly, or through {}_FILE, {}_COMMAND or {}/{})",
+        name,
+        name,
+        name,
+        SECRETS_DIR,
+        name.to_lowercase()
+    )
+    .into())
+}
+
+fn read_file(path: &Path) -> Result<String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("could not read secret file {}: {}", path.display(), e))?;
+    Ok(content.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn run_command(command: &str) -> Result<String> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .output()
+        .map_err(|e| format!("could not run the secret command: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("secret command failed with {}", output.status).into());
+    }
+    Ok(String::from_utf8(output.stdout)?
+        .trim_end_matches(['\r', '\n'])
+        .to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_from_env() -> testresult::TestResult {
+        std::env::set_var("GITKBAN_TEST_DIRECT", "direct-secret");
+        assert_eq!(from_env("GITKBAN_TEST_DIRECT")?, "direct-secret");
+
+        let path = std::env::temp_dir().join("gitkban_test_file_secret");
+        std::fs::write(&path, "file-secret\n")?;
+        std::env::set_var("GITKBAN_TEST_FROM_FILE_FILE", &path);
+        assert_eq!(from_env("GITKBAN_TEST_FROM_FILE")?, "file-secret");
+        std::fs::remove_file(&path)?;
+
+        std::env::set_var("GITKBAN_TEST_FROM_COMMAND_COMMAND", "echo command-secret");
+        assert_eq!(from_env("GITKBAN_TEST_FROM_COMMAND")?, "command-secret");
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_env_missing() {
+        let error = from_env("GITKBAN_TEST_MISSING").err().unwrap();
+        assert!(error
+            .to_string()
+            .starts_with("GITKBAN_TEST_MISSING must be set"));
+    }
+
+    #[test]
+    fn test_errors_hide_secret() {
+        let error = Credential::Command {
+            command: "echo command-secret; exit 1".to_string(),
+        }
+        .resolve()
+        .err()
+        .unwrap();
+        assert!(!error.to_string().contains("command-secret"));
+
+        std::env::set_var(
+            "GITKBAN_TEST_FAILING_COMMAND",
+            "echo command-secret; exit 1",
+        );
+        let error = from_env("GITKBAN_TEST_FAILING").err().unwrap();
+        assert!(!error.to_string().contains("command-secret"));
+
+        let credential = Credential::Value("literal-secret".to_string());
+        assert!(!format!("{:?}", credential).contains("literal-secret"));
+    }
+
+    #[test]
+    fn test_parse_credential() -> testresult::TestResult {
+        #[derive(Deserialize)]
+        struct Secrets {
+            value: Credential,
+            env: Credential,
+            file: Credential,
+            command: Credential,
+        }
+
+        let secrets: Secrets = toml::from_str(
+            r#"
+            value = "literal"
+            env = { env = "API_KEY" }
+            file = { file = "/run/secrets/api_key" }
+            command = { command = "pass show api_key" }
+            "#,
+        )?;
+        assert_eq!(secrets.value, Credential::Value("literal".to_string()));
+        assert_eq!(secrets.env, Credential::env("API_KEY"));
+        assert_eq!(
+            secrets.file,
+            Credential::File {
+                file: PathBuf::from("/run/secrets/api_key")
+            }
+        );
+        assert_eq!(
+            secrets.command,
+            Credential::Command {
+                command: "pass show api_key".to_string()
+            }
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,30 @@
+use std::fmt;
+
+/// Error returned by an API with a non-success HTTP status.
+#[derive(Debug)]
+pub struct StatusError {
+    pub status: u16,
+    pub message: String,
+}
+
+impl fmt::Display for StatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for StatusError {}
+
+/// Returns the HTTP status carried by an error, if known.
+pub fn status_of(error: &(dyn std::error::Error + 'static)) -> Option<u16> {
+    if let Some(e) = error.downcast_ref::<StatusError>() {
+        return Some(e.status);
+    }
+    if let Some(octocrab::Error::GitHub { source, .. }) = error.downcast_ref::<octocrab::Error>() {
+        return Some(source.status_code.as_u16());
+    }
+    error
+        .downcast_ref::<reqwest::Error>()
+        .and_then(|e| e.status())
+        .map(|status| status.as_u16())
+}
@@ -0,0 +1,149 @@
+use serde::Deserialize;
+
+use crate::{
+    kanbanize::{Card, CustomField},
+    markdown,
+};
+
+/// Custom field added to the pull request body.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct FieldConfig {
+    /// Field ID or name, names are matched ignoring case.
+    pub field: String,
+    #[serde(default)]
+    pub render: FieldStyle,
+}
+
+/// How a custom field is rendered.
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldStyle {
+    /// Row of a table gathering the short fields.
+    #[default]
+    Row,
+    /// Section with the field name as heading.
+    Section,
+    /// Task list with one item per line of the value.
+    Checklist,
+}
+
+impl FieldConfig {
+    fn matches(&self, field: &CustomField) -> bool {
+        field.id == self.field
+            || field
+                .name
+                .as_ref()
+                .is_some_and(|name| name.eq_ignore_ascii_case(&self.field))
+    }
+}
+
+/// Renders the custom fields of `card` listed in `fields`, in the configured
+/// order. Returns `None` if the card has none of them.
+pub fn render(card: &Card, fields: &[FieldConfig]) -> Option<String> {
+    let mut rows = Vec::new();
+    let mut sections = Vec::new();
+    for config in fields {
+        let Some(field) = card
+            .custom_fields
+            .iter()
+            .find(|field| config.matches(field))
+        else {
+            continue;
+        };
+        let name = markdown::neutralize_mentions(field.name.as_deref().unwrap_or(&config.field));
+        // Values are plain text, even on trackers with HTML descriptions
+        let value = markdown::neutralize_mentions(&field.value);
+        match config.render {
+            FieldStyle::Row => rows.push(format!("| {} | {} |", cell(&name), cell(&value))),
+            FieldStyle::Section => sections.push(format!("### {}\n\n{}", name, value.trim())),
+            FieldStyle::Checklist => sections.push(format!("### {}\n{}", name, checklist(&value))),
+        }
+    }
+
+    if !rows.is_empty() {
+        sections.insert(
+            0,
+            format!("| Field | Value |\n| --- | --- |\n{}", rows.join("\n")),
+        );
+    }
+    (!sections.is_empty()).then(|| sections.join("\n\n"))
+}
+
+/// Escapes a value to fit in a table cell.
+fn cell(value: &str) -> String {
+    value.trim().replace('|', "\\|").replace('\n', "<br>")
+}
+
+/// Task list with one item per non-empty line, list markers removed. Items
+/// ticked in the value stay ticked.
+fn checklist(value: &str) -> String {
+    let marker = regex::Regex::new(r"^\s*(?:[-*+]\s+(?:\[([ xX])\]\s+)?|\d+[.)]\s+)").unwrap();
+    value
+        .lines()
+        .filter_map(|line| {
+            let ticked = marker
+                .captures(line)
+                .and_then(|captures| captures.get(1))
+                .is_some_and(|state| state.as_str() != " ");
+            let text = marker.replace(line, "").trim().to_string();
+            (!text.is_empty()).then(|| format!("- [{}] {}", if ticked { "x" } else { " " }, text))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn field(id: &str, name: &str, value: &str) -> CustomField {
+        CustomField {
+            id: id.to_string(),
+            name: Some(name.to_string()),
+            value: value.to_string(),
+        }
+    }
+
+    fn config(field: &str, render: FieldStyle) -> FieldConfig {
+        FieldConfig {
+            field: field.to_string(),
+            render,
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let card = Card {
+            custom_fields: vec![
+                field(
+                    "7",
+                    "Acceptance criteria",
+                    "- Works offline\n- Syncs on reconnect",
+                ),
+                field("8", "QA notes", "Ask @jane for test accounts"),
+                field("9", "Rollout plan", "Flag | 10%\nthen 100%"),
+                field("10", "Estimate", "3"),
+            ],
+            ..Default::default()
+        };
+        let fields = [
+            config("acceptance criteria", FieldStyle::Checklist),
+            config("9", FieldStyle::Row),
+            config("QA notes", FieldStyle::Section),
+            config("Risk", FieldStyle::Row),
+        ];
+        assert_eq!(
+            render(&card, &fields).as_deref(),
+            Some("| Field | Value |\n| --- | --- |\n| Rollout plan | Flag \\| 10%<br>then 100% |\n\n### Acceptance criteria\n- [ ] Works offline\n- [ ] Syncs on reconnect\n\n### QA notes\n\nAsk @&#8203;jane for test accounts")
+        );
+        assert_eq!(render(&card, &[config("Risk", FieldStyle::Row)]), None);
+    }
+
+    #[test]
+    fn test_checklist() {
+        assert_eq!(
+            checklist("1. First\n\n* [x] Second\n- [ ] Third\nFourth"),
+            "- [ ] First\n- [x] Second\n- [ ] Third\n- [ ] Fourth"
+        );
+    }
+}
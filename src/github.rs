@@ -1,15 +1,28 @@
-use serde_json;
 use url::Url;
 
+use crate::{error::StatusError, redact::Sensitive};
+
 type Error = Box<dyn std::error::Error>;
 type Result<T, E = Error> = std::result::Result<T, E>;
 
 #[derive(Debug, Clone)]
 pub struct PullRequest {
     pub url: Url,
+    pub number: u64,
+    pub title: String,
+    /// Description of the pull request, empty when it has none.
+    pub body: String,
     pub is_private: bool,
+    /// Full name of the base repository, as `owner/repo`.
+    pub repository: String,
     pub repository_owner: String,
     pub head_reference: String,
+    /// Commit SHA of the head branch.
+    pub head_sha: String,
+    pub labels: Vec<String>,
+    /// Login of the user who opened the pull request.
+    pub author: String,
+    pub draft: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -17,14 +30,119 @@ pub struct Issue {
     pub url: Url,
     pub body: String,
     pub pull_request_url: Option<Url>,
+    /// Whether the pull request is a draft, always false for issues.
+    pub draft: bool,
+}
+
+/// Search result, the issue model doesn't include the draft status of pull
+/// requests.
+#[derive(serde::Deserialize)]
+struct SearchItem {
+    #[serde(flatten)]
+    issue: octocrab::models::issues::Issue,
+    #[serde(default)]
+    draft: bool,
+}
+
+/// Comment on the conversation of a pull request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    pub id: u64,
+    pub body: String,
+}
+
+/// Fields to change on a pull request, unset fields are left untouched.
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize)]
+pub struct PullRequestUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+}
+
+/// Commit status reported on the head of a pull request.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct CommitStatus {
+    pub state: StatusState,
+    /// Name of the status, such as `gitkban/card-link`.
+    pub context: String,
+    pub description: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StatusState {
+    Success,
+    Failure,
 }
 
 #[cfg_attr(test, mockall::automock)]
 #[async_trait::async_trait]
-pub trait GithubApi {
+pub trait GithubApi: Send + Sync {
     async fn get_issues(&self) -> Result<Vec<Issue>>;
     async fn get_pull_from_url(&self, url: &Url) -> Result<PullRequest>;
-    async fn update_pull_request(&self, url: &Url, body: String) -> Result<PullRequest>;
+    async fn update_pull_request(
+        &self,
+        url: &Url,
+        update: PullRequestUpdate,
+    ) -> Result<PullRequest>;
+    /// Names of the labels defined in `repository` (`owner/repo`).
+    async fn list_labels(&self, repository: &str) -> Result<Vec<String>>;
+    async fn create_label(&self, repository: &str, name: &str) -> Result<()>;
+    async fn add_labels(&self, pull: &PullRequest, labels: &[String]) -> Result<()>;
+    async fn remove_label(&self, pull: &PullRequest, label: &str) -> Result<()>;
+    async fn add_assignees(&self, pull: &PullRequest, assignees: &[String]) -> Result<()>;
+    async fn request_reviewers(&self, pull: &PullRequest, reviewers: &[String]) -> Result<()>;
+    /// Latest status named `context` on the head of `pull`, if any.
+    async fn get_commit_status(
+        &self,
+        pull: &PullRequest,
+        context: &str,
+    ) -> Result<Option<CommitStatus>>;
+    async fn set_commit_status(&self, pull: &PullRequest, status: CommitStatus) -> Result<()>;
+    /// Comments of `pull`, oldest first.
+    async fn list_comments(&self, pull: &PullRequest) -> Result<Vec<Comment>>;
+    async fn create_comment(&self, pull: &PullRequest, body: &str) -> Result<()>;
+    async fn update_comment(&self, pull: &PullRequest, id: u64, body: &str) -> Result<()>;
+    async fn delete_comment(&self, pull: &PullRequest, id: u64) -> Result<()>;
+    /// URL of a file already committed to the assets branch of `repository`.
+    async fn find_asset(&self, repository: &str, path: &str) -> Result<Option<Url>>;
+    /// Commits a file to the assets branch of `repository`, creating the
+    /// branch if needed, and returns a URL that renders it for people with
+    /// access to the repository.
+    async fn upload_asset(&self, repository: &str, path: &str, content: &[u8]) -> Result<Url>;
+    /// Finds the user with a public `email`.
+    async fn find_login_by_email(&self, email: &str) -> Result<Option<String>>;
+    /// Verifies the API is reachable and the token is accepted with the
+    /// scopes needed to edit pull requests.
+    async fn check(&self) -> Result<()>;
+}
+
+/// Scope needed to read and edit pull requests in private repositories.
+const REQUIRED_SCOPE: &str = "repo";
+
+/// Color of the labels created by gitkban.
+const LABEL_COLOR: &str = "ededed";
+
+/// Branch holding the files uploaded by gitkban, created from the default branch.
+const ASSETS_BRANCH: &str = "gitkban-assets";
+
+/// `/`-separated `path` with each segment percent-encoded, starting with `/`.
+fn encoded_path(path: &str) -> String {
+    let mut url = Url::parse("http://localhost").unwrap();
+    url.path_segments_mut().unwrap().extend(path.split('/'));
+    url.path().to_string()
+}
+
+/// Address rendering a file of the assets branch for people with access to
+/// `repository`.
+fn asset_url(repository: &str, path: &str) -> Result<Url> {
+    Ok(Url::parse(&format!(
+        "https://github.com/{}/blob/{}{}?raw=true",
+        repository,
+        ASSETS_BRANCH,
+        encoded_path(path)
+    ))?)
 }
 
 pub struct Github {
@@ -33,6 +151,7 @@ pub struct Github {
 }
 
 impl Github {
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(token: String, track_user: String) -> Box<dyn GithubApi> {
         Box::new(Github {
             instance: octocrab::OctocrabBuilder::new()
@@ -42,19 +161,73 @@ impl Github {
             track_user,
         })
     }
+
+    fn issues(&self, repository: &str) -> Result<octocrab::issues::IssueHandler<'_>> {
+        let (owner, repo) = repository
+            .split_once('/')
+            .ok_or_else(|| format!("invalid repository `{}`", repository))?;
+        Ok(self.instance.issues(owner, repo))
+    }
+
+    /// Whether `route` exists, only a 404 response means it doesn't.
+    async fn exists(&self, route: &str) -> Result<bool> {
+        let response = self.instance._get(route).await?;
+        match response.status().as_u16() {
+            200..=299 => Ok(true),
+            404 => Ok(false),
+            status => Err(StatusError {
+                status,
+                message: format!("GET {} failed with status {}", route, status),
+            }
+            .into()),
+        }
+    }
+
+    async fn create_assets_branch(&self, repository: &str) -> Result<()> {
+        let route = format!("/repos/{}/git/ref/heads/{}", repository, ASSETS_BRANCH);
+        if self.exists(&route).await? {
+            return Ok(());
+        }
+
+        let repo: serde_json::Value = self
+            .instance
+            .get(format!("/repos/{}", repository), None::<&()>)
+            .await?;
+        let default_branch = repo["default_branch"]
+            .as_str()
+            .ok_or("repository has no default branch")?;
+        let head: serde_json::Value = self
+            .instance
+            .get(
+                format!("/repos/{}/git/ref/heads/{}", repository, default_branch),
+                None::<&()>,
+            )
+            .await?;
+        let data = serde_json::json!({
+            "ref": format!("refs/heads/{}", ASSETS_BRANCH),
+            "sha": head["object"]["sha"].as_str().ok_or("branch has no commit")?,
+        });
+        tracing::info!("Creating branch {} in {}", ASSETS_BRANCH, repository);
+        self.instance
+            .post::<_, serde_json::Value>(format!("/repos/{}/git/refs", repository), Some(&data))
+            .await?;
+        Ok(())
+    }
 }
 
 #[async_trait::async_trait]
 impl GithubApi for Github {
     async fn get_issues(&self) -> Result<Vec<Issue>> {
+        let query = format!("is:open is:pr author:{}", self.track_user);
+        let parameters = [
+            ("q", query.as_str()),
+            ("sort", "created_at"),
+            ("order", "desc"),
+        ];
         self.instance
-            .search()
-            .issues_and_pull_requests(&format!("is:open is:pr author:{}", self.track_user))
-            .sort("created_at")
-            .order("desc")
-            .send()
+            .get::<octocrab::Page<SearchItem>, _, _>("/search/issues", Some(&parameters))
             .await
-            .map_err(|e| e.to_string())
+            .map_err(Into::<Error>::into)
             .map(|issues| {
                 issues
                     .into_iter()
@@ -67,34 +240,242 @@ impl GithubApi for Github {
         self.instance
             .get::<octocrab::models::pulls::PullRequest, &Url, ()>(url, None)
             .await
-            .map_err(|e| Into::<Error>::into(e))
+            .map_err(Into::<Error>::into)
             .map(|v| v.try_into())?
     }
 
-    async fn update_pull_request(&self, url: &Url, body: String) -> Result<PullRequest> {
-        let data = serde_json::json!({
-            "body": body
-        });
+    async fn update_pull_request(
+        &self,
+        url: &Url,
+        update: PullRequestUpdate,
+    ) -> Result<PullRequest> {
+        tracing::debug!("Updating pull request with: {:?}", Sensitive(&update));
+        let response = self.instance._patch(url.as_str(), Some(&update)).await?;
+        let status = response.status();
+        let body = self.instance.body_to_string(response).await?;
+        if !status.is_success() {
+            return Err(StatusError {
+                status: status.as_u16(),
+                message: github_error_message(&body),
+            }
+            .into());
+        }
+        serde_json::from_str::<octocrab::models::pulls::PullRequest>(&body)?.try_into()
+    }
+
+    async fn list_labels(&self, repository: &str) -> Result<Vec<String>> {
+        let issues = self.issues(repository)?;
+        let page = issues.list_labels_for_repo().per_page(100).send().await?;
+        let labels = self.instance.all_pages(page).await?;
+        Ok(labels.into_iter().map(|label| label.name).collect())
+    }
+
+    async fn create_label(&self, repository: &str, name: &str) -> Result<()> {
+        let issues = self.issues(repository)?;
+        issues
+            .create_label(name, LABEL_COLOR, "Managed by gitkban")
+            .await?;
+        Ok(())
+    }
+
+    async fn add_labels(&self, pull: &PullRequest, labels: &[String]) -> Result<()> {
+        let issues = self.issues(&pull.repository)?;
+        issues.add_labels(pull.number, labels).await?;
+        Ok(())
+    }
+
+    async fn remove_label(&self, pull: &PullRequest, label: &str) -> Result<()> {
+        let issues = self.issues(&pull.repository)?;
+        issues.remove_label(pull.number, label).await?;
+        Ok(())
+    }
+
+    async fn add_assignees(&self, pull: &PullRequest, assignees: &[String]) -> Result<()> {
+        let issues = self.issues(&pull.repository)?;
+        let assignees: Vec<&str> = assignees.iter().map(String::as_str).collect();
+        issues.add_assignees(pull.number, &assignees).await?;
+        Ok(())
+    }
 
-        tracing::debug!("Updating body to: {}", data.to_string());
+    async fn request_reviewers(&self, pull: &PullRequest, reviewers: &[String]) -> Result<()> {
+        let data = serde_json::json!({ "reviewers": reviewers });
         self.instance
-            .patch::<octocrab::models::pulls::PullRequest, &Url, serde_json::Value>(
-                url,
+            .post::<_, serde_json::Value>(
+                format!(
+                    "/repos/{}/pulls/{}/requested_reviewers",
+                    pull.repository, pull.number
+                ),
                 Some(&data),
             )
-            .await
-            .map_err(|e| Into::<Error>::into(e))
-            .map(|v| v.try_into())?
+            .await?;
+        Ok(())
+    }
+
+    async fn get_commit_status(
+        &self,
+        pull: &PullRequest,
+        context: &str,
+    ) -> Result<Option<CommitStatus>> {
+        // The combined status holds the latest status of every context
+        let combined: serde_json::Value = self
+            .instance
+            .get(
+                format!(
+                    "/repos/{}/commits/{}/status",
+                    pull.repository, pull.head_sha
+                ),
+                Some(&[("per_page", "100")]),
+            )
+            .await?;
+        Ok(combined["statuses"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find(|status| status["context"] == context)
+            // States set by other tools, such as `pending`, read as no status
+            .and_then(|status| serde_json::from_value(status.clone()).ok()))
+    }
+
+    async fn set_commit_status(&self, pull: &PullRequest, status: CommitStatus) -> Result<()> {
+        self.instance
+            .post::<_, serde_json::Value>(
+                format!("/repos/{}/statuses/{}", pull.repository, pull.head_sha),
+                Some(&status),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn list_comments(&self, pull: &PullRequest) -> Result<Vec<Comment>> {
+        let issues = self.issues(&pull.repository)?;
+        let page = issues
+            .list_comments(pull.number)
+            .per_page(100)
+            .send()
+            .await?;
+        let comments = self.instance.all_pages(page).await?;
+        Ok(comments
+            .into_iter()
+            .map(|comment| Comment {
+                id: comment.id.0,
+                body: comment.body.unwrap_or_default(),
+            })
+            .collect())
+    }
+
+    async fn create_comment(&self, pull: &PullRequest, body: &str) -> Result<()> {
+        let issues = self.issues(&pull.repository)?;
+        issues.create_comment(pull.number, body).await?;
+        Ok(())
+    }
+
+    async fn update_comment(&self, pull: &PullRequest, id: u64, body: &str) -> Result<()> {
+        let issues = self.issues(&pull.repository)?;
+        issues
+            .update_comment(octocrab::models::CommentId(id), body)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_comment(&self, pull: &PullRequest, id: u64) -> Result<()> {
+        let issues = self.issues(&pull.repository)?;
+        issues
+            .delete_comment(octocrab::models::CommentId(id))
+            .await?;
+        Ok(())
+    }
+
+    async fn find_asset(&self, repository: &str, path: &str) -> Result<Option<Url>> {
+        let route = format!(
+            "/repos/{}/contents{}?ref={}",
+            repository,
+            encoded_path(path),
+            ASSETS_BRANCH
+        );
+        match self.exists(&route).await? {
+            true => Ok(Some(asset_url(repository, path)?)),
+            false => Ok(None),
+        }
+    }
+
+    async fn upload_asset(&self, repository: &str, path: &str, content: &[u8]) -> Result<Url> {
+        use base64::Engine;
+
+        let route = format!("/repos/{}/contents{}", repository, encoded_path(path));
+        self.create_assets_branch(repository).await?;
+        let data = serde_json::json!({
+            "message": format!("Add {}", path),
+            "content": base64::engine::general_purpose::STANDARD.encode(content),
+            "branch": ASSETS_BRANCH,
+        });
+        self.instance
+            .put::<serde_json::Value, _, _>(route, Some(&data))
+            .await?;
+        asset_url(repository, path)
+    }
+
+    async fn find_login_by_email(&self, email: &str) -> Result<Option<String>> {
+        let users = self
+            .instance
+            .search()
+            .users(&format!("{} in:email", email))
+            .send()
+            .await?;
+        Ok(users.into_iter().next().map(|user| user.login))
+    }
+
+    async fn check(&self) -> Result<()> {
+        let response = self.instance._get("/user").await?;
+        let response = octocrab::map_github_error(response).await?;
+
+        // Only classic tokens report their scopes, fine-grained tokens are
+        // checked when the pull requests are updated.
+        if let Some(scopes) = response.headers().get("x-oauth-scopes") {
+            let scopes = scopes.to_str()?;
+            if !scopes
+                .split(',')
+                .any(|scope| scope.trim() == REQUIRED_SCOPE)
+            {
+                return Err(format!(
+                    "token is missing the `{}` scope (granted: {})",
+                    REQUIRED_SCOPE,
+                    if scopes.is_empty() { "none" } else { scopes }
+                )
+                .into());
+            }
+        }
+        Ok(())
     }
 }
 
-impl TryFrom<octocrab::models::issues::Issue> for Issue {
+/// Message of a GitHub error response, followed by the validation errors
+/// it details, if any.
+fn github_error_message(body: &str) -> String {
+    let Ok(error) = serde_json::from_str::<serde_json::Value>(body) else {
+        return body.to_string();
+    };
+    let mut message = error["message"].as_str().unwrap_or(body).to_string();
+    let details: Vec<&str> = error["errors"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|error| error["message"].as_str())
+        .collect();
+    if !details.is_empty() {
+        message = format!("{}: {}", message, details.join(", "));
+    }
+    message
+}
+
+impl TryFrom<SearchItem> for Issue {
     type Error = Error;
-    fn try_from(value: octocrab::models::issues::Issue) -> Result<Self, Self::Error> {
+    fn try_from(value: SearchItem) -> Result<Self, Self::Error> {
+        let SearchItem { issue, draft } = value;
         Ok(Self {
-            url: value.url,
-            body: value.body.unwrap_or_default(),
-            pull_request_url: value.pull_request.map(|pull| pull.url.clone()),
+            url: issue.url,
+            body: issue.body.unwrap_or_default(),
+            pull_request_url: issue.pull_request.map(|pull| pull.url.clone()),
+            draft,
         })
     }
 }
@@ -102,24 +483,30 @@ impl TryFrom<octocrab::models::issues::Issue> for Issue {
 impl TryFrom<octocrab::models::pulls::PullRequest> for PullRequest {
     type Error = Error;
     fn try_from(value: octocrab::models::pulls::PullRequest) -> Result<Self, Self::Error> {
-        return Ok(Self {
+        let repository = value
+            .base
+            .repo
+            .clone()
+            .ok_or("repository of pull request not found")?;
+        Ok(Self {
             url: value.url.parse()?,
-            is_private: value
-                .base
-                .clone()
-                .repo
-                .ok_or("repository of pull request not found")?
-                .private
-                .unwrap_or(true),
-            repository_owner: value
-                .base
-                .repo
-                .ok_or("repository of pull request not found")?
-                .owner
-                .ok_or("owner not found")?
-                .login,
+            number: value.number,
+            title: value.title.unwrap_or_default(),
+            body: value.body.unwrap_or_default(),
+            is_private: repository.private.unwrap_or(true),
+            repository: repository.full_name.ok_or("repository name not found")?,
+            repository_owner: repository.owner.ok_or("owner not found")?.login,
             head_reference: value.head.ref_field,
-        });
+            head_sha: value.head.sha,
+            labels: value
+                .labels
+                .unwrap_or_default()
+                .into_iter()
+                .map(|label| label.name)
+                .collect(),
+            author: value.user.map(|user| user.login).unwrap_or_default(),
+            draft: value.draft.unwrap_or_default(),
+        })
     }
 }
 
@@ -195,7 +582,7 @@ mod test {
                         "closed_at": null,
                         "author_association": "NONE",
                         "active_lock_reason": null,
-                        "draft": false,
+                        "draft": true,
                         "pull_request": {
                             "url": "https://api.github.com/repos/oh-my-fish/plugin-pyenv/pulls/11",
                             "html_url": "https://github.com/oh-my-fish/plugin-pyenv/pull/11",
@@ -244,8 +631,258 @@ mod test {
             issues[0].pull_request_url.as_ref().unwrap(),
             &Url::parse("https://api.github.com/repos/oh-my-fish/plugin-pyenv/pulls/11").unwrap()
         );
+        assert!(issues[0].draft);
 
         mock.assert_async().await;
         Ok(())
     }
+
+    fn github(server: &mockito::Server) -> Github {
+        Github {
+            instance: octocrab::OctocrabBuilder::new()
+                .base_uri(server.url())
+                .unwrap()
+                .build()
+                .unwrap(),
+            track_user: "trackuser".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check_scopes() -> testresult::TestResult {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/user")
+            .with_status(200)
+            .with_header("x-oauth-scopes", "read:org, repo")
+            .with_body(r#"{"login": "trackuser"}"#)
+            .create_async()
+            .await;
+
+        github(&server).check().await?;
+        mock.assert_async().await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_check_missing_scope() -> testresult::TestResult {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/user")
+            .with_status(200)
+            .with_header("x-oauth-scopes", "read:org")
+            .with_body(r#"{"login": "trackuser"}"#)
+            .create_async()
+            .await;
+
+        assert_eq!(
+            github(&server).check().await.err().unwrap().to_string(),
+            "token is missing the `repo` scope (granted: read:org)"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_upload_asset() -> testresult::TestResult {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/repos/acme/web/git/ref/heads/gitkban-assets")
+            .with_status(404)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/repos/acme/web")
+            .with_status(200)
+            .with_body(r#"{"default_branch": "main"}"#)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/repos/acme/web/git/ref/heads/main")
+            .with_status(200)
+            .with_body(r#"{"object": {"sha": "abc123"}}"#)
+            .create_async()
+            .await;
+        let branch = server
+            .mock("POST", "/repos/acme/web/git/refs")
+            .match_body(mockito::Matcher::Json(serde_json::json!({
+                "ref": "refs/heads/gitkban-assets",
+                "sha": "abc123",
+            })))
+            .with_status(201)
+            .with_body("{}")
+            .create_async()
+            .await;
+        let upload = server
+            .mock("PUT", "/repos/acme/web/contents/42/my%20shot.png")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "content": "AQID",
+                "branch": "gitkban-assets",
+            })))
+            .with_status(201)
+            .with_body("{}")
+            .create_async()
+            .await;
+
+        let url = github(&server)
+            .upload_asset("acme/web", "42/my shot.png", &[1, 2, 3])
+            .await?;
+        assert_eq!(
+            url.as_str(),
+            "https://github.com/acme/web/blob/gitkban-assets/42/my%20shot.png?raw=true"
+        );
+        branch.assert_async().await;
+        upload.assert_async().await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_find_asset() -> testresult::TestResult {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock(
+                "GET",
+                "/repos/acme/web/contents/42/shot.png?ref=gitkban-assets",
+            )
+            .with_status(200)
+            .with_body("{}")
+            .create_async()
+            .await;
+        server
+            .mock(
+                "GET",
+                "/repos/acme/web/contents/42/other.png?ref=gitkban-assets",
+            )
+            .with_status(404)
+            .create_async()
+            .await;
+        server
+            .mock(
+                "GET",
+                "/repos/acme/web/contents/42/broken.png?ref=gitkban-assets",
+            )
+            .with_status(500)
+            .create_async()
+            .await;
+
+        let github = github(&server);
+        assert_eq!(
+            github.find_asset("acme/web", "42/shot.png").await?,
+            Some(Url::parse(
+                "https://github.com/acme/web/blob/gitkban-assets/42/shot.png?raw=true"
+            )?)
+        );
+        assert_eq!(github.find_asset("acme/web", "42/other.png").await?, None);
+        assert!(github
+            .find_asset("acme/web", "42/broken.png")
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_create_assets_branch_error() -> testresult::TestResult {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/repos/acme/web/git/ref/heads/gitkban-assets")
+            .with_status(403)
+            .create_async()
+            .await;
+        let create = server
+            .mock("POST", "/repos/acme/web/git/refs")
+            .expect(0)
+            .create_async()
+            .await;
+
+        let error = github(&server)
+            .upload_asset("acme/web", "42/shot.png", &[1, 2, 3])
+            .await
+            .unwrap_err();
+        assert_eq!(crate::error::status_of(error.as_ref()), Some(403));
+        create.assert_async().await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_commit_status() -> testresult::TestResult {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/repos/acme/web/commits/abc123/status?per_page=100")
+            .with_status(200)
+            .with_body(
+                r#"{"state": "failure", "statuses": [
+                    {"state": "pending", "context": "ci/build", "description": null},
+                    {"state": "failure", "context": "gitkban/card-link", "description": "Card 42 doesn't exist"}
+                ]}"#,
+            )
+            .create_async()
+            .await;
+
+        let pull = PullRequest {
+            url: Url::parse("https://example.com/pull")?,
+            number: 1,
+            title: String::new(),
+            body: String::new(),
+            is_private: true,
+            repository: String::from("acme/web"),
+            repository_owner: String::from("acme"),
+            head_reference: String::from("request-42"),
+            head_sha: String::from("abc123"),
+            labels: vec![],
+            author: String::new(),
+            draft: false,
+        };
+        let github = github(&server);
+        assert_eq!(
+            github.get_commit_status(&pull, "gitkban/card-link").await?,
+            Some(CommitStatus {
+                state: StatusState::Failure,
+                context: String::from("gitkban/card-link"),
+                description: String::from("Card 42 doesn't exist"),
+            })
+        );
+        assert_eq!(github.get_commit_status(&pull, "ci/build").await?, None);
+        assert_eq!(github.get_commit_status(&pull, "other").await?, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_octocrab_error_status() -> testresult::TestResult {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/repos/acme/web/labels")
+            .match_query(mockito::Matcher::Any)
+            .with_status(404)
+            .with_body(r#"{"message": "Not Found"}"#)
+            .create_async()
+            .await;
+
+        let error = github(&server).list_labels("acme/web").await.unwrap_err();
+        assert_eq!(crate::error::status_of(error.as_ref()), Some(404));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_update_pull_request_rejected() -> testresult::TestResult {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("PATCH", "/repos/acme/web/pulls/1")
+            .with_status(422)
+            .with_body(
+                r#"{"message": "Validation Failed", "errors": [{"resource": "PullRequest", "code": "custom", "message": "body is too long (maximum is 65536 characters)"}]}"#,
+            )
+            .create_async()
+            .await;
+
+        let url = Url::parse(&format!("{}/repos/acme/web/pulls/1", server.url()))?;
+        let error = github(&server)
+            .update_pull_request(&url, PullRequestUpdate::default())
+            .await
+            .unwrap_err();
+        assert_eq!(crate::error::status_of(error.as_ref()), Some(422));
+        assert_eq!(
+            error.to_string(),
+            "Validation Failed: body is too long (maximum is 65536 characters)"
+        );
+        Ok(())
+    }
 }
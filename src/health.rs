@@ -0,0 +1,82 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::metrics::Metrics;
+
+/// Liveness and readiness state reported by the HTTP server.
+pub struct Health {
+    metrics: Arc<Metrics>,
+    started: Instant,
+    max_cycle_age: Duration,
+    readiness: Mutex<Option<Result<(), String>>>,
+}
+
+impl Health {
+    /// `max_cycle_age` is how long the service may go without finishing a cycle
+    /// before it is considered stuck.
+    pub fn new(metrics: Arc<Metrics>, max_cycle_age: Duration) -> Self {
+        Self {
+            metrics,
+            started: Instant::now(),
+            max_cycle_age,
+            readiness: Mutex::new(None),
+        }
+    }
+
+    /// Stores the result of the startup checks.
+    pub fn set_ready(&self, result: Result<(), String>) {
+        *self.readiness.lock().unwrap() = Some(result);
+    }
+
+    pub fn ready(&self) -> Result<(), String> {
+        self.readiness
+            .lock()
+            .unwrap()
+            .clone()
+            .unwrap_or_else(|| Err("startup checks pending".to_string()))
+    }
+
+    pub fn live(&self) -> Result<(), String> {
+        let elapsed = self
+            .metrics
+            .since_last_cycle()
+            .unwrap_or_else(|| self.started.elapsed());
+        if elapsed > self.max_cycle_age {
+            return Err(format!("no cycle finished in {}s", elapsed.as_secs()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ready() {
+        let health = Health::new(Arc::new(Metrics::default()), Duration::from_secs(60));
+        assert_eq!(health.ready(), Err("startup checks pending".to_string()));
+
+        health.set_ready(Err("github: bad credentials".to_string()));
+        assert_eq!(health.ready(), Err("github: bad credentials".to_string()));
+
+        health.set_ready(Ok(()));
+        assert_eq!(health.ready(), Ok(()));
+    }
+
+    #[test]
+    fn test_live() {
+        let metrics = Arc::new(Metrics::default());
+        let health = Health::new(metrics.clone(), Duration::ZERO);
+        std::thread::sleep(Duration::from_millis(1));
+        assert!(health.live().is_err());
+
+        let health = Health::new(metrics.clone(), Duration::from_secs(60));
+        assert_eq!(health.live(), Ok(()));
+
+        metrics.cycle_finished(Duration::from_millis(10), false);
+        assert_eq!(health.live(), Ok(()));
+    }
+}
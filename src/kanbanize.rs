@@ -1,28 +1,189 @@
+use std::{collections::HashMap, sync::Mutex};
+
 use kanbanize_api::models::GetCard200Response;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{
+    error::{status_of, StatusError},
+    redact::Sensitive,
+};
 
 type Error = Box<dyn std::error::Error>;
 type Result<T, E = Error> = std::result::Result<T, E>;
 
 #[cfg_attr(test, mockall::automock)]
 #[async_trait::async_trait]
-pub trait KanbanizeApi {
-    async fn find_by_id(&self, id: i32) -> Result<Card>;
+pub trait KanbanizeApi: Send + Sync {
+    /// Extracts the card identifier referenced by a branch name, if any.
+    fn card_id_from_branch(&self, branch: &str) -> Option<String>;
+    /// Describes the branch names `card_id_from_branch` understands, for
+    /// people whose branch doesn't reference a card.
+    fn branch_pattern(&self) -> &'static str;
+    async fn find_by_id(&self, id: &str) -> Result<Card>;
+    /// Cards among `ids`, by ID, in as few requests as the tracker allows.
+    /// Cards that don't exist are left out.
+    async fn find_many(&self, ids: &[String]) -> Result<HashMap<String, Card>>;
+    /// Current [`Card::revision`] of a card, cheaper to get than the card.
+    async fn revision(&self, id: &str) -> Result<Option<String>>;
+    /// Current revisions of the cards among `ids`, in as few requests as the
+    /// tracker allows. Cards that don't exist or have no revision are left out.
+    async fn revisions(&self, ids: &[String]) -> Result<HashMap<String, String>>;
+    /// Cards matching `text`, best matches first. Only the ID and title are
+    /// guaranteed to be set.
+    async fn search(&self, text: &str) -> Result<Vec<Card>>;
+    /// Cards in a column or workflow state, as [`Card::column`] identifies it.
+    /// Only the ID, title and column are guaranteed to be set.
+    async fn cards_in_column(&self, column: &str) -> Result<Vec<Card>>;
+    /// Moves a card to a column or workflow state, given by ID or by name.
+    async fn move_card(&self, id: &str, column: &str) -> Result<()>;
+    /// Marks a subtask of the card `card_id` as finished.
+    async fn complete_subtask(&self, card_id: &str, subtask_id: &str) -> Result<()>;
+    /// Whether `url` points to a file hosted by the tracker, which can only be
+    /// opened with its credentials.
+    fn is_attachment(&self, url: &Url) -> bool;
+    /// Downloads a file hosted by the tracker.
+    async fn download(&self, url: &Url) -> Result<Vec<u8>>;
+    /// Users of the tracker, used to look up the emails of card users.
+    async fn users(&self) -> Result<Vec<User>>;
+    /// Verifies the API is reachable and the credentials are accepted.
+    async fn check(&self) -> Result<()>;
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Card {
+    /// Identifier used to fetch the card.
+    pub id: String,
+    /// Human readable key, such as a Kanbanize custom ID or a Linear identifier.
+    pub custom_id: Option<String>,
+    pub title: String,
+    pub description: String,
+    pub markup: Markup,
+    /// Card type, as the tracker identifies it.
+    pub card_type: Option<String>,
+    /// Tag IDs or label names.
+    pub tags: Vec<String>,
+    pub priority: Option<String>,
+    pub color: Option<String>,
+    /// Column or workflow state the card is in.
+    pub column: Option<String>,
+    /// Display name of `column`, for trackers that identify columns by ID.
+    pub column_name: Option<String>,
+    pub owner: Option<User>,
+    pub co_owners: Vec<User>,
+    pub watchers: Vec<User>,
+    pub subtasks: Vec<Subtask>,
+    /// Address of the card in the tracker web app.
+    pub url: Option<String>,
+    /// Cards this card is linked to.
+    pub links: Vec<Link>,
+    pub custom_fields: Vec<CustomField>,
+    /// Changes whenever the card does, `None` if the tracker doesn't tell.
+    pub revision: Option<String>,
+}
+
+/// Custom field value of a card.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CustomField {
+    pub id: String,
+    /// Name from the field definition, when known.
+    pub name: Option<String>,
+    /// Value as text, the selected options separated by newlines.
+    pub value: String,
+}
+
+/// Link from a card to another card of the same tracker.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Link {
+    /// Identifier used to fetch the linked card.
+    pub card_id: String,
+    pub relation: Relation,
+}
+
+/// What the linked card is to the card holding the link.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Relation {
+    Parent,
+    Child,
+    Predecessor,
+    Successor,
+    Relative,
+}
+
+impl Relation {
+    /// Parses a Kanbanize link type.
+    fn from_kanbanize(link_type: &str) -> Option<Self> {
+        match link_type {
+            "parent" => Some(Relation::Parent),
+            "child" => Some(Relation::Child),
+            "predecessor" => Some(Relation::Predecessor),
+            "successor" => Some(Relation::Successor),
+            "relative" => Some(Relation::Relative),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Relation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Relation::Parent => "Parent",
+            Relation::Child => "Child",
+            Relation::Predecessor => "Predecessor",
+            Relation::Successor => "Successor",
+            Relation::Relative => "Related",
+        })
+    }
+}
+
+/// Subtask, checklist item or sub-issue of a card.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Subtask {
+    pub id: String,
     pub description: String,
+    pub finished: bool,
+}
+
+/// Tracker user, the email is only known when the tracker includes it.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct User {
+    pub id: String,
+    pub email: Option<String>,
+}
+
+impl User {
+    pub fn new(id: impl ToString) -> Self {
+        Self {
+            id: id.to_string(),
+            email: None,
+        }
+    }
+}
+
+/// Markup language used by a card description.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum Markup {
+    Html,
+    #[default]
+    Markdown,
 }
 
 #[derive(Debug)]
 pub struct Kanbanize {
     config: kanbanize_api::apis::configuration::Configuration,
+    /// Column names by column ID, for each board already seen.
+    columns: Mutex<HashMap<String, HashMap<String, String>>>,
+    /// Custom field names by field ID, once listed.
+    field_names: Mutex<Option<HashMap<String, String>>>,
 }
 
 impl Kanbanize {
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(base_path: &str, api_key: &str) -> Box<dyn KanbanizeApi> {
         Box::new(Kanbanize {
             config: Self::new_config(base_path, api_key),
+            columns: Mutex::default(),
+            field_names: Mutex::default(),
         })
     }
 
@@ -39,32 +200,546 @@ impl Kanbanize {
             ..Default::default()
         }
     }
+
+    /// Builds an authenticated request for endpoints not covered by the generated client.
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        self.authorized(method, &format!("{}{}", self.config.base_path, path))
+    }
+
+    /// Builds a request to `url` carrying the API key.
+    fn authorized(&self, method: reqwest::Method, url: &str) -> reqwest::RequestBuilder {
+        let request = self.config.client.request(method, url);
+        match &self.config.api_key {
+            Some(api_key) => request.header("apikey", &api_key.key),
+            None => request,
+        }
+    }
+
+    /// Sends a request, turning error statuses into a [`StatusError`].
+    async fn response(request: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+        let response = request.send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(StatusError {
+                status: status.as_u16(),
+                message: format!("error in response: status code {}", status),
+            }
+            .into());
+        }
+        Ok(response)
+    }
+
+    async fn send(request: reqwest::RequestBuilder) -> Result<serde_json::Value> {
+        let response = Self::response(request).await?;
+        let content = response.text().await?;
+        if content.is_empty() {
+            return Ok(serde_json::Value::Null);
+        }
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    /// Column names by column ID on `board_id`, listed once per board.
+    async fn columns(&self, board_id: &str) -> Result<HashMap<String, String>> {
+        if let Some(columns) = self.columns.lock().unwrap().get(board_id) {
+            return Ok(columns.clone());
+        }
+        let result = Self::send(self.request(
+            reqwest::Method::GET,
+            &format!("/boards/{}/columns", board_id),
+        ))
+        .await?;
+        let columns: HashMap<String, String> = result["data"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|column| {
+                Some((
+                    column["column_id"].as_i64()?.to_string(),
+                    column["name"].as_str()?.to_string(),
+                ))
+            })
+            .collect();
+        self.columns
+            .lock()
+            .unwrap()
+            .insert(board_id.to_string(), columns.clone());
+        Ok(columns)
+    }
+
+    /// Name of a column. Cards are still shown without it if the columns
+    /// can't be listed.
+    async fn column_name(&self, board_id: &str, column_id: &str) -> Option<String> {
+        match self.columns(board_id).await {
+            Ok(columns) => columns.get(column_id).cloned(),
+            Err(e) => {
+                tracing::debug!("Could not list the columns of board {}: {}", board_id, e);
+                None
+            }
+        }
+    }
+
+    /// ID of the column named `name` on the board of the card `card_id`.
+    async fn column_id(&self, card_id: &str, name: &str) -> Result<String> {
+        let result = Self::send(
+            self.request(reqwest::Method::GET, &format!("/cards/{}", card_id))
+                .query(&[("fields", "board_id")]),
+        )
+        .await?;
+        let board_id = result["data"]["board_id"]
+            .as_i64()
+            .ok_or_else(|| format!("card {} has no board", card_id))?
+            .to_string();
+        self.columns(&board_id)
+            .await?
+            .into_iter()
+            .find(|(_, column)| column == name)
+            .map(|(id, _)| id)
+            .ok_or_else(|| format!("no column `{}` on board {}", name, board_id).into())
+    }
+
+    /// Names of the custom fields, listed on first use. Fields are still
+    /// matched by ID if the definitions can't be listed.
+    async fn field_names(&self) -> Option<HashMap<String, String>> {
+        if let Some(names) = self.field_names.lock().unwrap().as_ref() {
+            return Some(names.clone());
+        }
+        let result = Self::send(self.request(reqwest::Method::GET, "/customFields")).await;
+        let result = match result {
+            Ok(result) => result,
+            Err(e) => {
+                tracing::debug!("Could not list the custom fields: {}", e);
+                return None;
+            }
+        };
+        let names: HashMap<String, String> = result["data"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|field| {
+                Some((
+                    field["field_id"].as_i64()?.to_string(),
+                    field["name"].as_str()?.to_string(),
+                ))
+            })
+            .collect();
+        *self.field_names.lock().unwrap() = Some(names.clone());
+        Some(names)
+    }
+
+    async fn subtasks(&self, card_id: &str) -> Result<Vec<Subtask>> {
+        let result = Self::send(self.request(
+            reqwest::Method::GET,
+            &format!("/cards/{}/subtasks", card_id),
+        ))
+        .await?;
+        Ok(result["data"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(subtask)
+            .collect())
+    }
+
+    /// Completes a card with its address, column name and custom field names.
+    async fn details(&self, mut card: Card, board_id: Option<String>) -> Result<Card> {
+        card.url = match (Url::parse(&self.config.base_path), &board_id) {
+            (Ok(base), Some(board_id)) => Some(format!(
+                "{}ctrl_board/{}/cards/{}/details/",
+                base.join("/")?,
+                board_id,
+                card.id
+            )),
+            _ => None,
+        };
+        if let (Some(board_id), Some(column)) = (&board_id, card.column.as_ref()) {
+            card.column_name = self.column_name(board_id, column).await;
+        }
+        if !card.custom_fields.is_empty() {
+            if let Some(names) = self.field_names().await {
+                for field in &mut card.custom_fields {
+                    field.name = names.get(&field.id).cloned();
+                }
+            }
+        }
+        Ok(card)
+    }
+}
+
+fn subtask(subtask: &serde_json::Value) -> Option<Subtask> {
+    Some(Subtask {
+        id: subtask["subtask_id"].as_i64()?.to_string(),
+        description: subtask["description"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string(),
+        finished: !subtask["finished_at"].is_null(),
+    })
+}
+
+/// Largest number of cards requested at once by [`Kanbanize::find_many`].
+const CARDS_PER_REQUEST: usize = 100;
+
+/// Fields of the cards listed by [`Kanbanize::find_many`], the ones
+/// [`Card`] is built from.
+const CARD_FIELDS: &str = "card_id,custom_id,board_id,title,description,owner_user_id,type_id,color,column_id,priority,revision";
+
+/// Expanded attributes of the cards listed by [`Kanbanize::find_many`].
+const CARD_EXPAND: &str = "co_owner_ids,watcher_ids,tag_ids,custom_fields,linked_cards,subtasks";
+
+/// The IDs among `ids` that designate the card numbered `card_id`, which the
+/// API reports without the leading zeros a branch name may have.
+fn requested_ids(ids: &[String], card_id: Option<i64>) -> Vec<String> {
+    ids.iter()
+        .filter(|id| card_id.is_some() && id.parse::<i64>().ok() == card_id)
+        .cloned()
+        .collect()
+}
+
+/// Finds cards one at a time, for trackers without a bulk endpoint.
+pub async fn find_each(
+    tracker: &dyn KanbanizeApi,
+    ids: &[String],
+) -> Result<HashMap<String, Card>> {
+    let mut cards = HashMap::new();
+    for id in ids {
+        match tracker.find_by_id(id).await {
+            Ok(card) => {
+                cards.insert(id.clone(), card);
+            }
+            Err(e) if status_of(e.as_ref()) == Some(404) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(cards)
+}
+
+/// Gets revisions one card at a time, for trackers without a bulk endpoint.
+pub async fn revision_each(
+    tracker: &dyn KanbanizeApi,
+    ids: &[String],
+) -> Result<HashMap<String, String>> {
+    let mut revisions = HashMap::new();
+    for id in ids {
+        match tracker.revision(id).await {
+            Ok(Some(revision)) => {
+                revisions.insert(id.clone(), revision);
+            }
+            Ok(None) => {}
+            Err(e) if status_of(e.as_ref()) == Some(404) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(revisions)
 }
 
 #[async_trait::async_trait]
 impl KanbanizeApi for Kanbanize {
-    async fn find_by_id(&self, id: i32) -> Result<Card> {
-        let result = kanbanize_api::apis::cards_api::get_card(&self.config, id).await;
-        tracing::debug!("Response: {:?}", result);
-        return result
-            .map_err(|e| Into::<Error>::into(e.to_string()))
-            .map(|c| c.try_into())?;
+    fn card_id_from_branch(&self, branch: &str) -> Option<String> {
+        let re = regex::Regex::new(r"\d+(\.\d+)?").unwrap();
+        re.find(branch).map(|id| id.as_str().to_string())
+    }
+
+    fn branch_pattern(&self) -> &'static str {
+        "the card ID, such as `1234-fix-login`"
+    }
+
+    async fn find_by_id(&self, id: &str) -> Result<Card> {
+        let result = kanbanize_api::apis::cards_api::get_card(&self.config, id.parse()?).await;
+        tracing::debug!("Response: {:?}", Sensitive(&result));
+        let response = result.map_err(|e| match &e {
+            kanbanize_api::apis::Error::ResponseError(response) => StatusError {
+                status: response.status.as_u16(),
+                message: e.to_string(),
+            }
+            .into(),
+            _ => Into::<Error>::into(e.to_string()),
+        })?;
+
+        // Subtasks are only fetched for cards that have some
+        let has_subtasks = response.data.as_ref().is_some_and(|card| {
+            card.finished_subtask_count.unwrap_or_default()
+                + card.unfinished_subtask_count.unwrap_or_default()
+                > 0
+        });
+        let board_id = response
+            .data
+            .as_ref()
+            .and_then(|card| card.board_id)
+            .map(|board_id| board_id.to_string());
+        let card: Card = response.try_into()?;
+        let mut card = self.details(card, board_id).await?;
+        if has_subtasks {
+            card.subtasks = self.subtasks(&card.id).await?;
+        }
+        Ok(card)
+    }
+
+    async fn find_many(&self, ids: &[String]) -> Result<HashMap<String, Card>> {
+        let mut cards = HashMap::new();
+        for chunk in ids.chunks(CARDS_PER_REQUEST) {
+            let card_ids = chunk.join(",");
+            let per_page = chunk.len().to_string();
+            let result = Self::send(self.request(reqwest::Method::GET, "/cards").query(&[
+                ("card_ids", card_ids.as_str()),
+                ("fields", CARD_FIELDS),
+                ("expand", CARD_EXPAND),
+                ("per_page", per_page.as_str()),
+            ]))
+            .await?;
+            for item in result["data"]["data"].as_array().into_iter().flatten() {
+                // Subtasks come with the card, unlike with `find_by_id`
+                let subtasks = item["subtasks"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(subtask)
+                    .collect();
+                let requested = requested_ids(chunk, item["card_id"].as_i64());
+                let board_id = item["board_id"].as_i64().map(|id| id.to_string());
+                let response = GetCard200Response {
+                    data: Some(Box::new(serde_json::from_value(item.clone())?)),
+                };
+                let card: Card = response.try_into()?;
+                let mut card = self.details(card, board_id).await?;
+                card.subtasks = subtasks;
+                for id in requested {
+                    cards.insert(id, card.clone());
+                }
+            }
+        }
+        Ok(cards)
+    }
+
+    async fn revision(&self, id: &str) -> Result<Option<String>> {
+        let result = Self::send(
+            self.request(reqwest::Method::GET, "/cards")
+                .query(&[("card_ids", id), ("fields", "card_id,revision")]),
+        )
+        .await?;
+        let card = result["data"]["data"]
+            .as_array()
+            .and_then(|cards| cards.first())
+            .ok_or_else(|| StatusError {
+                status: 404,
+                message: format!("card {} not found", id),
+            })?;
+        Ok(card["revision"]
+            .as_i64()
+            .map(|revision| revision.to_string()))
+    }
+
+    async fn revisions(&self, ids: &[String]) -> Result<HashMap<String, String>> {
+        let mut revisions = HashMap::new();
+        for chunk in ids.chunks(CARDS_PER_REQUEST) {
+            let card_ids = chunk.join(",");
+            let per_page = chunk.len().to_string();
+            let result = Self::send(self.request(reqwest::Method::GET, "/cards").query(&[
+                ("card_ids", card_ids.as_str()),
+                ("fields", "card_id,revision"),
+                ("per_page", per_page.as_str()),
+            ]))
+            .await?;
+            for item in result["data"]["data"].as_array().into_iter().flatten() {
+                if let Some(revision) = item["revision"].as_i64() {
+                    for id in requested_ids(chunk, item["card_id"].as_i64()) {
+                        revisions.insert(id, revision.to_string());
+                    }
+                }
+            }
+        }
+        Ok(revisions)
+    }
+
+    async fn search(&self, _text: &str) -> Result<Vec<Card>> {
+        // The Kanbanize API has no full text search over cards
+        Ok(Vec::new())
+    }
+
+    async fn cards_in_column(&self, column: &str) -> Result<Vec<Card>> {
+        let mut cards = Vec::new();
+        let mut page = 1;
+        loop {
+            let page_number = page.to_string();
+            let per_page = CARDS_PER_REQUEST.to_string();
+            let result = Self::send(self.request(reqwest::Method::GET, "/cards").query(&[
+                ("column_ids", column),
+                ("fields", "card_id,custom_id,board_id,title,column_id"),
+                ("page", page_number.as_str()),
+                ("per_page", per_page.as_str()),
+            ]))
+            .await?;
+            for item in result["data"]["data"].as_array().into_iter().flatten() {
+                let Some(id) = item["card_id"].as_i64() else {
+                    continue;
+                };
+                let card = Card {
+                    id: id.to_string(),
+                    custom_id: item["custom_id"]
+                        .as_str()
+                        .filter(|id| !id.is_empty())
+                        .map(ToString::to_string),
+                    title: item["title"].as_str().unwrap_or_default().to_string(),
+                    column: item["column_id"].as_i64().map(|id| id.to_string()),
+                    ..Default::default()
+                };
+                let board_id = item["board_id"].as_i64().map(|id| id.to_string());
+                cards.push(self.details(card, board_id).await?);
+            }
+            let pages = result["data"]["pagination"]["all_pages"]
+                .as_i64()
+                .unwrap_or(1);
+            if page >= pages {
+                return Ok(cards);
+            }
+            page += 1;
+        }
+    }
+
+    async fn move_card(&self, id: &str, column: &str) -> Result<()> {
+        let column_id = match column.parse::<i64>() {
+            Ok(column_id) => column_id,
+            Err(_) => self.column_id(id, column).await?.parse()?,
+        };
+        let data = serde_json::json!({ "column_id": column_id });
+        Self::send(
+            self.request(reqwest::Method::PATCH, &format!("/cards/{}", id))
+                .json(&data),
+        )
+        .await
+        .map(|_| ())
+    }
+
+    async fn complete_subtask(&self, card_id: &str, subtask_id: &str) -> Result<()> {
+        let data = serde_json::json!({ "is_finished": 1 });
+        Self::send(
+            self.request(
+                reqwest::Method::PATCH,
+                &format!("/cards/{}/subtasks/{}", card_id, subtask_id),
+            )
+            .json(&data),
+        )
+        .await
+        .map(|_| ())
+    }
+
+    fn is_attachment(&self, url: &Url) -> bool {
+        // Other pages of the host, such as card links, are not files
+        let is_file = url.path().starts_with("/files/") || url.path().contains("/attachments/");
+        is_file
+            && Url::parse(&self.config.base_path)
+                .is_ok_and(|base| base.host_str().is_some() && base.host_str() == url.host_str())
+    }
+
+    async fn download(&self, url: &Url) -> Result<Vec<u8>> {
+        let response = Self::response(self.authorized(reqwest::Method::GET, url.as_str())).await?;
+        Ok(response.bytes().await?.to_vec())
+    }
+
+    async fn users(&self) -> Result<Vec<User>> {
+        let result = Self::send(self.request(reqwest::Method::GET, "/users")).await?;
+        Ok(result["data"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|user| {
+                Some(User {
+                    id: user["user_id"].as_i64()?.to_string(),
+                    email: user["email"].as_str().map(ToString::to_string),
+                })
+            })
+            .collect())
+    }
+
+    async fn check(&self) -> Result<()> {
+        Self::send(self.request(reqwest::Method::GET, "/me"))
+            .await
+            .map(|_| ())
     }
 }
 
 impl TryFrom<GetCard200Response> for Card {
     type Error = Error;
     fn try_from(value: GetCard200Response) -> Result<Self, Self::Error> {
+        let card = value.data.ok_or("card has no data")?;
         Ok(Self {
-            description: value
-                .data
-                .ok_or("card has no data")?
-                .description
-                .unwrap_or_default(),
+            id: card.card_id.ok_or("card has no id")?.to_string(),
+            custom_id: card.custom_id.filter(|id| !id.is_empty()),
+            title: card.title.unwrap_or_default(),
+            description: card.description.unwrap_or_default(),
+            markup: Markup::Html,
+            card_type: card.type_id.map(|id| id.to_string()),
+            tags: card
+                .tag_ids
+                .unwrap_or_default()
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+            priority: card.priority.map(|priority| priority.to_string()),
+            color: card.color.filter(|color| !color.is_empty()),
+            column: card.column_id.map(|id| id.to_string()),
+            column_name: None,
+            owner: card.owner_user_id.map(User::new),
+            co_owners: card
+                .co_owner_ids
+                .unwrap_or_default()
+                .into_iter()
+                .map(User::new)
+                .collect(),
+            watchers: card
+                .watchers_ids
+                .unwrap_or_default()
+                .into_iter()
+                .map(User::new)
+                .collect(),
+            subtasks: Vec::new(),
+            url: None,
+            // Serialized to read the links without depending on how the
+            // generated client models them
+            links: serde_json::to_value(&card.linked_cards)?
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|link| {
+                    Some(Link {
+                        card_id: link["card_id"].as_i64()?.to_string(),
+                        relation: Relation::from_kanbanize(link["link_type"].as_str()?)?,
+                    })
+                })
+                .collect(),
+            revision: card.revision.map(|revision| revision.to_string()),
+            custom_fields: serde_json::to_value(&card.custom_fields)?
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|field| {
+                    Some(CustomField {
+                        id: field["field_id"].as_i64()?.to_string(),
+                        name: None,
+                        value: field_value(field),
+                    })
+                })
+                .filter(|field| !field.value.is_empty())
+                .collect(),
         })
     }
 }
 
+/// Text of a custom field value: the value itself, or the selected options.
+fn field_value(field: &serde_json::Value) -> String {
+    match &field["value"] {
+        serde_json::Value::String(value) => return value.clone(),
+        serde_json::Value::Number(value) => return value.to_string(),
+        _ => {}
+    }
+    field["selected_values"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|option| option["value"].as_str())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -83,19 +758,19 @@ mod test {
                     data: Some(Box::new(kanbanize_api::models::Card {
                         card_id: Some(4321),
                         description: Some("Card description".to_string()),
-                        custom_id: None,
+                        custom_id: Some("REQ-4321".to_string()),
                         board_id: None,
                         workflow_id: None,
-                        title: None,
-                        owner_user_id: None,
-                        type_id: None,
-                        color: None,
+                        title: Some("Card title".to_string()),
+                        owner_user_id: Some(17),
+                        type_id: Some(3),
+                        color: Some("e53935".to_string()),
                         section: None,
-                        column_id: None,
+                        column_id: Some(5),
                         lane_id: None,
                         position: None,
                         size: None,
-                        priority: None,
+                        priority: Some(1),
                         deadline: None,
                         reporter: None,
                         created_at: None,
@@ -110,8 +785,8 @@ mod test {
                         attachments: None,
                         custom_fields: None,
                         stickers: None,
-                        tag_ids: None,
-                        co_owner_ids: None,
+                        tag_ids: Some(vec![12, 14]),
+                        co_owner_ids: Some(vec![18]),
                         watchers_ids: None,
                         annotations: None,
                         outcomes: None,
@@ -126,9 +801,20 @@ mod test {
 
         // Create client
         let client = Kanbanize::new(server.url().as_str(), "api_key");
-        let card = client.find_by_id(4321).await?;
+        let card = client.find_by_id("4321").await?;
 
+        assert_eq!(card.id, "4321");
+        assert_eq!(card.custom_id, Some("REQ-4321".to_string()));
+        assert_eq!(card.title, "Card title");
+        assert_eq!(card.card_type, Some("3".to_string()));
+        assert_eq!(card.tags, vec!["12".to_string(), "14".to_string()]);
+        assert_eq!(card.priority, Some("1".to_string()));
+        assert_eq!(card.color, Some("e53935".to_string()));
+        assert_eq!(card.column, Some("5".to_string()));
+        assert_eq!(card.owner, Some(User::new(17)));
+        assert_eq!(card.co_owners, vec![User::new(18)]);
         assert_eq!(card.description, "Card description".to_string());
+        assert_eq!(card.markup, Markup::Html);
 
         mock.assert_async().await;
         Ok(())
@@ -148,14 +834,428 @@ mod test {
 
         // Create client
         let client = Kanbanize::new(server.url().as_str(), "api_key");
-        let card = client.find_by_id(4321).await;
+        let card = client.find_by_id("4321").await;
         assert!(card.is_err());
+        let error = card.err().unwrap();
         assert_eq!(
-            card.err().unwrap().to_string(),
+            error.to_string(),
             "error in response: status code 404 Not Found"
         );
+        assert_eq!(crate::error::status_of(error.as_ref()), Some(404));
+
+        mock.assert_async().await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_check() -> testresult::TestResult {
+        let mut server = mockito::Server::new_async().await;
+
+        let mock = server
+            .mock("GET", "/me")
+            .match_header("apikey", "api_key")
+            .with_status(200)
+            .with_body(r#"{"data": {"user_id": 1}}"#)
+            .create_async()
+            .await;
+
+        let client = Kanbanize::new(server.url().as_str(), "api_key");
+        client.check().await?;
+
+        mock.assert_async().await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_users() -> testresult::TestResult {
+        let mut server = mockito::Server::new_async().await;
+
+        let mock = server
+            .mock("GET", "/users")
+            .match_header("apikey", "api_key")
+            .with_status(200)
+            .with_body(
+                r#"{"data": [{"user_id": 17, "email": "jane@example.com"}, {"user_id": 18}]}"#,
+            )
+            .create_async()
+            .await;
+
+        let client = Kanbanize::new(server.url().as_str(), "api_key");
+        assert_eq!(
+            client.users().await?,
+            vec![
+                User {
+                    id: "17".to_string(),
+                    email: Some("jane@example.com".to_string()),
+                },
+                User::new(18),
+            ]
+        );
+
+        mock.assert_async().await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_find_card_subtasks() -> testresult::TestResult {
+        let mut server = mockito::Server::new_async().await;
+
+        let card = server
+            .mock("GET", "/cards/4321")
+            .with_status(200)
+            .with_body(r#"{"data": {"card_id": 4321, "board_id": 2, "title": "Card title", "finished_subtask_count": 1, "unfinished_subtask_count": 1}}"#)
+            .create_async()
+            .await;
+        let subtasks = server
+            .mock("GET", "/cards/4321/subtasks")
+            .match_header("apikey", "api_key")
+            .with_status(200)
+            .with_body(r#"{"data": [{"subtask_id": 1, "description": "Write tests", "finished_at": null}, {"subtask_id": 2, "description": "Design", "finished_at": "2023-10-01T10:00:00Z"}]}"#)
+            .create_async()
+            .await;
+
+        let client = Kanbanize::new(server.url().as_str(), "api_key");
+        let found = client.find_by_id("4321").await?;
+        assert_eq!(
+            found.url,
+            Some(format!("{}/ctrl_board/2/cards/4321/details/", server.url()))
+        );
+        assert_eq!(
+            found.subtasks,
+            vec![
+                Subtask {
+                    id: "1".to_string(),
+                    description: "Write tests".to_string(),
+                    finished: false,
+                },
+                Subtask {
+                    id: "2".to_string(),
+                    description: "Design".to_string(),
+                    finished: true,
+                },
+            ]
+        );
+
+        card.assert_async().await;
+        subtasks.assert_async().await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_find_card_links() -> testresult::TestResult {
+        let mut server = mockito::Server::new_async().await;
+
+        server
+            .mock("GET", mockito::Matcher::Regex(r"^/cards/\d+$".to_string()))
+            .with_status(200)
+            .with_body(r#"{"data": {"card_id": 4321, "board_id": 2, "column_id": 5, "title": "Card title", "linked_cards": [{"card_id": 12, "position": 0, "link_type": "parent"}, {"card_id": 4400, "position": 1, "link_type": "relative"}]}}"#)
+            .create_async()
+            .await;
+        let columns = server
+            .mock("GET", "/boards/2/columns")
+            .with_status(200)
+            .with_body(r#"{"data": [{"column_id": 5, "name": "In Progress"}]}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let client = Kanbanize::new(server.url().as_str(), "api_key");
+        let found = client.find_by_id("4321").await?;
+        assert_eq!(found.column_name, Some("In Progress".to_string()));
+        assert_eq!(
+            found.links,
+            vec![
+                Link {
+                    card_id: "12".to_string(),
+                    relation: Relation::Parent,
+                },
+                Link {
+                    card_id: "4400".to_string(),
+                    relation: Relation::Relative,
+                },
+            ]
+        );
+
+        // Columns are listed once per board
+        client.find_by_id("4400").await?;
+        columns.assert_async().await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_find_card_custom_fields() -> testresult::TestResult {
+        let mut server = mockito::Server::new_async().await;
+
+        server
+            .mock("GET", "/cards/4321")
+            .with_status(200)
+            .with_body(r#"{"data": {"card_id": 4321, "title": "Card title", "custom_fields": [{"field_id": 7, "value": "Works offline"}, {"field_id": 8, "value": null, "selected_values": [{"value_id": 1, "value": "iOS"}, {"value_id": 2, "value": "Android"}]}, {"field_id": 9, "value": ""}]}}"#)
+            .create_async()
+            .await;
+        let definitions = server
+            .mock("GET", "/customFields")
+            .with_status(200)
+            .with_body(r#"{"data": [{"field_id": 7, "name": "Acceptance criteria"}, {"field_id": 8, "name": "Platforms"}]}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let client = Kanbanize::new(server.url().as_str(), "api_key");
+        let found = client.find_by_id("4321").await?;
+        assert_eq!(
+            found.custom_fields,
+            vec![
+                CustomField {
+                    id: "7".to_string(),
+                    name: Some("Acceptance criteria".to_string()),
+                    value: "Works offline".to_string(),
+                },
+                CustomField {
+                    id: "8".to_string(),
+                    name: Some("Platforms".to_string()),
+                    value: "iOS\nAndroid".to_string(),
+                },
+            ]
+        );
+
+        // Definitions are listed once
+        client.find_by_id("4321").await?;
+        definitions.assert_async().await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_download() -> testresult::TestResult {
+        let mut server = mockito::Server::new_async().await;
+
+        let mock = server
+            .mock("GET", "/files/screenshot.png")
+            .match_header("apikey", "api_key")
+            .with_status(200)
+            .with_body([1, 2, 3])
+            .create_async()
+            .await;
+
+        let client = Kanbanize::new(&format!("{}/api/v2", server.url()), "api_key");
+        let url = Url::parse(&format!("{}/files/screenshot.png", server.url()))?;
+        assert!(client.is_attachment(&url));
+        assert!(!client.is_attachment(&Url::parse("https://example.com/logo.png")?));
+        let card_link = Url::parse(&format!("{}/ctrl_board/1/cards/5", server.url()))?;
+        assert!(!client.is_attachment(&card_link));
+        let attachment = Url::parse(&format!(
+            "{}/api/v2/cards/5/attachments/9/download",
+            server.url()
+        ))?;
+        assert!(client.is_attachment(&attachment));
+        assert_eq!(client.download(&url).await?, vec![1, 2, 3]);
+
+        mock.assert_async().await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_find_many() -> testresult::TestResult {
+        let mut server = mockito::Server::new_async().await;
+
+        let cards = server
+            .mock("GET", "/cards")
+            .match_query(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::UrlEncoded("card_ids".into(), "4321,04400,9".into()),
+                mockito::Matcher::UrlEncoded("expand".into(), CARD_EXPAND.into()),
+            ]))
+            .with_status(200)
+            .with_body(r#"{"data": {"pagination": {"all_pages": 1}, "data": [{"card_id": 4321, "board_id": 2, "title": "Card title", "subtasks": [{"subtask_id": 1, "description": "Write tests", "finished_at": null}]}, {"card_id": 4400, "title": "Other card", "tag_ids": [12]}]}}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let client = Kanbanize::new(server.url().as_str(), "api_key");
+        // Keyed by the requested IDs, which may have leading zeros
+        let ids = ["4321", "04400", "9"].map(String::from);
+        let found = client.find_many(&ids).await?;
+        assert_eq!(found.len(), 2);
+        assert_eq!(found["4321"].title, "Card title");
+        assert_eq!(
+            found["4321"].url,
+            Some(format!("{}/ctrl_board/2/cards/4321/details/", server.url()))
+        );
+        assert_eq!(
+            found["4321"].subtasks,
+            vec![Subtask {
+                id: "1".to_string(),
+                description: "Write tests".to_string(),
+                finished: false,
+            }]
+        );
+        assert_eq!(found["04400"].tags, vec!["12".to_string()]);
+
+        cards.assert_async().await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_revision() -> testresult::TestResult {
+        let mut server = mockito::Server::new_async().await;
+
+        server
+            .mock("GET", "/cards")
+            .match_query(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::UrlEncoded("card_ids".into(), "4321".into()),
+                mockito::Matcher::UrlEncoded("fields".into(), "card_id,revision".into()),
+            ]))
+            .with_status(200)
+            .with_body(r#"{"data": {"pagination": {"all_pages": 1}, "data": [{"card_id": 4321, "revision": 12}]}}"#)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/cards")
+            .match_query(mockito::Matcher::UrlEncoded("card_ids".into(), "9".into()))
+            .with_status(200)
+            .with_body(r#"{"data": {"pagination": {"all_pages": 1}, "data": []}}"#)
+            .create_async()
+            .await;
+
+        let client = Kanbanize::new(server.url().as_str(), "api_key");
+        assert_eq!(client.revision("4321").await?, Some("12".to_string()));
+        let missing = client.revision("9").await.unwrap_err();
+        assert_eq!(crate::error::status_of(missing.as_ref()), Some(404));
+
+        server
+            .mock("GET", "/cards")
+            .match_query(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::UrlEncoded("card_ids".into(), "04321,9".into()),
+                mockito::Matcher::UrlEncoded("fields".into(), "card_id,revision".into()),
+            ]))
+            .with_status(200)
+            .with_body(r#"{"data": {"pagination": {"all_pages": 1}, "data": [{"card_id": 4321, "revision": 12}]}}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        let ids = ["04321", "9"].map(String::from);
+        let revisions = client.revisions(&ids).await?;
+        assert_eq!(
+            revisions,
+            HashMap::from([("04321".to_string(), "12".to_string())])
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cards_in_column() -> testresult::TestResult {
+        let mut server = mockito::Server::new_async().await;
+
+        let first = server
+            .mock("GET", "/cards")
+            .match_query(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::UrlEncoded("column_ids".into(), "7".into()),
+                mockito::Matcher::UrlEncoded("page".into(), "1".into()),
+            ]))
+            .with_status(200)
+            .with_body(r#"{"data": {"pagination": {"all_pages": 2}, "data": [{"card_id": 4321, "custom_id": "REQ-1", "board_id": 2, "title": "Card title", "column_id": 7}]}}"#)
+            .create_async()
+            .await;
+        let second = server
+            .mock("GET", "/cards")
+            .match_query(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::UrlEncoded("column_ids".into(), "7".into()),
+                mockito::Matcher::UrlEncoded("page".into(), "2".into()),
+            ]))
+            .with_status(200)
+            .with_body(r#"{"data": {"pagination": {"all_pages": 2}, "data": [{"card_id": 4400, "board_id": 2, "title": "Other card", "column_id": 7}]}}"#)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/boards/2/columns")
+            .with_status(200)
+            .with_body(r#"{"data": [{"column_id": 7, "name": "In Review"}]}"#)
+            .create_async()
+            .await;
+
+        let client = Kanbanize::new(server.url().as_str(), "api_key");
+        let cards = client.cards_in_column("7").await?;
+        assert_eq!(
+            cards
+                .iter()
+                .map(|card| card.id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["4321", "4400"]
+        );
+        assert_eq!(cards[0].custom_id, Some("REQ-1".to_string()));
+        assert_eq!(cards[1].column_name, Some("In Review".to_string()));
+        assert_eq!(
+            cards[1].url,
+            Some(format!("{}/ctrl_board/2/cards/4400/details/", server.url()))
+        );
+
+        first.assert_async().await;
+        second.assert_async().await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_move_card() -> testresult::TestResult {
+        let mut server = mockito::Server::new_async().await;
+
+        let mock = server
+            .mock("PATCH", "/cards/4321")
+            .match_header("apikey", "api_key")
+            .match_body(mockito::Matcher::Json(
+                serde_json::json!({ "column_id": 7 }),
+            ))
+            .with_status(200)
+            .with_body(r#"{"data": {"card_id": 4321}}"#)
+            .expect(2)
+            .create_async()
+            .await;
+
+        let client = Kanbanize::new(server.url().as_str(), "api_key");
+        client.move_card("4321", "7").await?;
+
+        // Names are looked up on the board of the card
+        server
+            .mock("GET", "/cards/4321?fields=board_id")
+            .with_status(200)
+            .with_body(r#"{"data": {"card_id": 4321, "board_id": 2}}"#)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/boards/2/columns")
+            .with_status(200)
+            .with_body(r#"{"data": [{"column_id": 5, "name": "In Progress"}, {"column_id": 7, "name": "In Review"}]}"#)
+            .create_async()
+            .await;
+        client.move_card("4321", "In Review").await?;
+        assert!(client.move_card("4321", "Done").await.is_err());
 
         mock.assert_async().await;
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_check_unauthorized() -> testresult::TestResult {
+        let mut server = mockito::Server::new_async().await;
+
+        let mock = server
+            .mock("GET", "/me")
+            .with_status(401)
+            .create_async()
+            .await;
+
+        let client = Kanbanize::new(server.url().as_str(), "bad_key");
+        let error = client.check().await.err().unwrap();
+        assert_eq!(crate::error::status_of(error.as_ref()), Some(401));
+
+        mock.assert_async().await;
+        Ok(())
+    }
+
+    #[test]
+    fn test_card_id_from_branch() {
+        let client = Kanbanize::new("http://localhost", "api_key");
+        assert_eq!(
+            client.card_id_from_branch("request-123"),
+            Some("123".to_string())
+        );
+        assert_eq!(client.card_id_from_branch("main"), None);
+    }
 }
@@ -0,0 +1,150 @@
+use std::collections::{BTreeSet, HashMap};
+
+use serde::Deserialize;
+
+use crate::kanbanize::Card;
+
+/// Maps card attributes to pull request labels. Keys are the values reported
+/// by the tracker, such as a Kanbanize type ID or a Linear label name.
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+pub struct LabelMapping {
+    #[serde(default, rename = "type")]
+    pub card_type: HashMap<String, String>,
+    #[serde(default)]
+    pub tags: HashMap<String, String>,
+    #[serde(default)]
+    pub priority: HashMap<String, String>,
+    #[serde(default)]
+    pub color: HashMap<String, String>,
+}
+
+/// Labels to add to and remove from a pull request.
+#[derive(Debug, Default, PartialEq)]
+pub struct LabelChanges {
+    pub add: Vec<String>,
+    pub remove: Vec<String>,
+}
+
+impl LabelChanges {
+    pub fn is_empty(&self) -> bool {
+        self.add.is_empty() && self.remove.is_empty()
+    }
+}
+
+/// Whether `labels` has `label`, ignoring case like GitHub does.
+pub fn has_label<'a>(labels: impl IntoIterator<Item = &'a String>, label: &str) -> bool {
+    labels
+        .into_iter()
+        .any(|existing| existing.eq_ignore_ascii_case(label))
+}
+
+impl LabelMapping {
+    /// Labels derived from the attributes of `card`.
+    pub fn labels_for(&self, card: &Card) -> BTreeSet<String> {
+        let lookup = |map: &HashMap<String, String>, key: &Option<String>| {
+            key.as_ref().and_then(|key| map.get(key)).cloned()
+        };
+        lookup(&self.card_type, &card.card_type)
+            .into_iter()
+            .chain(lookup(&self.priority, &card.priority))
+            .chain(lookup(&self.color, &card.color))
+            .chain(
+                card.tags
+                    .iter()
+                    .filter_map(|tag| self.tags.get(tag).cloned()),
+            )
+            .collect()
+    }
+
+    /// Every label gitkban manages, the only ones it will ever remove.
+    fn managed(&self) -> BTreeSet<&String> {
+        self.card_type
+            .values()
+            .chain(self.tags.values())
+            .chain(self.priority.values())
+            .chain(self.color.values())
+            .collect()
+    }
+
+    /// Compares the labels of `card` with the `current` labels of a pull
+    /// request. Labels that are not managed by gitkban are kept.
+    pub fn changes(&self, card: &Card, current: &[String]) -> LabelChanges {
+        let wanted = self.labels_for(card);
+        let managed = self.managed();
+        LabelChanges {
+            add: wanted
+                .iter()
+                .filter(|label| !has_label(current, label))
+                .cloned()
+                .collect(),
+            remove: current
+                .iter()
+                .filter(|label| {
+                    has_label(managed.iter().copied(), label) && !has_label(&wanted, label)
+                })
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn mapping() -> LabelMapping {
+        toml::from_str(
+            r#"
+            type = { "3" = "bug" }
+            tags = { "12" = "frontend", "14" = "backend" }
+            priority = { "1" = "p1", "2" = "p2" }
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_labels_for() {
+        let card = Card {
+            card_type: Some("3".to_string()),
+            tags: vec!["12".to_string(), "99".to_string()],
+            priority: Some("1".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            mapping().labels_for(&card).into_iter().collect::<Vec<_>>(),
+            vec!["bug", "frontend", "p1"]
+        );
+    }
+
+    #[test]
+    fn test_changes() {
+        let card = Card {
+            priority: Some("1".to_string()),
+            ..Default::default()
+        };
+        let current = vec!["p2".to_string(), "needs-review".to_string()];
+        assert_eq!(
+            mapping().changes(&card, &current),
+            LabelChanges {
+                add: vec!["p1".to_string()],
+                remove: vec!["p2".to_string()],
+            }
+        );
+
+        let current = vec!["p1".to_string()];
+        assert!(mapping().changes(&card, &current).is_empty());
+
+        // GitHub label names are case insensitive
+        let current = vec!["P1".to_string()];
+        assert!(mapping().changes(&card, &current).is_empty());
+        let current = vec!["P2".to_string()];
+        assert_eq!(
+            mapping().changes(&card, &current),
+            LabelChanges {
+                add: vec!["p1".to_string()],
+                remove: vec!["P2".to_string()],
+            }
+        );
+    }
+}
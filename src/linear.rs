@@ -0,0 +1,553 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+};
+
+use regex::Regex;
+use url::Url;
+
+use crate::{
+    kanbanize::{self, Card, KanbanizeApi, Link, Markup, Relation, Subtask, User},
+    redact::Sensitive,
+};
+
+type Error = Box<dyn std::error::Error>;
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+const ISSUE_QUERY: &str =
+    "query Issue($id: String!) { issue(id: $id) { identifier url updatedAt title description priority labels { nodes { name } } state { name } assignee { id email } subscribers { nodes { id email } } children { nodes { id title completedAt } } parent { identifier } } }";
+const REVISION_QUERY: &str = "query Revision($id: String!) { issue(id: $id) { updatedAt } }";
+const SEARCH_QUERY: &str = "query Search($term: String!) { searchIssues(term: $term, first: 5) { nodes { identifier title } } }";
+const COLUMN_QUERY: &str = "query Column($state: String!, $after: String) { issues(filter: { state: { name: { eq: $state } } }, first: 250, after: $after) { nodes { identifier title url state { name } } pageInfo { hasNextPage endCursor } } }";
+const MOVE_MUTATION: &str = "mutation Move($id: String!, $state: String!) { issueUpdate(id: $id, input: { stateId: $state }) { success } }";
+const STATES_QUERY: &str =
+    "query States($id: String!) { issue(id: $id) { team { states { nodes { id name } } } } }";
+const COMPLETED_STATE_QUERY: &str = "query State($id: String!) { issue(id: $id) { team { states(filter: { type: { eq: \"completed\" } }, first: 1) { nodes { id } } } } }";
+const USERS_QUERY: &str = "query Users($after: String) { users(first: 250, after: $after) { nodes { id email } pageInfo { hasNextPage endCursor } } }";
+const TEAMS_QUERY: &str = "query Teams($after: String) { teams(first: 250, after: $after) { nodes { key } pageInfo { hasNextPage endCursor } } }";
+const VIEWER_QUERY: &str = "query { viewer { id } }";
+
+#[derive(Debug)]
+pub struct Linear {
+    client: reqwest::Client,
+    base_path: String,
+    api_key: String,
+    /// Keys of the workspace teams, the prefixes of issue identifiers. Loaded
+    /// by [`KanbanizeApi::check`], any prefix is accepted until then.
+    teams: Mutex<Option<Vec<String>>>,
+}
+
+impl Linear {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(base_path: &str, api_key: &str) -> Box<dyn KanbanizeApi> {
+        Box::new(Linear {
+            client: reqwest::Client::new(),
+            base_path: base_path.trim_end_matches('/').to_string(),
+            api_key: api_key.to_string(),
+            teams: Mutex::default(),
+        })
+    }
+
+    /// Runs a GraphQL query and returns its `data` object.
+    async fn query(&self, query: &str, variables: serde_json::Value) -> Result<serde_json::Value> {
+        let data = serde_json::json!({
+            "query": query,
+            "variables": variables,
+        });
+
+        let result: serde_json::Value = self
+            .client
+            .post(format!("{}/graphql", self.base_path))
+            .header("Authorization", &self.api_key)
+            .json(&data)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        tracing::debug!("Response: {:?}", Sensitive(&result));
+
+        if let Some(message) = result["errors"][0]["message"].as_str() {
+            return Err(message.into());
+        }
+        Ok(result["data"].clone())
+    }
+
+    /// Runs a query over a paginated connection and returns the nodes of
+    /// every page. The query takes the cursor as `$after`.
+    async fn nodes(
+        &self,
+        query: &str,
+        mut variables: serde_json::Value,
+        connection: &str,
+    ) -> Result<Vec<serde_json::Value>> {
+        let mut nodes = Vec::new();
+        loop {
+            let data = self.query(query, variables.clone()).await?;
+            let page = &data[connection];
+            nodes.extend(page["nodes"].as_array().into_iter().flatten().cloned());
+            match page["pageInfo"]["endCursor"].as_str() {
+                Some(cursor) if page["pageInfo"]["hasNextPage"].as_bool() == Some(true) => {
+                    variables["after"] = cursor.into();
+                }
+                _ => return Ok(nodes),
+            }
+        }
+    }
+
+    async fn set_state(&self, id: &str, state_id: &str) -> Result<()> {
+        let data = self
+            .query(
+                MOVE_MUTATION,
+                serde_json::json!({ "id": id, "state": state_id }),
+            )
+            .await?;
+        if data["issueUpdate"]["success"].as_bool() != Some(true) {
+            return Err(format!("could not move issue {}", id).into());
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl KanbanizeApi for Linear {
+    fn card_id_from_branch(&self, branch: &str) -> Option<String> {
+        // Linear suggests branches like `user/eng-42-short-title`
+        static IDENTIFIER: OnceLock<Regex> = OnceLock::new();
+        let re = IDENTIFIER
+            .get_or_init(|| Regex::new(r"(?i)(?:^|/)([a-z][a-z0-9]{0,9})-(\d+)").unwrap());
+        // Words like `feature-42` are not issues unless a team has that key
+        let teams = self.teams.lock().unwrap();
+        re.captures_iter(branch)
+            .find(|cap| {
+                teams
+                    .as_ref()
+                    .is_none_or(|teams| teams.iter().any(|key| key.eq_ignore_ascii_case(&cap[1])))
+            })
+            .map(|cap| format!("{}-{}", cap[1].to_uppercase(), &cap[2]))
+    }
+
+    fn branch_pattern(&self) -> &'static str {
+        "the issue identifier, such as `jane/eng-42-fix-login`"
+    }
+
+    async fn find_by_id(&self, id: &str) -> Result<Card> {
+        let data = self
+            .query(ISSUE_QUERY, serde_json::json!({ "id": id }))
+            .await?;
+
+        let issue = data["issue"].as_object().ok_or("issue not found")?;
+        let field = |name: &str| {
+            issue
+                .get(name)
+                .and_then(|d| d.as_str())
+                .unwrap_or_default()
+                .to_string()
+        };
+        Ok(Card {
+            id: id.to_string(),
+            custom_id: Some(field("identifier")).filter(|id| !id.is_empty()),
+            title: field("title"),
+            description: field("description"),
+            url: Some(field("url")).filter(|url| !url.is_empty()),
+            revision: Some(field("updatedAt")).filter(|revision| !revision.is_empty()),
+            markup: Markup::Markdown,
+            // Linear uses 0 for issues without priority
+            priority: issue
+                .get("priority")
+                .and_then(|p| p.as_u64())
+                .filter(|p| *p > 0)
+                .map(|p| p.to_string()),
+            tags: data["issue"]["labels"]["nodes"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|label| label["name"].as_str())
+                .map(ToString::to_string)
+                .collect(),
+            column: data["issue"]["state"]["name"]
+                .as_str()
+                .map(ToString::to_string),
+            owner: user(&data["issue"]["assignee"]),
+            // Children are already listed as subtasks
+            links: data["issue"]["parent"]["identifier"]
+                .as_str()
+                .map(|identifier| Link {
+                    card_id: identifier.to_string(),
+                    relation: Relation::Parent,
+                })
+                .into_iter()
+                .collect(),
+            watchers: data["issue"]["subscribers"]["nodes"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(user)
+                .collect(),
+            subtasks: data["issue"]["children"]["nodes"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|child| {
+                    Some(Subtask {
+                        id: child["id"].as_str()?.to_string(),
+                        description: child["title"].as_str().unwrap_or_default().to_string(),
+                        finished: !child["completedAt"].is_null(),
+                    })
+                })
+                .collect(),
+            ..Default::default()
+        })
+    }
+
+    async fn find_many(&self, ids: &[String]) -> Result<HashMap<String, Card>> {
+        kanbanize::find_each(self, ids).await
+    }
+
+    async fn revision(&self, id: &str) -> Result<Option<String>> {
+        let data = self
+            .query(REVISION_QUERY, serde_json::json!({ "id": id }))
+            .await?;
+        let issue = data["issue"].as_object().ok_or("issue not found")?;
+        Ok(issue
+            .get("updatedAt")
+            .and_then(|updated| updated.as_str())
+            .map(ToString::to_string))
+    }
+
+    async fn revisions(&self, ids: &[String]) -> Result<HashMap<String, String>> {
+        kanbanize::revision_each(self, ids).await
+    }
+
+    async fn search(&self, text: &str) -> Result<Vec<Card>> {
+        let data = self
+            .query(SEARCH_QUERY, serde_json::json!({ "term": text }))
+            .await?;
+        Ok(data["searchIssues"]["nodes"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|issue| {
+                let identifier = issue["identifier"].as_str()?.to_string();
+                Some(Card {
+                    id: identifier.clone(),
+                    custom_id: Some(identifier),
+                    title: issue["title"].as_str().unwrap_or_default().to_string(),
+                    ..Default::default()
+                })
+            })
+            .collect())
+    }
+
+    async fn cards_in_column(&self, column: &str) -> Result<Vec<Card>> {
+        let issues = self
+            .nodes(
+                COLUMN_QUERY,
+                serde_json::json!({ "state": column }),
+                "issues",
+            )
+            .await?;
+        Ok(issues
+            .iter()
+            .filter_map(|issue| {
+                let identifier = issue["identifier"].as_str()?.to_string();
+                Some(Card {
+                    id: identifier.clone(),
+                    custom_id: Some(identifier),
+                    title: issue["title"].as_str().unwrap_or_default().to_string(),
+                    url: issue["url"].as_str().map(ToString::to_string),
+                    column: issue["state"]["name"].as_str().map(ToString::to_string),
+                    ..Default::default()
+                })
+            })
+            .collect())
+    }
+
+    async fn move_card(&self, id: &str, column: &str) -> Result<()> {
+        // States are named like the column of the cards, the mutation takes
+        // the ID of a state of the issue team
+        let data = self
+            .query(STATES_QUERY, serde_json::json!({ "id": id }))
+            .await?;
+        let state = data["issue"]["team"]["states"]["nodes"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find(|state| state["name"] == column || state["id"] == column)
+            .and_then(|state| state["id"].as_str())
+            .ok_or_else(|| format!("no state `{}` in the team of issue {}", column, id))?;
+        self.set_state(id, state).await
+    }
+
+    async fn complete_subtask(&self, _card_id: &str, subtask_id: &str) -> Result<()> {
+        // Sub-issues are completed by moving them to the first completed
+        // state of their team
+        let data = self
+            .query(
+                COMPLETED_STATE_QUERY,
+                serde_json::json!({ "id": subtask_id }),
+            )
+            .await?;
+        let state = data["issue"]["team"]["states"]["nodes"][0]["id"]
+            .as_str()
+            .ok_or_else(|| format!("no completed state for issue {}", subtask_id))?;
+        self.set_state(subtask_id, state).await
+    }
+
+    fn is_attachment(&self, url: &Url) -> bool {
+        url.host_str() == Some("uploads.linear.app")
+    }
+
+    async fn download(&self, url: &Url) -> Result<Vec<u8>> {
+        let response = self
+            .client
+            .get(url.clone())
+            .header("Authorization", &self.api_key)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(response.bytes().await?.to_vec())
+    }
+
+    async fn users(&self) -> Result<Vec<User>> {
+        let users = self
+            .nodes(USERS_QUERY, serde_json::json!({}), "users")
+            .await?;
+        Ok(users.iter().filter_map(user).collect())
+    }
+
+    async fn check(&self) -> Result<()> {
+        self.query(VIEWER_QUERY, serde_json::json!({})).await?;
+        let teams = self
+            .nodes(TEAMS_QUERY, serde_json::json!({}), "teams")
+            .await?;
+        *self.teams.lock().unwrap() = Some(
+            teams
+                .iter()
+                .filter_map(|team| team["key"].as_str())
+                .map(str::to_uppercase)
+                .collect(),
+        );
+        Ok(())
+    }
+}
+
+fn user(value: &serde_json::Value) -> Option<User> {
+    Some(User {
+        id: value["id"].as_str()?.to_string(),
+        email: value["email"].as_str().map(ToString::to_string),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_find_issue() -> testresult::TestResult {
+        let mut server = mockito::Server::new_async().await;
+
+        let mock = server
+            .mock("POST", "/graphql")
+            .match_header("authorization", "api_key")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "variables": { "id": "ENG-42" },
+            })))
+            .with_status(200)
+            .with_body(
+                r#"{"data": {"issue": {"identifier": "ENG-42", "url": "https://linear.app/acme/issue/ENG-42", "updatedAt": "2023-10-01T10:00:00.000Z", "title": "Fix login", "description": "Issue **description**", "priority": 2, "labels": {"nodes": [{"name": "Bug"}]}, "state": {"name": "In Progress"}, "assignee": {"id": "u1", "email": "jane@example.com"}, "subscribers": {"nodes": []}, "parent": {"identifier": "ENG-40"}}}}"#,
+            )
+            .create_async()
+            .await;
+
+        let client = Linear::new(server.url().as_str(), "api_key");
+        let card = client.find_by_id("ENG-42").await?;
+
+        assert_eq!(card.custom_id, Some("ENG-42".to_string()));
+        assert_eq!(card.revision, Some("2023-10-01T10:00:00.000Z".to_string()));
+        assert_eq!(
+            card.links,
+            vec![Link {
+                card_id: "ENG-40".to_string(),
+                relation: Relation::Parent
+            }]
+        );
+        assert_eq!(card.title, "Fix login");
+        assert_eq!(
+            card.url,
+            Some("https://linear.app/acme/issue/ENG-42".to_string())
+        );
+        assert_eq!(card.priority, Some("2".to_string()));
+        assert_eq!(card.tags, vec!["Bug".to_string()]);
+        assert_eq!(card.column, Some("In Progress".to_string()));
+        assert_eq!(
+            card.owner,
+            Some(User {
+                id: "u1".to_string(),
+                email: Some("jane@example.com".to_string()),
+            })
+        );
+        assert_eq!(card.description, "Issue **description**".to_string());
+        assert_eq!(card.markup, Markup::Markdown);
+
+        mock.assert_async().await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_move_issue() -> testresult::TestResult {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/graphql")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "query": STATES_QUERY,
+                "variables": { "id": "ENG-42" },
+            })))
+            .with_status(200)
+            .with_body(
+                r#"{"data": {"issue": {"team": {"states": {"nodes": [{"id": "s1", "name": "In Progress"}, {"id": "s2", "name": "In Review"}]}}}}}"#,
+            )
+            .create_async()
+            .await;
+        let mutation = server
+            .mock("POST", "/graphql")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "query": MOVE_MUTATION,
+                "variables": { "id": "ENG-42", "state": "s2" },
+            })))
+            .with_status(200)
+            .with_body(r#"{"data": {"issueUpdate": {"success": true}}}"#)
+            .create_async()
+            .await;
+
+        let client = Linear::new(server.url().as_str(), "api_key");
+        client.move_card("ENG-42", "In Review").await?;
+        assert!(client.move_card("ENG-42", "Done").await.is_err());
+
+        mutation.assert_async().await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_find_issue_not_found() -> testresult::TestResult {
+        let mut server = mockito::Server::new_async().await;
+
+        let mock = server
+            .mock("POST", "/graphql")
+            .match_header("authorization", "api_key")
+            .with_status(200)
+            .with_body(r#"{"data": null, "errors": [{"message": "Entity not found: Issue"}]}"#)
+            .create_async()
+            .await;
+
+        let client = Linear::new(server.url().as_str(), "api_key");
+        let card = client.find_by_id("ENG-42").await;
+        assert_eq!(
+            card.err().unwrap().to_string(),
+            "Entity not found: Issue".to_string()
+        );
+
+        mock.assert_async().await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_check() -> testresult::TestResult {
+        let mut server = mockito::Server::new_async().await;
+
+        let mock = server
+            .mock("POST", "/graphql")
+            .match_header("authorization", "api_key")
+            .match_body(mockito::Matcher::PartialJson(
+                serde_json::json!({ "query": VIEWER_QUERY }),
+            ))
+            .with_status(200)
+            .with_body(r#"{"data": {"viewer": {"id": "1"}}}"#)
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/graphql")
+            .match_body(mockito::Matcher::PartialJson(
+                serde_json::json!({ "query": TEAMS_QUERY, "variables": {} }),
+            ))
+            .with_status(200)
+            .with_body(r#"{"data": {"teams": {"nodes": [{"key": "ENG"}], "pageInfo": {"hasNextPage": true, "endCursor": "c1"}}}}"#)
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/graphql")
+            .match_body(mockito::Matcher::PartialJson(
+                serde_json::json!({ "query": TEAMS_QUERY, "variables": { "after": "c1" } }),
+            ))
+            .with_status(200)
+            .with_body(r#"{"data": {"teams": {"nodes": [{"key": "Ops2"}], "pageInfo": {"hasNextPage": false, "endCursor": "c2"}}}}"#)
+            .create_async()
+            .await;
+
+        let client = Linear::new(server.url().as_str(), "api_key");
+        client.check().await?;
+
+        // Only the keys of the teams are issue prefixes now
+        assert_eq!(client.card_id_from_branch("feature-42-login"), None);
+        assert_eq!(client.card_id_from_branch("release-2"), None);
+        assert_eq!(
+            client.card_id_from_branch("feature/eng-42-login"),
+            Some("ENG-42".to_string())
+        );
+        assert_eq!(
+            client.card_id_from_branch("ops2-7"),
+            Some("OPS2-7".to_string())
+        );
+
+        mock.assert_async().await;
+        Ok(())
+    }
+
+    #[test]
+    fn test_card_id_from_branch() {
+        let client = Linear::new("http://localhost", "api_key");
+        assert_eq!(
+            client.card_id_from_branch("user/eng-42-fix-login"),
+            Some("ENG-42".to_string())
+        );
+        assert_eq!(
+            client.card_id_from_branch("ENG-42"),
+            Some("ENG-42".to_string())
+        );
+        assert_eq!(client.card_id_from_branch("main"), None);
+    }
+
+    #[tokio::test]
+    async fn test_users() -> testresult::TestResult {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/graphql")
+            .match_body(mockito::Matcher::PartialJson(
+                serde_json::json!({ "query": USERS_QUERY, "variables": {} }),
+            ))
+            .with_status(200)
+            .with_body(r#"{"data": {"users": {"nodes": [{"id": "u1", "email": "jane@example.com"}], "pageInfo": {"hasNextPage": true, "endCursor": "c1"}}}}"#)
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/graphql")
+            .match_body(mockito::Matcher::PartialJson(
+                serde_json::json!({ "query": USERS_QUERY, "variables": { "after": "c1" } }),
+            ))
+            .with_status(200)
+            .with_body(r#"{"data": {"users": {"nodes": [{"id": "u2", "email": null}], "pageInfo": {"hasNextPage": false, "endCursor": null}}}}"#)
+            .create_async()
+            .await;
+
+        let client = Linear::new(server.url().as_str(), "api_key");
+        let users = client.users().await?;
+        assert_eq!(
+            users
+                .iter()
+                .map(|user| user.id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["u1", "u2"]
+        );
+        Ok(())
+    }
+}
@@ -1,56 +1,339 @@
-use std::time::Duration;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio_util::sync::CancellationToken;
+use tracing::{field::Empty, Instrument};
+use url::Url;
 
 use crate::{
-    github::{GithubApi, Issue, PullRequest},
-    kanbanize::{Card, KanbanizeApi},
+    attachments,
+    audit::{AuditLog, Entry},
+    config::{repository_config, DraftMode, RepositoryConfig},
+    error::status_of,
+    fields::{self, FieldConfig},
+    github::{
+        Comment, CommitStatus, GithubApi, Issue, PullRequest, PullRequestUpdate, StatusState,
+    },
+    kanbanize::{Card, KanbanizeApi, Markup},
+    labels::{has_label, LabelChanges},
+    markdown,
+    metrics::Metrics,
+    redact::Sensitive,
+    related::{self, Related},
+    report::Orphan,
+    router::Router,
+    subtasks,
+    users::{PeopleConfig, Resolver, Role, UserMapping},
 };
 
+/// Time between two processing cycles.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
 pub struct Service {
     github: Box<dyn GithubApi>,
-    kanbanize: Box<dyn KanbanizeApi>,
+    trackers: Router,
     owner_whitelist: String,
+    metrics: Arc<Metrics>,
+    repositories: Vec<RepositoryConfig>,
+    users: UserMapping,
+    /// Bodies GitHub refused for each pull request, not sent again until the
+    /// card changes.
+    rejected: Mutex<HashMap<Url, String>>,
+    /// Card users and their roles last applied to each pull request, applied
+    /// again only when they change.
+    people: Mutex<HashMap<Url, Vec<(String, Role)>>>,
+    /// Unresolved card comment of each pull request whose comments were
+    /// listed, `None` if it has none, so they are only listed again when
+    /// unknown.
+    unresolved_comments: Mutex<HashMap<Url, Option<Comment>>>,
+    /// Whether each pull request was last seen as a draft. Stays `true` once
+    /// it is ready for review, until its card is moved.
+    was_draft: Mutex<HashMap<Url, bool>>,
+    audit: Option<AuditLog>,
 }
 
 impl Service {
-    pub fn new(
-        github: Box<dyn GithubApi>,
-        kanbanize: Box<dyn KanbanizeApi>,
-        owner_whitelist: String,
-    ) -> Self {
+    pub fn new(github: Box<dyn GithubApi>, trackers: Router, owner_whitelist: String) -> Self {
         Self {
             github,
-            kanbanize,
+            trackers,
             owner_whitelist,
+            metrics: Arc::new(Metrics::default()),
+            repositories: Vec::new(),
+            users: UserMapping::default(),
+            rejected: Mutex::default(),
+            people: Mutex::default(),
+            unresolved_comments: Mutex::default(),
+            was_draft: Mutex::default(),
+            audit: None,
+        }
+    }
+
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    pub fn with_repositories(mut self, repositories: Vec<RepositoryConfig>) -> Self {
+        self.repositories = repositories;
+        self
+    }
+
+    pub fn with_users(mut self, users: UserMapping) -> Self {
+        self.users = users;
+        self
+    }
+
+    pub fn with_audit(mut self, audit: AuditLog) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// Verifies GitHub and every tracker are reachable with the configured credentials.
+    pub async fn check(&self) -> Result<(), String> {
+        let errors: Vec<String> = self
+            .checks()
+            .await
+            .into_iter()
+            .filter_map(|(name, result)| result.err().map(|e| format!("{}: {}", name, e)))
+            .collect();
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join(", "))
+        }
+    }
+
+    /// Checks GitHub and every tracker, returning the result of each by name.
+    pub async fn checks(&self) -> Vec<(String, Result<(), String>)> {
+        let mut results = vec![(
+            "github".to_string(),
+            self.github.check().await.map_err(|e| e.to_string()),
+        )];
+        let mut trackers: Vec<_> = self.trackers.iter().collect();
+        trackers.sort_by_key(|(name, _)| *name);
+        for (name, tracker) in trackers {
+            results.push((
+                name.to_string(),
+                tracker.check().await.map_err(|e| e.to_string()),
+            ));
+        }
+        results
+    }
+
+    /// Cards in the `review_columns` of each tracker, by tracker name, that
+    /// no open pull request references by its branch.
+    pub async fn orphans(
+        &self,
+        review_columns: &HashMap<String, Vec<String>>,
+    ) -> Result<Vec<Orphan>, Box<dyn std::error::Error>> {
+        let mut linked = HashSet::new();
+        for issue in self.github.get_issues().await? {
+            let Some(url) = issue.pull_request_url else {
+                continue;
+            };
+            let pull = self.github.get_pull_from_url(&url).await?;
+            let Some((name, tracker)) = self
+                .trackers
+                .resolve(&pull.repository, &pull.head_reference)
+            else {
+                continue;
+            };
+            if let Some(id) = tracker.card_id_from_branch(&pull.head_reference) {
+                linked.insert((name.to_string(), id));
+            }
+        }
+
+        let mut names: Vec<_> = review_columns.keys().collect();
+        names.sort();
+        let mut orphans = Vec::new();
+        for name in names {
+            let tracker = self
+                .trackers
+                .get(name)
+                .ok_or_else(|| format!("Unknown tracker `{}`", name))?;
+            for column in &review_columns[name] {
+                for card in tracker.cards_in_column(column).await? {
+                    if !linked.contains(&(name.clone(), card.id.clone())) {
+                        orphans.push(Orphan::new(name, card));
+                    }
+                }
+            }
+        }
+        Ok(orphans)
+    }
+
+    /// Restores the body the pull request at `url` had before the change
+    /// numbered `entry` in its audit history, the latest one by default.
+    /// Refuses when the body was edited since the latest recorded change,
+    /// unless `force` is set. The pull request is then left alone by the
+    /// polling cycles.
+    pub async fn revert(
+        &self,
+        url: &str,
+        entry: Option<usize>,
+        force: bool,
+    ) -> Result<Entry, Box<dyn std::error::Error>> {
+        let audit = self.audit.as_ref().ok_or("No audit log configured")?;
+        let entries = audit.history(url)?;
+        let latest = entries
+            .last()
+            .ok_or_else(|| format!("No recorded change for {}", url))?;
+        let number = entry.unwrap_or(entries.len());
+        let target = number
+            .checked_sub(1)
+            .and_then(|index| entries.get(index))
+            .ok_or_else(|| format!("No change #{} for {}, see `gitkban history`", number, url))?;
+
+        let current = self
+            .github
+            .get_pull_from_url(&target.pull_request)
+            .await?
+            .body;
+        if current != latest.body && !force {
+            return Err(format!(
+                "The body of {} was edited since the latest recorded change, pass --force to revert anyway",
+                url
+            )
+            .into());
         }
+
+        let update = PullRequestUpdate {
+            body: Some(target.previous_body.clone()),
+            ..Default::default()
+        };
+        self.github
+            .update_pull_request(&target.pull_request, update)
+            .await?;
+        let reverted = Entry {
+            card_id: target.card_id.clone(),
+            revision: target.revision.clone(),
+            reverts: Some(number),
+            ..Entry::new(&target.pull_request, None, &current, &target.previous_body)
+        };
+        audit.append(&reverted)?;
+        Ok(reverted)
     }
 
-    pub async fn run(&self) {
-        let mut interval = tokio::time::interval(Duration::from_secs(5 * 60));
+    /// Processes pull requests every [`POLL_INTERVAL`] until `shutdown` is cancelled.
+    pub async fn run(&self, shutdown: CancellationToken) {
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
         loop {
-            interval.tick().await;
+            tokio::select! {
+                biased;
+                _ = shutdown.cancelled() => break,
+                _ = interval.tick() => {}
+            }
             tracing::info!("Checking for new PR's");
-            if let Err(e) = self.process().await {
+            let start = Instant::now();
+            let result = self
+                .process(&shutdown)
+                .instrument(tracing::info_span!("cycle"))
+                .await;
+            if let Err(e) = &result {
                 tracing::error!("Error processing issues: {}", e)
             };
+            self.metrics.cycle_finished(start.elapsed(), result.is_ok());
         }
     }
 
-    async fn process(&self) -> Result<(), Box<dyn std::error::Error>> {
+    /// Processes every open pull request. Once `shutdown` is cancelled, the
+    /// pull request in progress is finished and the remaining ones are left
+    /// for the next start.
+    async fn process(
+        &self,
+        shutdown: &CancellationToken,
+    ) -> Result<(), Box<dyn std::error::Error>> {
         let issues = self.github.get_issues().await?;
-        for issue in issues.iter() {
-            tracing::debug!("Received issue {:?}", issue);
-            if let Err(e) = self.process_issue(&issue).await {
-                tracing::error!("Error processing issue {}: {}", issue.url, e);
+        let reverted = match self.audit.as_ref().map(AuditLog::reverted) {
+            Some(Ok(reverted)) => reverted,
+            Some(Err(e)) => {
+                tracing::warn!("Could not read the reverted pull requests: {}", e);
+                HashSet::new()
+            }
+            None => HashSet::new(),
+        };
+        // Pull requests are inspected first, so their cards can be fetched
+        // together
+        let mut pending = Vec::new();
+        for (i, issue) in issues.iter().enumerate() {
+            if shutdown.is_cancelled() {
+                tracing::info!(
+                    "Shutting down, skipping {} remaining issues",
+                    issues.len() - i
+                );
+                return Ok(());
+            }
+            let span = tracing::info_span!(
+                "pull_request",
+                issue = %issue.url,
+                repo = Empty,
+                pr = Empty,
+                branch = Empty,
+                tracker = Empty,
+                card_id = Empty,
+                decision = Empty,
+            );
+            span.in_scope(|| tracing::debug!("Received issue {:?}", Sensitive(issue)));
+            self.metrics.pull_scanned();
+            match self
+                .inspect_issue(issue, &reverted)
+                .instrument(span.clone())
+                .await
+            {
+                Ok(Some(pull)) => pending.push((issue, pull, span)),
+                Ok(None) => {}
+                Err(e) => self.failed(&span, issue, e.as_ref()),
+            }
+        }
+
+        let pulls: Vec<&PullRequest> = pending.iter().map(|(_, pull, _)| pull).collect();
+        let cards = self.prefetch_cards(&pulls).await;
+        for (i, (issue, pull, span)) in pending.iter().enumerate() {
+            if shutdown.is_cancelled() {
+                tracing::info!(
+                    "Shutting down, skipping {} remaining pull requests",
+                    pending.len() - i
+                );
+                break;
+            }
+            let result = self
+                .process_pull(issue, pull, &cards)
+                .instrument(span.clone())
+                .await;
+            if let Err(e) = result {
+                self.failed(span, issue, e.as_ref());
             }
         }
 
         Ok(())
     }
 
-    async fn process_issue(&self, issue: &Issue) -> Result<(), Box<dyn std::error::Error>> {
-        // Don't do anything if the issue has a body
-        if issue.body != String::default() {
-            return Ok(());
+    fn failed(&self, span: &tracing::Span, issue: &Issue, error: &dyn std::error::Error) {
+        span.record("decision", "failed");
+        span.in_scope(|| tracing::error!("Error processing issue {}: {}", issue.url, error));
+        self.metrics.pull_failed();
+    }
+
+    /// Fetches the pull request of `issue`, unless it is to be skipped.
+    /// Pull requests in `reverted` had a change undone with `gitkban revert`.
+    async fn inspect_issue(
+        &self,
+        issue: &Issue,
+        reverted: &HashSet<Url>,
+    ) -> Result<Option<PullRequest>, Box<dyn std::error::Error>> {
+        let has_body = has_body(issue);
+        let inspecting = self
+            .repositories
+            .iter()
+            .any(RepositoryConfig::inspects_pulls_with_body);
+        if has_body && !inspecting {
+            self.skip("has_body");
+            return Ok(None);
         }
 
         let pull = self
@@ -62,55 +345,797 @@ impl Service {
                     .ok_or("issue is not a pull request")?,
             )
             .await?;
+        let span = tracing::Span::current();
+        span.record("repo", pull.repository.as_str());
+        span.record("pr", pull.number);
+        span.record("branch", pull.head_reference.as_str());
 
         // Repository must be private
         if !pull.is_private {
-            return Ok(());
+            self.skip("public");
+            return Ok(None);
         }
 
         if pull.repository_owner != self.owner_whitelist {
-            return Ok(());
+            self.skip("owner");
+            return Ok(None);
         }
 
-        // Try to grab the ID from the branch
-        let re = regex::Regex::new(r"\d+(\.\d+)?").unwrap();
-        if let Some(cap) = re.captures(pull.head_reference.as_str()) {
-            if let Some(id) = cap.get(0) {
-                let card_id = id.as_str().parse()?;
-                // Update issue body with card ID
-                self.process_issue_with_card_id(&pull, card_id).await?;
+        if reverted.contains(&pull.url) {
+            self.skip("reverted");
+            return Ok(None);
+        }
+
+        let config = repository_config(&self.repositories, &pull.repository);
+        if has_body && !config.is_some_and(RepositoryConfig::inspects_pulls_with_body) {
+            self.skip("has_body");
+            return Ok(None);
+        }
+
+        Ok(Some(pull))
+    }
+
+    /// Fetches the cards of `pulls` with one request per tracker. Trackers
+    /// with a single card, and the cards of a failed request, are left to
+    /// [`Self::resolve_card`].
+    async fn prefetch_cards(&self, pulls: &[&PullRequest]) -> HashMap<(String, String), Card> {
+        let mut cards = HashMap::new();
+        if pulls.len() < 2 {
+            return cards;
+        }
+        let mut ids: HashMap<&str, (&dyn KanbanizeApi, Vec<String>)> = HashMap::new();
+        for pull in pulls {
+            let Some((name, tracker)) = self
+                .trackers
+                .resolve(&pull.repository, &pull.head_reference)
+            else {
+                continue;
+            };
+            if let Some(card_id) = tracker.card_id_from_branch(&pull.head_reference) {
+                let (_, tracker_ids) = ids.entry(name).or_insert((tracker, Vec::new()));
+                if !tracker_ids.contains(&card_id) {
+                    tracker_ids.push(card_id);
+                }
             }
         }
 
+        for (name, (tracker, ids)) in ids {
+            if ids.len() < 2 {
+                continue;
+            }
+            match tracker.find_many(&ids).await {
+                Ok(found) => cards.extend(
+                    found
+                        .into_iter()
+                        .map(|(id, card)| ((name.to_string(), id), card)),
+                ),
+                Err(e) => tracing::warn!("Could not fetch the cards of `{}`: {}", name, e),
+            }
+        }
+        cards
+    }
+
+    async fn process_pull(
+        &self,
+        issue: &Issue,
+        pull: &PullRequest,
+        cards: &HashMap<(String, String), Card>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let span = tracing::Span::current();
+        let has_body = has_body(issue);
+        let config = repository_config(&self.repositories, &pull.repository);
+        let drafts = config.map_or(DraftMode::default(), |config| config.drafts);
+        let link = self.resolve_card(pull, cards).await?;
+        if let Some(config) = config.filter(|config| config.enforce_card_link) {
+            // The status is informative, the body is synced regardless
+            let status = card_link_status(&link, config);
+            if let Err(e) = self.sync_commit_status(pull, status).await {
+                tracing::warn!("Could not report the card link of {}: {}", pull.url, e);
+            }
+        }
+        if config.is_some_and(|config| config.comment_unresolved) {
+            self.sync_unresolved_comment(pull, &link).await?;
+        }
+
+        // Card attributes follow the card on every cycle, even once the pull
+        // request has a body. Drafts held back wait like their body.
+        let held_back = pull.draft && drafts != DraftMode::Sync;
+        let ready = self.became_ready(issue, pull);
+        let attributes = match (&link, config) {
+            (CardLink::Linked { tracker, card }, Some(config)) if !held_back => self
+                .sync_attributes(*tracker, pull, card, config, ready)
+                .await
+                .map_err(|e| e.to_string()),
+            _ => Ok(()),
+        };
+
+        match link {
+            CardLink::Unresolved { reason, .. } => self.skip(reason),
+            CardLink::Linked { tracker, card }
+                if has_body && config.is_some_and(|config| config.sync_subtasks) =>
+            {
+                if self
+                    .sync_subtasks(tracker, pull, &issue.body, &card)
+                    .await?
+                {
+                    span.record("decision", "updated");
+                    self.metrics.pull_updated();
+                } else {
+                    self.skip("has_body");
+                }
+            }
+            CardLink::Linked { .. } if has_body => self.skip("has_body"),
+            CardLink::Linked { card, .. } if pull.draft && drafts == DraftMode::Minimal => {
+                tracing::info!("Linking the card of draft {}", pull.url);
+                let update = PullRequestUpdate {
+                    body: Some(draft_body(&card)),
+                    ..Default::default()
+                };
+                self.update_pull(pull, &issue.body, Some(&card), update)
+                    .await?;
+                span.record("decision", "updated");
+                self.metrics.pull_updated();
+            }
+            CardLink::Linked { .. } if pull.draft && drafts == DraftMode::Skip => {
+                self.skip("draft")
+            }
+            CardLink::Linked { tracker, card } => {
+                // Update issue body with the card
+                if self
+                    .update_from_card(tracker, pull, &issue.body, *card)
+                    .await?
+                {
+                    span.record("decision", "updated");
+                    self.metrics.pull_updated();
+                }
+            }
+        }
+
+        attributes.map_err(Into::into)
+    }
+
+    /// Whether `pull` was marked ready for review and its card not moved
+    /// yet: it was seen as a draft, or still has the body given to drafts.
+    fn became_ready(&self, issue: &Issue, pull: &PullRequest) -> bool {
+        let mut was_draft = self.was_draft.lock().unwrap();
+        let pending =
+            was_draft.get(&pull.url) == Some(&true) || issue.body.starts_with(DRAFT_MARKER);
+        was_draft.insert(pull.url.clone(), pull.draft || pending);
+        !pull.draft && pending
+    }
+
+    /// Moves `card` to the ready column once `pull` is `ready` for review,
+    /// then applies the labels and people of `card` to `pull`.
+    async fn sync_attributes(
+        &self,
+        tracker: &dyn KanbanizeApi,
+        pull: &PullRequest,
+        card: &Card,
+        config: &RepositoryConfig,
+        ready: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if ready {
+            if let Some(column) = &config.ready_column {
+                let in_column = [&card.column, &card.column_name]
+                    .into_iter()
+                    .flatten()
+                    .any(|current| current == column);
+                if !in_column {
+                    tracing::info!("Moving card {} to `{}`", card.id, column);
+                    tracker.move_card(&card.id, column).await?;
+                }
+            }
+            // Failed moves are retried on the next cycle
+            self.was_draft
+                .lock()
+                .unwrap()
+                .insert(pull.url.clone(), false);
+        }
+        if let Some(mapping) = &config.labels {
+            self.apply_labels(pull, mapping.changes(card, &pull.labels))
+                .await?;
+        }
+        if let Some(people) = &config.people {
+            self.apply_people(tracker, pull, card, people).await?;
+        }
+        Ok(())
+    }
+
+    /// Finds the card referenced by the branch of `pull`, among the `cards`
+    /// fetched beforehand if possible.
+    async fn resolve_card(
+        &self,
+        pull: &PullRequest,
+        cards: &HashMap<(String, String), Card>,
+    ) -> Result<CardLink<'_>, Box<dyn std::error::Error>> {
+        let span = tracing::Span::current();
+        let Some((name, tracker)) = self
+            .trackers
+            .resolve(&pull.repository, &pull.head_reference)
+        else {
+            return Ok(CardLink::Unresolved {
+                tracker: None,
+                reason: "no_tracker",
+                summary: "No tracker is configured for this repository and branch".to_string(),
+            });
+        };
+        span.record("tracker", name);
+
+        // Try to grab the ID from the branch
+        let Some(card_id) = tracker.card_id_from_branch(&pull.head_reference) else {
+            return Ok(CardLink::Unresolved {
+                tracker: Some(tracker),
+                reason: "no_card_id",
+                summary: format!("Branch `{}` doesn't reference a card", pull.head_reference),
+            });
+        };
+        span.record("card_id", card_id.as_str());
+
+        let found = match cards.get(&(name.to_string(), card_id.clone())) {
+            Some(card) => Ok(card.clone()),
+            None => tracker.find_by_id(&card_id).await,
+        };
+        match found {
+            Ok(card) => Ok(CardLink::Linked {
+                tracker,
+                card: Box::new(card),
+            }),
+            Err(e) if status_of(e.as_ref()) == Some(404) => Ok(CardLink::Unresolved {
+                tracker: Some(tracker),
+                reason: "card_not_found",
+                summary: format!("Card {} doesn't exist", card_id),
+            }),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Posts `status` on the head of `pull`, unless it is already there.
+    /// GitHub keeps at most 1000 statuses per commit.
+    async fn sync_commit_status(
+        &self,
+        pull: &PullRequest,
+        status: CommitStatus,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let current = self.github.get_commit_status(pull, &status.context).await?;
+        if current.as_ref() == Some(&status) {
+            return Ok(());
+        }
+        self.github.set_commit_status(pull, status).await
+    }
+
+    /// Keeps a single comment on `pull` explaining why its card couldn't be
+    /// found in its tracker, editing it on later cycles and deleting it once
+    /// the card is found. Pull requests without a tracker get no comment.
+    async fn sync_unresolved_comment(
+        &self,
+        pull: &PullRequest,
+        link: &CardLink<'_>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let known = self
+            .unresolved_comments
+            .lock()
+            .unwrap()
+            .get(&pull.url)
+            .cloned();
+        let existing = match known {
+            Some(existing) => existing,
+            None => self
+                .github
+                .list_comments(pull)
+                .await?
+                .into_iter()
+                .find(|comment| comment.body.starts_with(UNRESOLVED_MARKER)),
+        };
+        let remember = |comment: Option<Comment>| {
+            self.unresolved_comments
+                .lock()
+                .unwrap()
+                .insert(pull.url.clone(), comment);
+        };
+
+        // Without a tracker there is no card for the author to fix
+        let (tracker, summary) = match (link, &existing) {
+            (
+                CardLink::Unresolved {
+                    tracker: Some(tracker),
+                    summary,
+                    ..
+                },
+                _,
+            ) => (*tracker, summary),
+            (_, None) => {
+                remember(None);
+                return Ok(());
+            }
+            (_, Some(comment)) => {
+                tracing::info!("Nothing to explain, deleting the comment on {}", pull.url);
+                self.github.delete_comment(pull, comment.id).await?;
+                remember(None);
+                return Ok(());
+            }
+        };
+
+        let candidates = tracker.search(&pull.title).await.unwrap_or_else(|e| {
+            tracing::warn!("Could not search cards for {}: {}", pull.url, e);
+            Vec::new()
+        });
+        let body = unresolved_comment(summary, tracker.branch_pattern(), &candidates);
+        match existing {
+            Some(comment) if comment.body == body => remember(Some(comment)),
+            Some(comment) => {
+                if let Err(e) = self.github.update_comment(pull, comment.id, &body).await {
+                    // The comment may have been deleted, look it up again next time
+                    self.unresolved_comments.lock().unwrap().remove(&pull.url);
+                    return Err(e);
+                }
+                remember(Some(Comment { body, ..comment }));
+            }
+            None => {
+                tracing::info!("Commenting on {} about its missing card", pull.url);
+                self.github.create_comment(pull, &body).await?;
+                // Its ID is unknown, the comments are listed again next time
+                self.unresolved_comments.lock().unwrap().remove(&pull.url);
+            }
+        }
         Ok(())
     }
 
-    async fn process_issue_with_card_id(
+    /// Finishes the subtasks ticked in the task list of `body`, then refreshes
+    /// the task list from the card. Returns whether anything changed.
+    async fn sync_subtasks(
+        &self,
+        tracker: &dyn KanbanizeApi,
+        pull: &PullRequest,
+        body: &str,
+        card: &Card,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let Some(checked) = subtasks::checked(body) else {
+            return Ok(false);
+        };
+
+        let mut changed = false;
+        let mut card_subtasks = card.subtasks.clone();
+        for subtask in card_subtasks
+            .iter_mut()
+            .filter(|subtask| !subtask.finished && checked.contains(&subtask.id))
+        {
+            tracing::info!("Finishing subtask {} of card {}", subtask.id, card.id);
+            tracker.complete_subtask(&card.id, &subtask.id).await?;
+            subtask.finished = true;
+            changed = true;
+        }
+
+        let refreshed = subtasks::replace(body, &card_subtasks);
+        if refreshed != body {
+            let update = PullRequestUpdate {
+                body: Some(refreshed),
+                ..Default::default()
+            };
+            self.update_pull(pull, body, Some(card), update).await?;
+            changed = true;
+        }
+        Ok(changed)
+    }
+
+    /// Updates a pull request, recording the change of its body in the audit
+    /// log. The update stands even if the log can't be written.
+    async fn update_pull(
         &self,
         pull: &PullRequest,
-        card_id: i32,
+        previous_body: &str,
+        card: Option<&Card>,
+        update: PullRequestUpdate,
     ) -> Result<(), Box<dyn std::error::Error>> {
+        let body = update.body.clone();
+        self.github.update_pull_request(&pull.url, update).await?;
+        let Some(audit) = &self.audit else {
+            return Ok(());
+        };
+        if let Some(body) = body.filter(|body| body != previous_body) {
+            let entry = Entry::new(&pull.url, card, previous_body, &body);
+            if let Err(e) = audit.append(&entry) {
+                tracing::error!("Could not record the update of {}: {}", pull.url, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Records why the current pull request was left untouched.
+    fn skip(&self, reason: &'static str) {
+        tracing::Span::current().record("decision", reason);
+        tracing::debug!("Skipping pull request: {}", reason);
+        self.metrics.pull_skipped(reason);
+    }
+
+    /// Updates `pull` from `card`. Returns `false` if GitHub rejects the
+    /// body, which is then skipped until the card changes.
+    async fn update_from_card(
+        &self,
+        tracker: &dyn KanbanizeApi,
+        pull: &PullRequest,
+        previous_body: &str,
+        card: Card,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
         tracing::info!("Updating pull request {}", pull.url);
-        // Get card
-        let card = self.kanbanize.find_by_id(card_id).await?;
-        let body = card_to_markdown(card);
+        let mut update = PullRequestUpdate::default();
+
+        // Replace the title GitHub generated from the branch, if enabled
+        let config = repository_config(&self.repositories, &pull.repository);
+        if let Some(format) = config.and_then(|config| config.title_format.as_deref()) {
+            if is_default_title(&pull.title, &pull.head_reference) {
+                update.title = Some(format_title(format, &card));
+            }
+        }
+        let description = description_to_markdown(&card);
+        let description = match config.map(|config| config.images) {
+            Some(mode) => {
+                attachments::rehost(
+                    &description,
+                    mode,
+                    tracker,
+                    self.github.as_ref(),
+                    &pull.repository,
+                    &card.id,
+                )
+                .await
+            }
+            None => description,
+        };
+        let related = match config.map_or(0, |config| config.related_depth) {
+            0 => Vec::new(),
+            depth => related::fetch(tracker, &card, depth).await,
+        };
+        let custom_fields = config.map_or(&[][..], |config| &config.custom_fields);
+        let body = card_body(&card, &description, custom_fields, &related);
+        if self.rejected.lock().unwrap().get(&pull.url) == Some(&body) {
+            self.skip("rejected");
+            return Ok(false);
+        }
+        update.body = Some(body.clone());
 
-        // Update Pull request
-        self.github.update_pull_request(&pull.url, body).await?;
+        // Update Pull request, validation errors would repeat on every cycle
+        match self
+            .update_pull(pull, previous_body, Some(&card), update)
+            .await
+        {
+            Ok(_) => {
+                self.rejected.lock().unwrap().remove(&pull.url);
+            }
+            Err(e) if status_of(e.as_ref()) == Some(422) => {
+                tracing::warn!("GitHub rejected the update of {}: {}", pull.url, e);
+                self.rejected.lock().unwrap().insert(pull.url.clone(), body);
+                self.skip("rejected");
+                return Ok(false);
+            }
+            Err(e) => return Err(e),
+        }
+
+        Ok(true)
+    }
+
+    /// Adds and removes labels, creating the missing ones in the repository.
+    async fn apply_labels(
+        &self,
+        pull: &PullRequest,
+        changes: LabelChanges,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if changes.is_empty() {
+            return Ok(());
+        }
+        tracing::info!(
+            "Updating labels of {}: adding {:?}, removing {:?}",
+            pull.url,
+            changes.add,
+            changes.remove
+        );
+
+        if !changes.add.is_empty() {
+            let existing = self.github.list_labels(&pull.repository).await?;
+            for label in changes.add.iter() {
+                if !has_label(&existing, label) {
+                    self.github.create_label(&pull.repository, label).await?;
+                }
+            }
+            self.github.add_labels(pull, &changes.add).await?;
+        }
+        for label in changes.remove.iter() {
+            self.github.remove_label(pull, label).await?;
+        }
+        Ok(())
+    }
+
+    /// Assigns the card users or requests their review, according to their
+    /// role on the card. Users without a GitHub login are reported and
+    /// skipped, and so are logins GitHub rejects, such as people who are not
+    /// collaborators. Nothing is sent while the card users stay the same.
+    async fn apply_people(
+        &self,
+        tracker: &dyn KanbanizeApi,
+        pull: &PullRequest,
+        card: &Card,
+        people: &PeopleConfig,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let users = card
+            .owner
+            .iter()
+            .map(|user| (user, people.owner))
+            .chain(card.co_owners.iter().map(|user| (user, people.co_owners)))
+            .chain(card.watchers.iter().map(|user| (user, people.watchers)))
+            .filter(|(_, role)| *role != Role::None);
+        let roles: Vec<(String, Role)> = users
+            .clone()
+            .map(|(user, role)| (user.id.clone(), role))
+            .collect();
+        if self.people.lock().unwrap().get(&pull.url) == Some(&roles) {
+            return Ok(());
+        }
+
+        let mut resolver = Resolver::new(&self.users, self.github.as_ref(), tracker);
+        let mut assignees = Vec::new();
+        let mut reviewers = Vec::new();
+        for (user, role) in users {
+            let Some(login) = resolver.login(user).await? else {
+                tracing::warn!("No GitHub login mapped for tracker user {}", user.id);
+                self.metrics.user_unmapped();
+                continue;
+            };
+            let logins = match role {
+                Role::Assignee => &mut assignees,
+                // GitHub rejects review requests from the author
+                Role::Reviewer if login == pull.author => continue,
+                Role::Reviewer => &mut reviewers,
+                Role::None => continue,
+            };
+            if !logins.contains(&login) {
+                logins.push(login);
+            }
+        }
 
+        if !assignees.is_empty() {
+            match self.github.add_assignees(pull, &assignees).await {
+                Err(e) if status_of(e.as_ref()) == Some(422) => {
+                    tracing::warn!("GitHub rejected the assignees {:?}: {}", assignees, e);
+                }
+                result => result?,
+            }
+        }
+        if !reviewers.is_empty() {
+            match self.github.request_reviewers(pull, &reviewers).await {
+                Err(e) if status_of(e.as_ref()) == Some(422) => {
+                    tracing::warn!("GitHub rejected the reviewers {:?}: {}", reviewers, e);
+                }
+                result => result?,
+            }
+        }
+        self.people.lock().unwrap().insert(pull.url.clone(), roles);
         Ok(())
     }
 }
 
-fn card_to_markdown(card: Card) -> String {
-    html2md::parse_html(card.description.as_str())
+/// Outcome of looking up the card of a pull request.
+enum CardLink<'a> {
+    Linked {
+        tracker: &'a dyn KanbanizeApi,
+        card: Box<Card>,
+    },
+    Unresolved {
+        /// Tracker the card was looked up in, if one was routed.
+        tracker: Option<&'a dyn KanbanizeApi>,
+        /// Skip reason reported in the metrics.
+        reason: &'static str,
+        summary: String,
+    },
+}
+
+/// Name of the commit status reporting the card link.
+const CARD_LINK_CONTEXT: &str = "gitkban/card-link";
+
+/// Builds the `gitkban/card-link` status, failing for unresolved cards and
+/// cards in a disallowed column.
+fn card_link_status(link: &CardLink, config: &RepositoryConfig) -> CommitStatus {
+    let (state, description) = match link {
+        CardLink::Unresolved { summary, .. } => (StatusState::Failure, summary.clone()),
+        CardLink::Linked { card, .. } => {
+            let key = card.custom_id.as_deref().unwrap_or(card.id.as_str());
+            // Columns are configured by ID or by name
+            match [&card.column, &card.column_name]
+                .into_iter()
+                .flatten()
+                .find(|column| config.disallowed_columns.contains(column))
+            {
+                Some(column) => (
+                    StatusState::Failure,
+                    format!(
+                        "Card {} is in column `{}`, which is not allowed",
+                        key, column
+                    ),
+                ),
+                None => (
+                    StatusState::Success,
+                    format!("Linked to card {}: {}", key, card.title),
+                ),
+            }
+        }
+    };
+
+    CommitStatus {
+        state,
+        context: CARD_LINK_CONTEXT.to_string(),
+        // GitHub rejects descriptions longer than 140 characters
+        description: description.chars().take(140).collect(),
+    }
+}
+
+/// First line of the comment posted on pull requests without a card, used to
+/// find it again.
+const UNRESOLVED_MARKER: &str = "<!-- gitkban:unresolved-card -->";
+
+/// Builds the comment explaining why a card couldn't be found, with the
+/// branch names the tracker expects and the cards that might match.
+fn unresolved_comment(summary: &str, branch_pattern: &str, candidates: &[Card]) -> String {
+    let mut body = format!(
+        "{}\ngitkban couldn't link this pull request to a card: {}.\n",
+        UNRESOLVED_MARKER, summary
+    );
+    body.push_str(&format!(
+        "\nBranch names must contain {}. Rename the branch to link the card, this comment is removed once the card is found.\n",
+        branch_pattern
+    ));
+    if !candidates.is_empty() {
+        body.push_str("\nCards that might match:\n");
+        for card in candidates {
+            let key = card.custom_id.as_deref().unwrap_or(card.id.as_str());
+            body.push_str(&format!(
+                "- `{}` {}\n",
+                key,
+                markdown::neutralize_mentions(&card.title)
+            ));
+        }
+    }
+    body
+}
+
+/// Whether the pull request of `issue` already has a body. Pull requests with
+/// a body are only inspected to report their card link, the minimal body of a
+/// draft is replaced once it is ready.
+fn has_body(issue: &Issue) -> bool {
+    let awaiting_sync = issue.body.starts_with(DRAFT_MARKER) && !issue.draft;
+    issue.body != String::default() && !awaiting_sync
+}
+
+/// First line of the body given to drafts in [`DraftMode::Minimal`].
+const DRAFT_MARKER: &str = "<!-- gitkban:draft -->";
+
+/// Body linking the card of a draft until the full sync.
+fn draft_body(card: &Card) -> String {
+    let key = card.custom_id.as_deref().unwrap_or(card.id.as_str());
+    format!(
+        "{}\nCard {}: {}\n\nThe card description is added once this pull request is ready for review.\n",
+        DRAFT_MARKER,
+        key,
+        markdown::neutralize_mentions(&card.title)
+    )
+}
+
+/// Whether `title` is the one GitHub suggests for `branch`: the branch name
+/// with dashes and underscores as spaces and the first letter capitalized.
+fn is_default_title(title: &str, branch: &str) -> bool {
+    let humanized = branch.replace(['-', '_'], " ");
+    let mut chars = humanized.chars();
+    let generated = match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    };
+    title == branch || title == generated
+}
+
+/// Renders a title format, replacing `{id}`, `{custom_id}` and `{title}` with
+/// the card fields. `{custom_id}` falls back to the card ID.
+pub fn format_title(format: &str, card: &Card) -> String {
+    format
+        .replace("{id}", &card.id)
+        .replace(
+            "{custom_id}",
+            card.custom_id.as_deref().unwrap_or(card.id.as_str()),
+        )
+        .replace("{title}", &card.title)
+}
+
+fn description_to_markdown(card: &Card) -> String {
+    match card.markup {
+        Markup::Html => markdown::from_html(&card.description),
+        Markup::Markdown => markdown::neutralize_mentions(&card.description),
+    }
+}
+
+/// Maximum length of a pull request body, in characters.
+const BODY_LIMIT: usize = 65_536;
+
+/// Pull request body for `card`, made of its `description`, custom fields,
+/// related cards and subtasks. To keep the body within [`BODY_LIMIT`], the
+/// subtasks are left out if they don't fit whole, the custom fields and
+/// related cards are cut, and the description gets the room left.
+fn card_body(
+    card: &Card,
+    description: &str,
+    custom_fields: &[FieldConfig],
+    related: &[Related],
+) -> String {
+    let notice = match &card.url {
+        Some(url) => format!(
+            "*The card is too long for a pull request, read the rest on [the card]({}).*",
+            url
+        ),
+        None => "*The card is too long for a pull request, read the rest on the card.*".to_string(),
+    };
+    let mut budget = BODY_LIMIT.saturating_sub(notice.chars().count() + 2);
+    let mut cut = false;
+    let mut fit = |section: Option<String>, cuttable: bool| {
+        let section = section?;
+        let length = section.chars().count() + 2;
+        if length <= budget {
+            budget -= length;
+            return Some(section);
+        }
+        cut = true;
+        let section = markdown::truncate(&section, budget.saturating_sub(2))
+            .filter(|section| cuttable && !section.trim().is_empty())?;
+        budget -= section.chars().count() + 2;
+        Some(section)
+    };
+    // By priority, the subtask markers must stay paired for the sync
+    let subtasks = fit(
+        (!card.subtasks.is_empty()).then(|| subtasks::render(&card.subtasks)),
+        false,
+    );
+    let fields = fit(fields::render(card, custom_fields), true);
+    let related = fit(
+        (!related.is_empty()).then(|| related::render(related)),
+        true,
+    );
+
+    let mut body = match markdown::truncate(description, budget) {
+        Some(truncated) => {
+            cut = true;
+            truncated
+        }
+        None => description.to_string(),
+    };
+    if cut {
+        body = format!("{}\n\n{}", body, notice);
+    }
+    for section in [fields, related, subtasks].into_iter().flatten() {
+        body = format!("{}\n\n{}", body, section);
+    }
+    body
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::{github::MockGithubApi, kanbanize::MockKanbanizeApi};
-    use url::Url;
+    use crate::{
+        github::MockGithubApi,
+        kanbanize::{MockKanbanizeApi, Relation, Subtask, User},
+        router::Route,
+    };
+
+    /// Private pull request of `owner/repo` from `branch`.
+    fn pull_request(branch: &str) -> PullRequest {
+        PullRequest {
+            url: Url::parse("https://example.com/pull").unwrap(),
+            number: 1,
+            title: String::from("Request 123"),
+            body: String::new(),
+            head_reference: branch.to_string(),
+            head_sha: String::from("abc123"),
+            repository: String::from("owner/repo"),
+            repository_owner: String::from("owner"),
+            is_private: true,
+            labels: vec![],
+            author: String::from("author"),
+            draft: false,
+        }
+    }
 
     #[tokio::test]
     async fn test_process_no_issues() {
@@ -120,8 +1145,12 @@ mod test {
             .expect_get_issues()
             .return_once(|| Ok(Vec::<Issue>::default()));
 
-        let logic = Service::new(Box::new(github), Box::new(kanbanize), "owner".to_string());
-        let result = logic.process().await;
+        let logic = Service::new(
+            Box::new(github),
+            Router::single(Box::new(kanbanize)),
+            "owner".to_string(),
+        );
+        let result = logic.process(&CancellationToken::new()).await;
         assert!(result.is_ok(), "result error: {:?}", result.err());
     }
 
@@ -129,17 +1158,13 @@ mod test {
     async fn test_process() -> testresult::TestResult {
         let mut github = MockGithubApi::new();
         let mut kanbanize = MockKanbanizeApi::new();
-        let pull = PullRequest {
-            url: Url::parse("https://example.com/pull")?,
-            head_reference: String::from("request-123"),
-            repository_owner: String::from("owner"),
-            is_private: true,
-        };
+        let pull = pull_request("request-123");
         github.expect_get_issues().return_once(|| {
             Ok(vec![Issue {
                 url: Url::parse("https://example.com/issue")?,
                 body: String::default(),
                 pull_request_url: Some(Url::parse("https://example.com/pull")?),
+                draft: false,
             }])
         });
 
@@ -151,12 +1176,21 @@ mod test {
             )?))
             .return_once(move |_| Ok(pull1));
 
+        kanbanize
+            .expect_card_id_from_branch()
+            .with(mockall::predicate::eq("request-123"))
+            .return_once(|_| Some(String::from("123")));
         kanbanize
             .expect_find_by_id()
-            .with(mockall::predicate::eq(123))
+            .with(mockall::predicate::eq("123"))
             .return_once(|_| {
                 Ok(Card {
+                    id: String::from("123"),
+                    custom_id: None,
+                    title: String::from("Card title"),
                     description: String::from("description"),
+                    markup: Markup::Html,
+                    ..Default::default()
                 })
             });
 
@@ -164,12 +1198,19 @@ mod test {
             .expect_update_pull_request()
             .with(
                 mockall::predicate::eq(Url::parse("https://example.com/pull")?),
-                mockall::predicate::eq(String::from("description")),
+                mockall::predicate::eq(PullRequestUpdate {
+                    body: Some(String::from("description")),
+                    ..Default::default()
+                }),
             )
             .return_once(|_, _| Ok(pull));
 
-        let logic = Service::new(Box::new(github), Box::new(kanbanize), "owner".to_string());
-        let result = logic.process().await;
+        let logic = Service::new(
+            Box::new(github),
+            Router::single(Box::new(kanbanize)),
+            "owner".to_string(),
+        );
+        let result = logic.process(&CancellationToken::new()).await;
         assert!(result.is_ok(), "result error: {:?}", result.err());
         Ok(())
     }
@@ -179,16 +1220,15 @@ mod test {
         let mut github = MockGithubApi::new();
         let kanbanize = MockKanbanizeApi::new();
         let pull = PullRequest {
-            url: Url::parse("https://example.com/pull")?,
-            head_reference: String::from("request-123"),
             repository_owner: String::from("not owner"),
-            is_private: true,
+            ..pull_request("request-123")
         };
         github.expect_get_issues().return_once(|| {
             Ok(vec![Issue {
                 url: Url::parse("https://example.com/issue")?,
                 body: String::default(),
                 pull_request_url: Some(Url::parse("https://example.com/pull")?),
+                draft: false,
             }])
         });
 
@@ -198,8 +1238,12 @@ mod test {
             .with(mockall::predicate::eq(pull.url.clone()))
             .return_once(move |_| Ok(pull1));
 
-        let logic = Service::new(Box::new(github), Box::new(kanbanize), "owner".to_string());
-        let result = logic.process().await;
+        let logic = Service::new(
+            Box::new(github),
+            Router::single(Box::new(kanbanize)),
+            "owner".to_string(),
+        );
+        let result = logic.process(&CancellationToken::new()).await;
         assert!(result.is_ok(), "result error: {:?}", result.err());
         Ok(())
     }
@@ -209,16 +1253,15 @@ mod test {
         let mut github = MockGithubApi::new();
         let kanbanize = MockKanbanizeApi::new();
         let pull = PullRequest {
-            url: Url::parse("https://example.com/pull")?,
-            head_reference: String::from("request-123"),
-            repository_owner: String::from("owner"),
             is_private: false,
+            ..pull_request("request-123")
         };
         github.expect_get_issues().return_once(|| {
             Ok(vec![Issue {
                 url: Url::parse("https://example.com/issue")?,
                 body: String::default(),
                 pull_request_url: Some(Url::parse("https://example.com/pull")?),
+                draft: false,
             }])
         });
 
@@ -230,8 +1273,12 @@ mod test {
             )?))
             .return_once(move |_| Ok(pull1));
 
-        let logic = Service::new(Box::new(github), Box::new(kanbanize), "owner".to_string());
-        let result = logic.process().await;
+        let logic = Service::new(
+            Box::new(github),
+            Router::single(Box::new(kanbanize)),
+            "owner".to_string(),
+        );
+        let result = logic.process(&CancellationToken::new()).await;
         assert!(result.is_ok(), "result error: {:?}", result.err());
         Ok(())
     }
@@ -245,12 +1292,1574 @@ mod test {
                 url: Url::parse("https://example.com/issue")?,
                 body: String::from("existing"),
                 pull_request_url: Some(Url::parse("https://example.com/pull")?),
+                draft: false,
             }])
         });
 
-        let logic = Service::new(Box::new(github), Box::new(kanbanize), "owner".to_string());
-        let result = logic.process().await;
+        let logic = Service::new(
+            Box::new(github),
+            Router::single(Box::new(kanbanize)),
+            "owner".to_string(),
+        );
+        let result = logic.process(&CancellationToken::new()).await;
         assert!(result.is_ok(), "result error: {:?}", result.err());
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_process_audit_and_revert() -> testresult::TestResult {
+        let mut github = MockGithubApi::new();
+        let mut kanbanize = MockKanbanizeApi::new();
+        let url = Url::parse("https://api.github.com/repos/owner/repo/pulls/1")?;
+        let pull = PullRequest {
+            url: url.clone(),
+            ..pull_request("request-123")
+        };
+        let pull_url = url.clone();
+        github.expect_get_issues().times(2).returning(move || {
+            Ok(vec![Issue {
+                url: Url::parse("https://example.com/issue")?,
+                body: String::default(),
+                pull_request_url: Some(pull_url.clone()),
+                draft: false,
+            }])
+        });
+        // Polling, then the reverts, the first one after an edit by hand,
+        // polling again and a forced revert
+        let mut sequence = mockall::Sequence::new();
+        for body in ["", "edited by hand", "description", "", "edited by hand"] {
+            let pull = PullRequest {
+                body: String::from(body),
+                ..pull.clone()
+            };
+            github
+                .expect_get_pull_from_url()
+                .times(1)
+                .in_sequence(&mut sequence)
+                .return_once(move |_| Ok(pull));
+        }
+        kanbanize
+            .expect_card_id_from_branch()
+            .return_once(|_| Some(String::from("123")));
+        kanbanize.expect_find_by_id().return_once(|_| {
+            Ok(Card {
+                id: String::from("123"),
+                description: String::from("description"),
+                revision: Some(String::from("4")),
+                ..Default::default()
+            })
+        });
+        let mut updates = mockall::Sequence::new();
+        for body in ["description", "", "description"] {
+            let pull = pull.clone();
+            github
+                .expect_update_pull_request()
+                .with(
+                    mockall::predicate::eq(url.clone()),
+                    mockall::predicate::eq(PullRequestUpdate {
+                        body: Some(String::from(body)),
+                        ..Default::default()
+                    }),
+                )
+                .times(1)
+                .in_sequence(&mut updates)
+                .return_once(move |_, _| Ok(pull));
+        }
+
+        let path = std::env::temp_dir().join("gitkban_test_logic_audit.jsonl");
+        let _ = std::fs::remove_file(&path);
+        let audit = AuditLog::new(&path);
+        let logic = Service::new(
+            Box::new(github),
+            Router::single(Box::new(kanbanize)),
+            "owner".to_string(),
+        )
+        .with_audit(audit.clone());
+        logic.process(&CancellationToken::new()).await?;
+
+        let history = audit.history("https://github.com/owner/repo/pull/1")?;
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].card_id, Some(String::from("123")));
+        assert_eq!(history[0].revision, Some(String::from("4")));
+        assert_eq!(history[0].previous_body, "");
+        assert_eq!(history[0].body, "description");
+
+        let url = "https://github.com/owner/repo/pull/1";
+        assert!(logic.revert(url, Some(2), false).await.is_err());
+        // The body was edited since it was written
+        assert!(logic.revert(url, None, false).await.is_err());
+        logic.revert(url, None, false).await?;
+        let history = audit.history(url)?;
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1].card_id, Some(String::from("123")));
+        assert_eq!(history[1].revision, Some(String::from("4")));
+        assert_eq!(history[1].reverts, Some(1));
+        assert_eq!(history[1].previous_body, "description");
+        assert_eq!(history[1].body, "");
+
+        // The empty body is not filled again
+        logic.process(&CancellationToken::new()).await?;
+        assert_eq!(audit.history(url)?.len(), 2);
+
+        logic.revert(url, Some(2), true).await?;
+        let history = audit.history(url)?;
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[2].previous_body, "edited by hand");
+        assert_eq!(history[2].body, "description");
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_bulk() -> testresult::TestResult {
+        let mut github = MockGithubApi::new();
+        let mut kanbanize = MockKanbanizeApi::new();
+        github.expect_get_issues().return_once(|| {
+            Ok(["1", "2", "3"]
+                .iter()
+                .map(|number| Issue {
+                    url: Url::parse(&format!("https://example.com/issue/{}", number)).unwrap(),
+                    body: String::default(),
+                    pull_request_url: Some(
+                        Url::parse(&format!("https://example.com/pull/{}", number)).unwrap(),
+                    ),
+                    draft: false,
+                })
+                .collect())
+        });
+        github.expect_get_pull_from_url().times(3).returning(|url| {
+            let number: u64 = url.path().trim_start_matches("/pull/").parse()?;
+            Ok(PullRequest {
+                url: url.clone(),
+                number,
+                title: format!("Request {}", number),
+                // The last two pull requests are for the same card
+                ..pull_request(&format!("request-{}", number.min(2)))
+            })
+        });
+        kanbanize
+            .expect_card_id_from_branch()
+            .returning(|branch| branch.strip_prefix("request-").map(ToString::to_string));
+        // One request for the cycle instead of one per pull request
+        kanbanize
+            .expect_find_many()
+            .with(mockall::predicate::eq(vec![
+                String::from("1"),
+                String::from("2"),
+            ]))
+            .times(1)
+            .returning(|ids| {
+                Ok(ids
+                    .iter()
+                    .map(|id| {
+                        let card = Card {
+                            id: id.clone(),
+                            description: format!("Card {}", id),
+                            ..Default::default()
+                        };
+                        (id.clone(), card)
+                    })
+                    .collect())
+            });
+        kanbanize.expect_find_by_id().never();
+        github
+            .expect_update_pull_request()
+            .times(3)
+            .returning(|url, _| {
+                Ok(PullRequest {
+                    url: url.clone(),
+                    ..pull_request("")
+                })
+            });
+
+        let metrics = Arc::new(Metrics::default());
+        let logic = Service::new(
+            Box::new(github),
+            Router::single(Box::new(kanbanize)),
+            "owner".to_string(),
+        )
+        .with_metrics(metrics.clone());
+        logic.process(&CancellationToken::new()).await?;
+        assert_eq!(metrics.totals().updated, 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_orphans() -> testresult::TestResult {
+        let mut github = MockGithubApi::new();
+        let mut kanbanize = MockKanbanizeApi::new();
+        github.expect_get_issues().return_once(|| {
+            Ok(vec![Issue {
+                url: Url::parse("https://example.com/issue/1")?,
+                body: String::from("Body"),
+                pull_request_url: Some(Url::parse("https://example.com/pull/1")?),
+                draft: false,
+            }])
+        });
+        github.expect_get_pull_from_url().returning(|url| {
+            Ok(PullRequest {
+                url: url.clone(),
+                title: String::from("Request 1"),
+                ..pull_request("request-1")
+            })
+        });
+        kanbanize
+            .expect_card_id_from_branch()
+            .returning(|branch| branch.strip_prefix("request-").map(ToString::to_string));
+        kanbanize
+            .expect_cards_in_column()
+            .with(mockall::predicate::eq("7"))
+            .return_once(|_| {
+                Ok(["1", "2"]
+                    .iter()
+                    .map(|id| Card {
+                        id: id.to_string(),
+                        title: format!("Card {}", id),
+                        column_name: Some(String::from("In Review")),
+                        ..Default::default()
+                    })
+                    .collect())
+            });
+
+        let logic = Service::new(
+            Box::new(github),
+            Router::single(Box::new(kanbanize)),
+            "owner".to_string(),
+        );
+        let review_columns = HashMap::from([("default".to_string(), vec!["7".to_string()])]);
+        let orphans = logic.orphans(&review_columns).await?;
+        assert_eq!(
+            orphans
+                .iter()
+                .map(|orphan| orphan.id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["2"]
+        );
+        assert_eq!(orphans[0].column, "In Review");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_title_sync() -> testresult::TestResult {
+        let mut github = MockGithubApi::new();
+        let mut kanbanize = MockKanbanizeApi::new();
+        let pull = pull_request("request-123");
+        github.expect_get_issues().return_once(|| {
+            Ok(vec![Issue {
+                url: Url::parse("https://example.com/issue")?,
+                body: String::default(),
+                pull_request_url: Some(Url::parse("https://example.com/pull")?),
+                draft: false,
+            }])
+        });
+
+        let pull1 = pull.clone();
+        github
+            .expect_get_pull_from_url()
+            .return_once(move |_| Ok(pull1));
+
+        kanbanize
+            .expect_card_id_from_branch()
+            .return_once(|_| Some(String::from("123")));
+        kanbanize.expect_find_by_id().return_once(|_| {
+            Ok(Card {
+                id: String::from("123"),
+                custom_id: Some(String::from("REQ-123")),
+                title: String::from("Card title"),
+                description: String::from("description"),
+                markup: Markup::Markdown,
+                ..Default::default()
+            })
+        });
+
+        github
+            .expect_update_pull_request()
+            .with(
+                mockall::predicate::always(),
+                mockall::predicate::eq(PullRequestUpdate {
+                    title: Some(String::from("[REQ-123] Card title")),
+                    body: Some(String::from("description")),
+                }),
+            )
+            .return_once(|_, _| Ok(pull));
+
+        let logic = Service::new(
+            Box::new(github),
+            Router::single(Box::new(kanbanize)),
+            "owner".to_string(),
+        )
+        .with_repositories(vec![RepositoryConfig {
+            repository: String::from("owner/*"),
+            title_format: Some(String::from("[{custom_id}] {title}")),
+            ..Default::default()
+        }]);
+        logic.process(&CancellationToken::new()).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_labels() -> testresult::TestResult {
+        let mut github = MockGithubApi::new();
+        let mut kanbanize = MockKanbanizeApi::new();
+        let pull = PullRequest {
+            labels: vec![String::from("p2"), String::from("needs-review")],
+            ..pull_request("request-123")
+        };
+        github.expect_get_issues().return_once(|| {
+            Ok(vec![Issue {
+                url: Url::parse("https://example.com/issue")?,
+                body: String::default(),
+                pull_request_url: Some(Url::parse("https://example.com/pull")?),
+                draft: false,
+            }])
+        });
+
+        let pull1 = pull.clone();
+        github
+            .expect_get_pull_from_url()
+            .return_once(move |_| Ok(pull1));
+        kanbanize
+            .expect_card_id_from_branch()
+            .return_once(|_| Some(String::from("123")));
+        kanbanize.expect_find_by_id().return_once(|_| {
+            Ok(Card {
+                priority: Some(String::from("1")),
+                ..Default::default()
+            })
+        });
+        github
+            .expect_update_pull_request()
+            .return_once(|_, _| Ok(pull));
+
+        github
+            .expect_list_labels()
+            .with(mockall::predicate::eq("owner/repo"))
+            .return_once(|_| Ok(vec![String::from("p2")]));
+        github
+            .expect_create_label()
+            .with(
+                mockall::predicate::eq("owner/repo"),
+                mockall::predicate::eq("p1"),
+            )
+            .return_once(|_, _| Ok(()));
+        github
+            .expect_add_labels()
+            .withf(|_, labels| labels == [String::from("p1")])
+            .return_once(|_, _| Ok(()));
+        github
+            .expect_remove_label()
+            .withf(|_, label| label == "p2")
+            .return_once(|_, _| Ok(()));
+
+        let metrics = Arc::new(Metrics::default());
+        let logic = Service::new(
+            Box::new(github),
+            Router::single(Box::new(kanbanize)),
+            "owner".to_string(),
+        )
+        .with_repositories(vec![RepositoryConfig {
+            repository: String::from("owner/repo"),
+            labels: Some(toml::from_str(r#"priority = { "1" = "p1", "2" = "p2" }"#)?),
+            ..Default::default()
+        }])
+        .with_metrics(metrics.clone());
+        logic.process(&CancellationToken::new()).await?;
+        assert_eq!(metrics.totals().updated, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_labels_other_case() -> testresult::TestResult {
+        let mut github = MockGithubApi::new();
+        let mut kanbanize = MockKanbanizeApi::new();
+        let pull = pull_request("request-123");
+        github.expect_get_issues().return_once(|| {
+            Ok(vec![Issue {
+                url: Url::parse("https://example.com/issue")?,
+                body: String::default(),
+                pull_request_url: Some(Url::parse("https://example.com/pull")?),
+                draft: false,
+            }])
+        });
+
+        let pull1 = pull.clone();
+        github
+            .expect_get_pull_from_url()
+            .return_once(move |_| Ok(pull1));
+        kanbanize
+            .expect_card_id_from_branch()
+            .return_once(|_| Some(String::from("123")));
+        kanbanize.expect_find_by_id().return_once(|_| {
+            Ok(Card {
+                card_type: Some(String::from("3")),
+                ..Default::default()
+            })
+        });
+        github
+            .expect_update_pull_request()
+            .return_once(|_, _| Ok(pull));
+
+        // The repository label differs only by case, GitHub would refuse to create it
+        github
+            .expect_list_labels()
+            .return_once(|_| Ok(vec![String::from("Bug")]));
+        github.expect_create_label().never();
+        github
+            .expect_add_labels()
+            .withf(|_, labels| labels == [String::from("bug")])
+            .return_once(|_, _| Ok(()));
+
+        let metrics = Arc::new(Metrics::default());
+        let logic = Service::new(
+            Box::new(github),
+            Router::single(Box::new(kanbanize)),
+            "owner".to_string(),
+        )
+        .with_repositories(vec![RepositoryConfig {
+            repository: String::from("owner/repo"),
+            labels: Some(toml::from_str(r#"type = { "3" = "bug" }"#)?),
+            ..Default::default()
+        }])
+        .with_metrics(metrics.clone());
+        logic.process(&CancellationToken::new()).await?;
+        assert_eq!(metrics.totals().updated, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_labels_with_body() -> testresult::TestResult {
+        let mut github = MockGithubApi::new();
+        let mut kanbanize = MockKanbanizeApi::new();
+        let pull = PullRequest {
+            labels: vec![String::from("p2")],
+            ..pull_request("request-123")
+        };
+        github.expect_get_issues().return_once(|| {
+            Ok(vec![Issue {
+                url: Url::parse("https://example.com/issue")?,
+                body: String::from("Synced on an earlier cycle"),
+                pull_request_url: Some(Url::parse("https://example.com/pull")?),
+                draft: false,
+            }])
+        });
+        github
+            .expect_get_pull_from_url()
+            .return_once(move |_| Ok(pull));
+        kanbanize
+            .expect_card_id_from_branch()
+            .return_once(|_| Some(String::from("123")));
+        // The priority changed since the body was written
+        kanbanize.expect_find_by_id().return_once(|_| {
+            Ok(Card {
+                priority: Some(String::from("1")),
+                ..Default::default()
+            })
+        });
+        github.expect_update_pull_request().never();
+
+        github
+            .expect_list_labels()
+            .return_once(|_| Ok(vec![String::from("p1"), String::from("p2")]));
+        github.expect_create_label().never();
+        github
+            .expect_add_labels()
+            .withf(|_, labels| labels == [String::from("p1")])
+            .times(1)
+            .return_once(|_, _| Ok(()));
+        github
+            .expect_remove_label()
+            .withf(|_, label| label == "p2")
+            .times(1)
+            .return_once(|_, _| Ok(()));
+
+        let metrics = Arc::new(Metrics::default());
+        let logic = Service::new(
+            Box::new(github),
+            Router::single(Box::new(kanbanize)),
+            "owner".to_string(),
+        )
+        .with_repositories(vec![RepositoryConfig {
+            repository: String::from("owner/repo"),
+            labels: Some(toml::from_str(r#"priority = { "1" = "p1", "2" = "p2" }"#)?),
+            ..Default::default()
+        }])
+        .with_metrics(metrics.clone());
+        logic.process(&CancellationToken::new()).await?;
+        assert_eq!(metrics.totals().failed, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_people() -> testresult::TestResult {
+        let mut github = MockGithubApi::new();
+        let mut kanbanize = MockKanbanizeApi::new();
+        let pull = pull_request("request-123");
+        github.expect_get_issues().return_once(|| {
+            Ok(vec![Issue {
+                url: Url::parse("https://example.com/issue")?,
+                body: String::default(),
+                pull_request_url: Some(Url::parse("https://example.com/pull")?),
+                draft: false,
+            }])
+        });
+
+        let pull1 = pull.clone();
+        github
+            .expect_get_pull_from_url()
+            .return_once(move |_| Ok(pull1));
+        kanbanize
+            .expect_card_id_from_branch()
+            .return_once(|_| Some(String::from("123")));
+        kanbanize.expect_find_by_id().return_once(|_| {
+            Ok(Card {
+                owner: Some(User::new(17)),
+                co_owners: vec![User::new(18), User::new(19), User::new(20)],
+                ..Default::default()
+            })
+        });
+        kanbanize.expect_users().return_once(|| Ok(vec![]));
+        github
+            .expect_update_pull_request()
+            .return_once(|_, _| Ok(pull));
+
+        github
+            .expect_add_assignees()
+            .withf(|_, assignees| assignees == [String::from("jane-gh")])
+            .return_once(|_, _| Ok(()));
+        github
+            .expect_request_reviewers()
+            .withf(|_, reviewers| reviewers == [String::from("john-gh")])
+            .return_once(|_, _| Ok(()));
+
+        let metrics = Arc::new(Metrics::default());
+        let logic = Service::new(
+            Box::new(github),
+            Router::single(Box::new(kanbanize)),
+            "owner".to_string(),
+        )
+        .with_repositories(vec![RepositoryConfig {
+            repository: String::from("owner/repo"),
+            people: Some(PeopleConfig::default()),
+            ..Default::default()
+        }])
+        .with_users(toml::from_str(
+            r#"logins = { "17" = "jane-gh", "18" = "john-gh", "19" = "author" }"#,
+        )?)
+        .with_metrics(metrics.clone());
+        logic.process(&CancellationToken::new()).await?;
+        assert!(metrics.render()?.contains("gitkban_users_unmapped_total 1"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_people_with_body() -> testresult::TestResult {
+        let mut github = MockGithubApi::new();
+        let mut kanbanize = MockKanbanizeApi::new();
+        github.expect_get_issues().times(2).returning(|| {
+            Ok(vec![Issue {
+                url: Url::parse("https://example.com/issue")?,
+                body: String::from("Synced on an earlier cycle"),
+                pull_request_url: Some(Url::parse("https://example.com/pull")?),
+                draft: false,
+            }])
+        });
+        github
+            .expect_get_pull_from_url()
+            .times(2)
+            .returning(|_| Ok(pull_request("request-123")));
+        kanbanize
+            .expect_card_id_from_branch()
+            .returning(|_| Some(String::from("123")));
+        kanbanize.expect_find_by_id().times(2).returning(|_| {
+            Ok(Card {
+                owner: Some(User::new(17)),
+                ..Default::default()
+            })
+        });
+        github.expect_update_pull_request().never();
+        // Applied on the first cycle only, the card users didn't change since
+        github
+            .expect_add_assignees()
+            .withf(|_, assignees| assignees == [String::from("jane-gh")])
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        let logic = Service::new(
+            Box::new(github),
+            Router::single(Box::new(kanbanize)),
+            "owner".to_string(),
+        )
+        .with_repositories(vec![RepositoryConfig {
+            repository: String::from("owner/repo"),
+            people: Some(PeopleConfig::default()),
+            ..Default::default()
+        }])
+        .with_users(toml::from_str(r#"logins = { "17" = "jane-gh" }"#)?);
+        logic.process(&CancellationToken::new()).await?;
+        logic.process(&CancellationToken::new()).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_people_rejected() -> testresult::TestResult {
+        let mut github = MockGithubApi::new();
+        let mut kanbanize = MockKanbanizeApi::new();
+        github.expect_get_issues().times(2).returning(|| {
+            Ok(vec![Issue {
+                url: Url::parse("https://example.com/issue")?,
+                body: String::from("Synced on an earlier cycle"),
+                pull_request_url: Some(Url::parse("https://example.com/pull")?),
+                draft: false,
+            }])
+        });
+        github
+            .expect_get_pull_from_url()
+            .times(2)
+            .returning(|_| Ok(pull_request("request-123")));
+        kanbanize
+            .expect_card_id_from_branch()
+            .returning(|_| Some(String::from("123")));
+        kanbanize.expect_find_by_id().times(2).returning(|_| {
+            Ok(Card {
+                owner: Some(User::new(17)),
+                co_owners: vec![User::new(18)],
+                ..Default::default()
+            })
+        });
+        github
+            .expect_add_assignees()
+            .times(1)
+            .returning(|_, _| Ok(()));
+        // Not a collaborator, which is not retried until the card users change
+        github
+            .expect_request_reviewers()
+            .withf(|_, reviewers| reviewers == [String::from("john-gh")])
+            .times(1)
+            .returning(|_, _| {
+                Err(crate::error::StatusError {
+                    status: 422,
+                    message: String::from("Reviews may only be requested from collaborators"),
+                }
+                .into())
+            });
+
+        let metrics = Arc::new(Metrics::default());
+        let logic = Service::new(
+            Box::new(github),
+            Router::single(Box::new(kanbanize)),
+            "owner".to_string(),
+        )
+        .with_repositories(vec![RepositoryConfig {
+            repository: String::from("owner/repo"),
+            people: Some(PeopleConfig::default()),
+            ..Default::default()
+        }])
+        .with_users(toml::from_str(
+            r#"logins = { "17" = "jane-gh", "18" = "john-gh" }"#,
+        )?)
+        .with_metrics(metrics.clone());
+        logic.process(&CancellationToken::new()).await?;
+        logic.process(&CancellationToken::new()).await?;
+        assert_eq!(metrics.totals().failed, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_enforce_card_link() -> testresult::TestResult {
+        let mut github = MockGithubApi::new();
+        let mut kanbanize = MockKanbanizeApi::new();
+        let pull = PullRequest {
+            title: String::from("Fix typo"),
+            ..pull_request("fix-typo")
+        };
+        github.expect_get_issues().return_once(|| {
+            Ok(vec![Issue {
+                url: Url::parse("https://example.com/issue")?,
+                body: String::from("existing"),
+                pull_request_url: Some(Url::parse("https://example.com/pull")?),
+                draft: false,
+            }])
+        });
+        github
+            .expect_get_pull_from_url()
+            .return_once(move |_| Ok(pull));
+        kanbanize.expect_card_id_from_branch().return_once(|_| None);
+        github
+            .expect_get_commit_status()
+            .withf(|_, context| context == "gitkban/card-link")
+            .return_once(|_, _| Ok(None));
+        github
+            .expect_set_commit_status()
+            .withf(|pull, status| {
+                pull.head_sha == "abc123"
+                    && *status
+                        == CommitStatus {
+                            state: StatusState::Failure,
+                            context: String::from("gitkban/card-link"),
+                            description: String::from("Branch `fix-typo` doesn't reference a card"),
+                        }
+            })
+            .return_once(|_, _| Ok(()));
+
+        let metrics = Arc::new(Metrics::default());
+        let logic = Service::new(
+            Box::new(github),
+            Router::single(Box::new(kanbanize)),
+            "owner".to_string(),
+        )
+        .with_repositories(vec![RepositoryConfig {
+            repository: String::from("owner/repo"),
+            enforce_card_link: true,
+            ..Default::default()
+        }])
+        .with_metrics(metrics.clone());
+        logic.process(&CancellationToken::new()).await?;
+        assert!(metrics
+            .render()?
+            .contains(r#"gitkban_pull_requests_skipped_total{reason="no_card_id"} 1"#));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_enforce_card_link_unchanged() -> testresult::TestResult {
+        let mut github = MockGithubApi::new();
+        let mut kanbanize = MockKanbanizeApi::new();
+        github.expect_get_issues().return_once(|| {
+            Ok(vec![
+                Issue {
+                    url: Url::parse("https://example.com/issue/1")?,
+                    body: String::from("existing"),
+                    pull_request_url: Some(Url::parse("https://example.com/pull/1")?),
+                    draft: false,
+                },
+                Issue {
+                    url: Url::parse("https://example.com/issue/2")?,
+                    body: String::from("existing"),
+                    pull_request_url: Some(Url::parse("https://example.com/pull/2")?),
+                    draft: false,
+                },
+            ])
+        });
+        github.expect_get_pull_from_url().returning(|url| {
+            Ok(PullRequest {
+                url: url.clone(),
+                head_sha: url.path().replace("/pull/", "sha"),
+                ..pull_request("fix-typo")
+            })
+        });
+        kanbanize.expect_card_id_from_branch().returning(|_| None);
+        // The status of the first pull request is already reported, the
+        // second one can't be read
+        github
+            .expect_get_commit_status()
+            .returning(|pull, _| match pull.head_sha.as_str() {
+                "sha1" => Ok(Some(CommitStatus {
+                    state: StatusState::Failure,
+                    context: String::from("gitkban/card-link"),
+                    description: String::from("Branch `fix-typo` doesn't reference a card"),
+                })),
+                _ => Err("unavailable".into()),
+            });
+        github.expect_set_commit_status().never();
+
+        let metrics = Arc::new(Metrics::default());
+        let logic = Service::new(
+            Box::new(github),
+            Router::single(Box::new(kanbanize)),
+            "owner".to_string(),
+        )
+        .with_repositories(vec![RepositoryConfig {
+            repository: String::from("owner/repo"),
+            enforce_card_link: true,
+            ..Default::default()
+        }])
+        .with_metrics(metrics.clone());
+        logic.process(&CancellationToken::new()).await?;
+        assert_eq!(metrics.totals().failed, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_card_link_status() {
+        let tracker = MockKanbanizeApi::new();
+        let config = RepositoryConfig {
+            disallowed_columns: vec![String::from("5")],
+            ..Default::default()
+        };
+        let link = |column: &str| CardLink::Linked {
+            tracker: &tracker,
+            card: Box::new(Card {
+                id: String::from("123"),
+                title: String::from("Login page"),
+                column: Some(String::from(column)),
+                ..Default::default()
+            }),
+        };
+
+        let status = card_link_status(&link("4"), &config);
+        assert_eq!(status.state, StatusState::Success);
+        assert_eq!(status.description, "Linked to card 123: Login page");
+
+        let status = card_link_status(&link("5"), &config);
+        assert_eq!(status.state, StatusState::Failure);
+        assert_eq!(
+            status.description,
+            "Card 123 is in column `5`, which is not allowed"
+        );
+
+        // Columns may be configured by name
+        let config = RepositoryConfig {
+            disallowed_columns: vec![String::from("Done")],
+            ..Default::default()
+        };
+        let link = CardLink::Linked {
+            tracker: &tracker,
+            card: Box::new(Card {
+                id: String::from("123"),
+                column: Some(String::from("5")),
+                column_name: Some(String::from("Done")),
+                ..Default::default()
+            }),
+        };
+        let status = card_link_status(&link, &config);
+        assert_eq!(status.state, StatusState::Failure);
+        assert_eq!(
+            status.description,
+            "Card 123 is in column `Done`, which is not allowed"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_process_comment_unresolved() -> testresult::TestResult {
+        let mut github = MockGithubApi::new();
+        let mut kanbanize = MockKanbanizeApi::new();
+        github.expect_get_issues().times(2).returning(|| {
+            Ok(vec![Issue {
+                url: Url::parse("https://example.com/issue")?,
+                body: String::from("existing"),
+                pull_request_url: Some(Url::parse("https://example.com/pull")?),
+                draft: false,
+            }])
+        });
+        github.expect_get_pull_from_url().returning(|_| {
+            Ok(PullRequest {
+                title: String::from("Fix typo"),
+                ..pull_request("fix-typo")
+            })
+        });
+        kanbanize.expect_card_id_from_branch().returning(|_| None);
+        kanbanize
+            .expect_branch_pattern()
+            .return_const("the card ID");
+        kanbanize
+            .expect_search()
+            .with(mockall::predicate::eq("Fix typo"))
+            .returning(|_| {
+                Ok(vec![Card {
+                    id: String::from("42"),
+                    custom_id: Some(String::from("ENG-42")),
+                    title: String::from("Typo on the login page"),
+                    ..Default::default()
+                }])
+            });
+        github.expect_list_comments().times(1).return_once(|_| {
+            Ok(vec![
+                Comment {
+                    id: 6,
+                    body: String::from("Looks good"),
+                },
+                Comment {
+                    id: 7,
+                    body: format!("{}\nstale", UNRESOLVED_MARKER),
+                },
+            ])
+        });
+        github
+            .expect_update_comment()
+            .withf(|_, id, body| {
+                *id == 7
+                    && body.starts_with(UNRESOLVED_MARKER)
+                    && body.contains("Branch `fix-typo` doesn't reference a card")
+                    && body.contains("Branch names must contain the card ID.")
+                    && body.contains("- `ENG-42` Typo on the login page")
+            })
+            .times(1)
+            .return_once(|_, _, _| Ok(()));
+
+        let metrics = Arc::new(Metrics::default());
+        let logic = Service::new(
+            Box::new(github),
+            Router::single(Box::new(kanbanize)),
+            "owner".to_string(),
+        )
+        .with_repositories(vec![RepositoryConfig {
+            repository: String::from("owner/repo"),
+            comment_unresolved: true,
+            ..Default::default()
+        }])
+        .with_metrics(metrics.clone());
+        logic.process(&CancellationToken::new()).await?;
+        // The comment is up to date, neither listed nor edited again
+        logic.process(&CancellationToken::new()).await?;
+        assert!(metrics
+            .render()?
+            .contains(r#"gitkban_pull_requests_skipped_total{reason="no_card_id"} 2"#));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_comment_no_tracker() -> testresult::TestResult {
+        let mut github = MockGithubApi::new();
+        github.expect_get_issues().return_once(|| {
+            Ok(vec![Issue {
+                url: Url::parse("https://example.com/issue")?,
+                body: String::from("existing"),
+                pull_request_url: Some(Url::parse("https://example.com/pull")?),
+                draft: false,
+            }])
+        });
+        github
+            .expect_get_pull_from_url()
+            .return_once(|_| Ok(pull_request("fix-typo")));
+        github.expect_list_comments().return_once(|_| Ok(vec![]));
+        // There is no tracker whose branch names the author could follow
+        github.expect_create_comment().never();
+
+        let metrics = Arc::new(Metrics::default());
+        let logic = Service::new(Box::new(github), Router::new(None), "owner".to_string())
+            .with_repositories(vec![RepositoryConfig {
+                repository: String::from("owner/repo"),
+                comment_unresolved: true,
+                ..Default::default()
+            }])
+            .with_metrics(metrics.clone());
+        logic.process(&CancellationToken::new()).await?;
+        assert!(metrics
+            .render()?
+            .contains(r#"gitkban_pull_requests_skipped_total{reason="no_tracker"} 1"#));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_comment_resolved() -> testresult::TestResult {
+        let mut github = MockGithubApi::new();
+        let mut kanbanize = MockKanbanizeApi::new();
+        github.expect_get_issues().times(2).returning(|| {
+            Ok(vec![Issue {
+                url: Url::parse("https://example.com/issue")?,
+                body: String::from("existing"),
+                pull_request_url: Some(Url::parse("https://example.com/pull")?),
+                draft: false,
+            }])
+        });
+        github
+            .expect_get_pull_from_url()
+            .returning(|_| Ok(pull_request("request-123")));
+        kanbanize
+            .expect_card_id_from_branch()
+            .returning(|_| Some(String::from("123")));
+        kanbanize.expect_find_by_id().times(2).returning(|_| {
+            Ok(Card {
+                id: String::from("123"),
+                ..Default::default()
+            })
+        });
+        // Listed once, the second cycle knows the comment is gone
+        github.expect_list_comments().times(1).return_once(|_| {
+            Ok(vec![Comment {
+                id: 7,
+                body: format!("{}\nstale", UNRESOLVED_MARKER),
+            }])
+        });
+        github
+            .expect_delete_comment()
+            .withf(|_, id| *id == 7)
+            .times(1)
+            .return_once(|_, _| Ok(()));
+
+        let logic = Service::new(
+            Box::new(github),
+            Router::single(Box::new(kanbanize)),
+            "owner".to_string(),
+        )
+        .with_repositories(vec![RepositoryConfig {
+            repository: String::from("owner/repo"),
+            comment_unresolved: true,
+            ..Default::default()
+        }]);
+        logic.process(&CancellationToken::new()).await?;
+        logic.process(&CancellationToken::new()).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_draft_minimal() -> testresult::TestResult {
+        let mut github = MockGithubApi::new();
+        let mut kanbanize = MockKanbanizeApi::new();
+        let pull = PullRequest {
+            draft: true,
+            ..pull_request("request-123")
+        };
+        github.expect_get_issues().return_once(|| {
+            Ok(vec![Issue {
+                url: Url::parse("https://example.com/issue")?,
+                body: String::default(),
+                pull_request_url: Some(Url::parse("https://example.com/pull")?),
+                draft: true,
+            }])
+        });
+        let pull1 = pull.clone();
+        github
+            .expect_get_pull_from_url()
+            .return_once(move |_| Ok(pull1));
+        kanbanize
+            .expect_card_id_from_branch()
+            .return_once(|_| Some(String::from("123")));
+        kanbanize.expect_find_by_id().return_once(|_| {
+            Ok(Card {
+                id: String::from("123"),
+                custom_id: Some(String::from("REQ-123")),
+                title: String::from("Card title"),
+                description: String::from("description"),
+                ..Default::default()
+            })
+        });
+        github
+            .expect_update_pull_request()
+            .withf(|_, update| {
+                update.title.is_none()
+                    && update.body.as_ref().is_some_and(|body| {
+                        body.starts_with(DRAFT_MARKER) && body.contains("Card REQ-123: Card title")
+                    })
+            })
+            .return_once(|_, _| Ok(pull));
+
+        let logic = Service::new(
+            Box::new(github),
+            Router::single(Box::new(kanbanize)),
+            "owner".to_string(),
+        )
+        .with_repositories(vec![RepositoryConfig {
+            repository: String::from("owner/repo"),
+            drafts: DraftMode::Minimal,
+            ready_column: Some(String::from("7")),
+            ..Default::default()
+        }]);
+        logic.process(&CancellationToken::new()).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_ready_for_review() -> testresult::TestResult {
+        let mut github = MockGithubApi::new();
+        let mut kanbanize = MockKanbanizeApi::new();
+        let pull = pull_request("request-123");
+        // Ready for review, with the body written while it was a draft
+        github.expect_get_issues().return_once(|| {
+            Ok(vec![Issue {
+                url: Url::parse("https://example.com/issue")?,
+                body: format!("{}\nCard 123: Card title", DRAFT_MARKER),
+                pull_request_url: Some(Url::parse("https://example.com/pull")?),
+                draft: false,
+            }])
+        });
+        let pull1 = pull.clone();
+        github
+            .expect_get_pull_from_url()
+            .return_once(move |_| Ok(pull1));
+        kanbanize
+            .expect_card_id_from_branch()
+            .return_once(|_| Some(String::from("123")));
+        kanbanize.expect_find_by_id().return_once(|_| {
+            Ok(Card {
+                id: String::from("123"),
+                title: String::from("Card title"),
+                description: String::from("description"),
+                column: Some(String::from("5")),
+                ..Default::default()
+            })
+        });
+        github
+            .expect_update_pull_request()
+            .with(
+                mockall::predicate::always(),
+                mockall::predicate::eq(PullRequestUpdate {
+                    body: Some(String::from("description")),
+                    ..Default::default()
+                }),
+            )
+            .return_once(|_, _| Ok(pull));
+        kanbanize
+            .expect_move_card()
+            .with(mockall::predicate::eq("123"), mockall::predicate::eq("7"))
+            .return_once(|_, _| Ok(()));
+
+        let logic = Service::new(
+            Box::new(github),
+            Router::single(Box::new(kanbanize)),
+            "owner".to_string(),
+        )
+        .with_repositories(vec![RepositoryConfig {
+            repository: String::from("owner/repo"),
+            drafts: DraftMode::Minimal,
+            ready_column: Some(String::from("7")),
+            ..Default::default()
+        }]);
+        logic.process(&CancellationToken::new()).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_ready_transition() -> testresult::TestResult {
+        let mut github = MockGithubApi::new();
+        let mut kanbanize = MockKanbanizeApi::new();
+        // A draft on the first cycle, ready for review on the next ones
+        let cycle = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let issues_cycle = cycle.clone();
+        github.expect_get_issues().times(4).returning(move || {
+            let cycle = issues_cycle.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(vec![Issue {
+                url: Url::parse("https://example.com/issue")?,
+                body: match cycle {
+                    0 => String::new(),
+                    _ => String::from("description"),
+                },
+                pull_request_url: Some(Url::parse("https://example.com/pull")?),
+                draft: cycle == 0,
+            }])
+        });
+        github
+            .expect_get_pull_from_url()
+            .times(4)
+            .returning(move |_| {
+                Ok(PullRequest {
+                    draft: cycle.load(std::sync::atomic::Ordering::SeqCst) == 1,
+                    ..pull_request("request-123")
+                })
+            });
+        kanbanize
+            .expect_card_id_from_branch()
+            .returning(|_| Some(String::from("123")));
+        kanbanize.expect_find_by_id().times(4).returning(|_| {
+            Ok(Card {
+                id: String::from("123"),
+                description: String::from("description"),
+                column: Some(String::from("5")),
+                column_name: Some(String::from("In Progress")),
+                ..Default::default()
+            })
+        });
+        // Drafts are synced, the body is written once
+        github
+            .expect_update_pull_request()
+            .times(1)
+            .returning(|_, _| Ok(pull_request("request-123")));
+        // The first move fails and is retried on the next cycle only
+        let mut moves = 0;
+        kanbanize
+            .expect_move_card()
+            .with(
+                mockall::predicate::eq("123"),
+                mockall::predicate::eq("In Review"),
+            )
+            .times(2)
+            .returning(move |_, _| {
+                moves += 1;
+                match moves {
+                    1 => Err("unavailable".into()),
+                    _ => Ok(()),
+                }
+            });
+
+        let metrics = Arc::new(Metrics::default());
+        let logic = Service::new(
+            Box::new(github),
+            Router::single(Box::new(kanbanize)),
+            "owner".to_string(),
+        )
+        .with_repositories(vec![RepositoryConfig {
+            repository: String::from("owner/repo"),
+            ready_column: Some(String::from("In Review")),
+            ..Default::default()
+        }])
+        .with_metrics(metrics.clone());
+        for _ in 0..4 {
+            logic.process(&CancellationToken::new()).await?;
+        }
+        assert_eq!(metrics.totals().failed, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_sync_subtasks() -> testresult::TestResult {
+        let mut github = MockGithubApi::new();
+        let mut kanbanize = MockKanbanizeApi::new();
+        let pull = pull_request("request-123");
+        let subtask = |id: &str, finished| crate::kanbanize::Subtask {
+            id: id.to_string(),
+            description: format!("Subtask {}", id),
+            finished,
+        };
+        let card_subtasks = vec![subtask("1", false), subtask("2", true)];
+        // The first subtask was ticked on GitHub
+        let body = format!("description\n\n{}", subtasks::render(&card_subtasks))
+            .replace("- [ ] Subtask 1", "- [x] Subtask 1");
+        github.expect_get_issues().return_once(move || {
+            Ok(vec![Issue {
+                url: Url::parse("https://example.com/issue")?,
+                body,
+                pull_request_url: Some(Url::parse("https://example.com/pull")?),
+                draft: false,
+            }])
+        });
+        let pull1 = pull.clone();
+        github
+            .expect_get_pull_from_url()
+            .return_once(move |_| Ok(pull1));
+        kanbanize
+            .expect_card_id_from_branch()
+            .return_once(|_| Some(String::from("123")));
+        let card = Card {
+            id: String::from("123"),
+            description: String::from("description"),
+            subtasks: card_subtasks,
+            ..Default::default()
+        };
+        kanbanize.expect_find_by_id().return_once(|_| Ok(card));
+        kanbanize
+            .expect_complete_subtask()
+            .with(mockall::predicate::eq("123"), mockall::predicate::eq("1"))
+            .times(1)
+            .return_once(|_, _| Ok(()));
+        let expected = format!(
+            "description\n\n{}",
+            subtasks::render(&[subtask("1", true), subtask("2", true)])
+        );
+        github
+            .expect_update_pull_request()
+            .with(
+                mockall::predicate::always(),
+                mockall::predicate::eq(PullRequestUpdate {
+                    body: Some(expected),
+                    ..Default::default()
+                }),
+            )
+            .return_once(|_, _| Ok(pull));
+
+        let metrics = Arc::new(Metrics::default());
+        let logic = Service::new(
+            Box::new(github),
+            Router::single(Box::new(kanbanize)),
+            "owner".to_string(),
+        )
+        .with_repositories(vec![RepositoryConfig {
+            repository: String::from("owner/repo"),
+            sync_subtasks: true,
+            ..Default::default()
+        }])
+        .with_metrics(metrics.clone());
+        logic.process(&CancellationToken::new()).await?;
+        assert!(metrics
+            .render()?
+            .contains("gitkban_pull_requests_updated_total 1"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_card_body() {
+        let mut card = Card {
+            url: Some(String::from("https://example.com/card")),
+            subtasks: vec![Subtask {
+                id: String::from("1"),
+                description: String::from("Write tests"),
+                finished: false,
+            }],
+            ..Default::default()
+        };
+        let section = subtasks::render(&card.subtasks);
+        assert_eq!(
+            card_body(&card, "Short", &[], &[]),
+            format!("Short\n\n{}", section)
+        );
+        let related = vec![Related {
+            relation: Relation::Parent,
+            card: Card {
+                id: String::from("12"),
+                title: String::from("Epic"),
+                ..Default::default()
+            },
+            parent: None,
+        }];
+        assert_eq!(
+            card_body(&card, "Short", &[], &related),
+            format!(
+                "Short\n\n### Related cards\n- Parent: 12 Epic\n\n{}",
+                section
+            )
+        );
+
+        let log = format!("```\n{}```", "error: timeout\n".repeat(2_500));
+        let description = format!("Steps\n\n{}\n\n{}", log, log);
+        let body = card_body(&card, &description, &[], &[]);
+        assert!(body.chars().count() <= BODY_LIMIT);
+        assert_eq!(
+            body,
+            format!(
+                "Steps\n\n{}\n\n*The card is too long for a pull request, read the rest on [the card](https://example.com/card).*\n\n{}",
+                log, section
+            )
+        );
+
+        // Sections too long on their own are cut, or left out for subtasks
+        let many = |count: usize| -> Vec<Related> {
+            (0..count)
+                .map(|id| Related {
+                    relation: Relation::Relative,
+                    card: Card {
+                        id: id.to_string(),
+                        title: "Related card with a long title".repeat(4),
+                        ..Default::default()
+                    },
+                    parent: None,
+                })
+                .collect()
+        };
+        let body = card_body(&card, "Short", &[], &many(1_000));
+        assert!(body.chars().count() <= BODY_LIMIT);
+        assert!(body.starts_with("Short\n\n*The card is too long"));
+        assert!(body.contains("### Related cards\n- Related: 0 "));
+        assert!(body.ends_with(&section));
+        card.subtasks = (0..3_000)
+            .map(|id| Subtask {
+                id: id.to_string(),
+                description: String::from("Write more tests"),
+                finished: false,
+            })
+            .collect();
+        let body = card_body(&card, "Short", &[], &[]);
+        assert!(body.chars().count() <= BODY_LIMIT);
+        assert_eq!(
+            body,
+            "Short\n\n*The card is too long for a pull request, read the rest on [the card](https://example.com/card).*"
+        );
+
+        card.url = None;
+        card.subtasks.clear();
+        assert!(card_body(&card, &description, &[], &[]).ends_with("read the rest on the card.*"));
+    }
+
+    #[tokio::test]
+    async fn test_process_rejected() -> testresult::TestResult {
+        let mut github = MockGithubApi::new();
+        let mut kanbanize = MockKanbanizeApi::new();
+        let pull = pull_request("request-123");
+        github.expect_get_issues().times(2).returning(|| {
+            Ok(vec![Issue {
+                url: Url::parse("https://example.com/issue")?,
+                body: String::new(),
+                pull_request_url: Some(Url::parse("https://example.com/pull")?),
+                draft: false,
+            }])
+        });
+        github
+            .expect_get_pull_from_url()
+            .times(2)
+            .returning(move |_| Ok(pull.clone()));
+        kanbanize
+            .expect_card_id_from_branch()
+            .returning(|_| Some(String::from("123")));
+        kanbanize.expect_find_by_id().times(2).returning(|_| {
+            Ok(Card {
+                id: String::from("123"),
+                title: String::from("Card title"),
+                description: String::from("description"),
+                ..Default::default()
+            })
+        });
+        // Sent once, the same body isn't retried on the next cycle
+        github
+            .expect_update_pull_request()
+            .times(1)
+            .returning(|_, _| {
+                Err(crate::error::StatusError {
+                    status: 422,
+                    message: String::from("Validation Failed"),
+                }
+                .into())
+            });
+
+        let metrics = Arc::new(Metrics::default());
+        let logic = Service::new(
+            Box::new(github),
+            Router::single(Box::new(kanbanize)),
+            "owner".to_string(),
+        )
+        .with_metrics(metrics.clone());
+        logic.process(&CancellationToken::new()).await?;
+        logic.process(&CancellationToken::new()).await?;
+
+        let output = metrics.render()?;
+        assert!(output.contains(r#"gitkban_pull_requests_skipped_total{reason="rejected"} 2"#));
+        assert!(!output.contains("gitkban_pull_requests_failed_total 1"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_default_title() {
+        assert!(is_default_title("Request 123", "request-123"));
+        assert!(is_default_title("Fix login page", "fix_login-page"));
+        assert!(is_default_title("request-123", "request-123"));
+        assert!(!is_default_title("Add the login page", "request-123"));
+    }
+
+    #[tokio::test]
+    async fn test_process_routed_tracker() -> testresult::TestResult {
+        let mut github = MockGithubApi::new();
+        let default_tracker = MockKanbanizeApi::new();
+        let mut eu_tracker = MockKanbanizeApi::new();
+        let pull = PullRequest {
+            repository: String::from("owner/payments"),
+            ..pull_request("request-123")
+        };
+        github.expect_get_issues().return_once(|| {
+            Ok(vec![Issue {
+                url: Url::parse("https://example.com/issue")?,
+                body: String::default(),
+                pull_request_url: Some(Url::parse("https://example.com/pull")?),
+                draft: false,
+            }])
+        });
+
+        let pull1 = pull.clone();
+        github
+            .expect_get_pull_from_url()
+            .return_once(move |_| Ok(pull1));
+
+        eu_tracker
+            .expect_card_id_from_branch()
+            .return_once(|_| Some(String::from("123")));
+        eu_tracker
+            .expect_find_by_id()
+            .with(mockall::predicate::eq("123"))
+            .return_once(|_| {
+                Ok(Card {
+                    id: String::from("123"),
+                    custom_id: None,
+                    title: String::from("Card title"),
+                    description: String::from("eu description"),
+                    markup: Markup::Html,
+                    ..Default::default()
+                })
+            });
+
+        github
+            .expect_update_pull_request()
+            .with(
+                mockall::predicate::always(),
+                mockall::predicate::eq(PullRequestUpdate {
+                    body: Some(String::from("eu description")),
+                    ..Default::default()
+                }),
+            )
+            .return_once(|_, _| Ok(pull));
+
+        let mut trackers = Router::new(Some("default".to_string()));
+        trackers.add_tracker("default", Box::new(default_tracker));
+        trackers.add_tracker("eu", Box::new(eu_tracker));
+        trackers.add_route(Route {
+            repository: Some(String::from("owner/payments")),
+            branch_prefix: None,
+            tracker: String::from("eu"),
+        });
+
+        let logic = Service::new(Box::new(github), trackers, "owner".to_string());
+        let result = logic.process(&CancellationToken::new()).await;
+        assert!(result.is_ok(), "result error: {:?}", result.err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_after_shutdown() -> testresult::TestResult {
+        let mut github = MockGithubApi::new();
+        let kanbanize = MockKanbanizeApi::new();
+        github.expect_get_issues().return_once(|| {
+            Ok(vec![Issue {
+                url: Url::parse("https://example.com/issue")?,
+                body: String::default(),
+                pull_request_url: Some(Url::parse("https://example.com/pull")?),
+                draft: false,
+            }])
+        });
+
+        let logic = Service::new(
+            Box::new(github),
+            Router::single(Box::new(kanbanize)),
+            "owner".to_string(),
+        );
+        let shutdown = CancellationToken::new();
+        shutdown.cancel();
+        // No pull request is fetched once shutdown started
+        logic.process(&shutdown).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_run_stops_on_shutdown() {
+        let github = MockGithubApi::new();
+        let kanbanize = MockKanbanizeApi::new();
+
+        let logic = Service::new(
+            Box::new(github),
+            Router::single(Box::new(kanbanize)),
+            "owner".to_string(),
+        );
+        let shutdown = CancellationToken::new();
+        shutdown.cancel();
+        logic.run(shutdown).await;
+    }
+
+    #[tokio::test]
+    async fn test_check() {
+        let mut github = MockGithubApi::new();
+        let mut kanbanize = MockKanbanizeApi::new();
+        github.expect_check().return_once(|| Ok(()));
+        kanbanize
+            .expect_check()
+            .return_once(|| Err("bad api key".into()));
+
+        let logic = Service::new(
+            Box::new(github),
+            Router::single(Box::new(kanbanize)),
+            "owner".to_string(),
+        );
+        assert_eq!(logic.check().await, Err("default: bad api key".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_process_metrics() -> testresult::TestResult {
+        let mut github = MockGithubApi::new();
+        let kanbanize = MockKanbanizeApi::new();
+        github.expect_get_issues().return_once(|| {
+            Ok(vec![Issue {
+                url: Url::parse("https://example.com/issue")?,
+                body: String::from("existing"),
+                pull_request_url: Some(Url::parse("https://example.com/pull")?),
+                draft: false,
+            }])
+        });
+
+        let metrics = Arc::new(Metrics::default());
+        let logic = Service::new(
+            Box::new(github),
+            Router::single(Box::new(kanbanize)),
+            "owner".to_string(),
+        )
+        .with_metrics(metrics.clone());
+        logic.process(&CancellationToken::new()).await?;
+
+        let output = metrics.render()?;
+        assert!(output.contains("gitkban_pull_requests_scanned_total 1"));
+        assert!(output.contains(r#"gitkban_pull_requests_skipped_total{reason="has_body"} 1"#));
+        Ok(())
+    }
 }
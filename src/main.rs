@@ -1,38 +1,242 @@
+use std::sync::Arc;
+
+use clap::{Parser, Subcommand};
+use tokio_util::sync::CancellationToken;
+
+mod attachments;
+mod audit;
+mod cache;
+mod config;
+mod credentials;
+mod error;
+mod fields;
 mod github;
+mod health;
 mod kanbanize;
+mod labels;
+mod linear;
 mod logic;
+mod markdown;
+mod metrics;
+mod redact;
+mod related;
+mod report;
+mod router;
+mod server;
+mod settings;
+mod subtasks;
+mod telemetry;
+mod trello;
+mod users;
+
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Log card content, API responses and credentials without redaction
+    #[arg(long)]
+    log_sensitive: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Validate the settings and credentials against the APIs, then exit
+    #[command(alias = "doctor")]
+    Check,
+    /// Print a report, then exit
+    Report {
+        #[command(subcommand)]
+        report: Report,
+    },
+    /// Show the body changes of a pull request recorded in the audit log, then exit
+    History {
+        /// Address of the pull request
+        url: String,
+    },
+    /// Restore the body a pull request had before a recorded change, then exit
+    Revert {
+        /// Address of the pull request
+        url: String,
+        /// Change to undo, as numbered by `history`, the latest by default
+        #[arg(long)]
+        entry: Option<usize>,
+        /// Revert even if the body was edited since the latest recorded change
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum Report {
+    /// Cards in the review columns that no open pull request references
+    Orphans {
+        #[arg(long, value_enum, default_value_t)]
+        format: report::Format,
+    },
+}
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Load env vars
-    dotenv::dotenv().ok().unwrap();
+    let cli = Cli::parse();
+    redact::set_log_sensitive(cli.log_sensitive);
+
+    // Load env vars, the .env file is optional
+    dotenv::dotenv().ok();
+    let metrics = Arc::new(metrics::Metrics::default());
+    let settings = match settings::Settings::from_env(&metrics) {
+        Ok(settings) => settings,
+        Err(problems) => {
+            eprintln!("{}", problems);
+            std::process::exit(1);
+        }
+    };
 
     // construct a subscriber that prints formatted traces to stdout
-    let subscriber = tracing_subscriber::FmtSubscriber::builder()
-        .with_max_level(tracing::Level::INFO)
-        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
-        .finish();
-    tracing::subscriber::set_global_default(subscriber).unwrap();
+    telemetry::init(settings.log_format)?;
 
     // Load services
-    let kanbanize = kanbanize::Kanbanize::new(
-        std::env::var("KANBANIZE_BASE_PATH")
-            .expect("KANBANIZE_BASE_PATH must be set")
-            .as_str(),
-        std::env::var("KANBANIZE_API_KEY")
-            .expect("KANBANIZE_API_KEY must be set")
-            .as_str(),
+    let github = metrics::MeteredGithub::new(
+        github::Github::new(settings.github_token, settings.track_user),
+        metrics.clone(),
     );
-    let github = github::Github::new(
-        std::env::var("GITHUB_PERSONAL_TOKEN").expect("GITHUB_PERSONAL_TOKEN must be set"),
-        std::env::var("GITHUB_TRACK_USER").expect("GITHUB_TRACK_USER must be set"),
+    let mut service = logic::Service::new(github, settings.trackers, settings.owner_filter)
+        .with_metrics(metrics.clone())
+        .with_repositories(settings.repositories)
+        .with_users(settings.users);
+    let audit = settings.audit_log.as_deref().map(audit::AuditLog::new);
+    if let Some(audit) = &audit {
+        service = service.with_audit(audit.clone());
+    }
+
+    match cli.command {
+        Some(Command::Check) => {
+            std::process::exit(if check(&service).await { 0 } else { 1 });
+        }
+        Some(Command::Report {
+            report: Report::Orphans { format },
+        }) => {
+            if settings.review_columns.is_empty() {
+                eprintln!("No review columns configured, set `review_columns` in GITKBAN_CONFIG");
+                std::process::exit(1);
+            }
+            let orphans = service.orphans(&settings.review_columns).await?;
+            println!("{}", report::render(&orphans, format)?);
+            return Ok(());
+        }
+        Some(Command::History { url }) => {
+            let Some(audit) = &audit else {
+                eprintln!("No audit log configured, set `audit_log` in GITKBAN_CONFIG");
+                std::process::exit(1);
+            };
+            let entries = audit.history(&url)?;
+            if entries.is_empty() {
+                println!("No recorded change for {}", url);
+            } else {
+                println!("{}", audit::render(&entries));
+            }
+            return Ok(());
+        }
+        Some(Command::Revert { url, entry, force }) => {
+            service.revert(&url, entry, force).await?;
+            println!("Restored the previous body of {}", url);
+            return Ok(());
+        }
+        None => {}
+    }
+
+    let health = Arc::new(health::Health::new(
+        metrics.clone(),
+        logic::POLL_INTERVAL * settings.health_max_missed_cycles,
+    ));
+    if let Some(addr) = settings.http_addr {
+        tokio::spawn(server::serve(addr, metrics.clone(), health.clone()));
+    }
+
+    // Check credentials before starting
+    let readiness = service.check().await;
+    if let Err(e) = &readiness {
+        tracing::error!("Startup checks failed: {}", e);
+    }
+    health.set_ready(readiness);
+
+    // Run process until a shutdown signal is received
+    let shutdown = CancellationToken::new();
+    let run = service.run(shutdown.clone());
+    tokio::pin!(run);
+    tokio::select! {
+        _ = &mut run => {}
+        _ = shutdown_signal() => {
+            tracing::info!("Shutdown requested, finishing in-flight work");
+            health.set_ready(Err("shutting down".to_string()));
+            shutdown.cancel();
+            if tokio::time::timeout(settings.shutdown_timeout, &mut run).await.is_err() {
+                tracing::warn!(
+                    "In-flight work did not finish within {}s, exiting anyway",
+                    settings.shutdown_timeout.as_secs()
+                );
+            }
+        }
+    }
+
+    let totals = metrics.totals();
+    tracing::info!(
+        "Stopped after scanning {} pull requests: {} updated, {} skipped, {} failed",
+        totals.scanned,
+        totals.updated,
+        totals.skipped,
+        totals.failed
     );
+    telemetry::shutdown();
+    Ok(())
+}
+
+/// Prints the result of every startup check, returning whether all passed.
+async fn check(service: &logic::Service) -> bool {
+    println!("settings: ok");
+    let mut failed = 0;
+    for (name, result) in service.checks().await {
+        match result {
+            Ok(()) => println!("{}: ok", name),
+            Err(e) => {
+                failed += 1;
+                // Some client errors append a backtrace after the message
+                println!("{}: FAILED, {}", name, e.lines().next().unwrap_or_default());
+            }
+        }
+    }
+
+    if failed == 0 {
+        println!("All checks passed");
+    } else {
+        println!(
+            "{} check(s) failed, fix the credentials or permissions above and run `gitkban check` again",
+            failed
+        );
+    }
+    failed == 0
+}
+
+/// Resolves on Ctrl-C or, on Unix, SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to listen for Ctrl-C");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
 
-    let owner_filter: String =
-        std::env::var("GITHUB_OWNER_FILTER").expect("GITHUB_OWNER_FILTER must be set");
-    // Run process
-    logic::Service::new(github, kanbanize, owner_filter)
-        .run()
-        .await;
-    return Ok(());
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
 }
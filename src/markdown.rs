@@ -0,0 +1,609 @@
+use html5ever::{tendril::TendrilSink, ParseOpts};
+use markup5ever_rcdom::{Handle, NodeData, RcDom};
+
+/// Elements dropped with their content, everything else is either converted
+/// or replaced by its content.
+const DROPPED: &[&str] = &[
+    "script", "style", "head", "title", "iframe", "object", "embed", "noscript", "template", "svg",
+    "math", "form", "input", "button", "select", "textarea", "canvas", "audio", "video",
+];
+
+/// Elements converted to markdown blocks.
+const BLOCKS: &[&str] = &[
+    "p",
+    "div",
+    "section",
+    "article",
+    "header",
+    "footer",
+    "main",
+    "aside",
+    "nav",
+    "center",
+    "figure",
+    "figcaption",
+    "address",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "ul",
+    "ol",
+    "pre",
+    "blockquote",
+    "table",
+    "hr",
+    "dl",
+    "dt",
+    "dd",
+];
+
+/// URL schemes kept in links and images.
+const ALLOWED_SCHEMES: &[&str] = &["http", "https", "mailto"];
+
+/// Converts card HTML to GitHub flavored markdown. Only an allow-list of
+/// elements is converted, scripts and styles are dropped, and mentions are
+/// neutralized so the card text never notifies GitHub users.
+pub fn from_html(html: &str) -> String {
+    let dom = html5ever::parse_document(RcDom::default(), ParseOpts::default()).one(html);
+    let children = dom.document.children.borrow();
+    blocks(&children).join("\n\n")
+}
+
+/// Stops `@name` from mentioning GitHub users or teams in markdown, leaving
+/// code blocks and code spans untouched.
+pub fn neutralize_mentions(markdown: &str) -> String {
+    let mut fence: Option<String> = None;
+    let mut lines = Vec::new();
+    for line in markdown.split('\n') {
+        let trimmed = line.trim_start();
+        match &fence {
+            Some(open) => {
+                if trimmed.starts_with(open.as_str()) {
+                    fence = None;
+                }
+                lines.push(line.to_string());
+            }
+            None if trimmed.starts_with("```") || trimmed.starts_with("~~~") => {
+                let marker = trimmed.chars().next().unwrap_or('`');
+                fence = Some(trimmed.chars().take_while(|c| *c == marker).collect());
+                lines.push(line.to_string());
+            }
+            None => lines.push(neutralize_outside_code_spans(line)),
+        }
+    }
+    lines.join("\n")
+}
+
+fn neutralize_outside_code_spans(line: &str) -> String {
+    let mut result = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(start) = rest.find('`') {
+        let ticks = rest[start..].chars().take_while(|c| *c == '`').count();
+        let fence = &rest[start..start + ticks];
+        let Some(end) = rest[start + ticks..].find(fence) else {
+            break;
+        };
+        let end = start + ticks + end + ticks;
+        result.push_str(&neutralize(&rest[..start]));
+        result.push_str(&rest[start..end]);
+        rest = &rest[end..];
+    }
+    result.push_str(&neutralize(rest));
+    result
+}
+
+/// Inserts a zero width space after every `@` that would start a mention.
+fn neutralize(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut previous: Option<char> = None;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        result.push(c);
+        let starts_word = !previous.is_some_and(|p| p.is_alphanumeric() || p == '_' || p == '`');
+        if c == '@' && starts_word && chars.peek().is_some_and(|n| n.is_ascii_alphanumeric()) {
+            result.push_str("&#8203;");
+        }
+        previous = Some(c);
+    }
+    result
+}
+
+fn element_name(node: &Handle) -> Option<&str> {
+    match &node.data {
+        NodeData::Element { name, .. } => Some(&name.local),
+        _ => None,
+    }
+}
+
+fn attribute(node: &Handle, attribute: &str) -> Option<String> {
+    match &node.data {
+        NodeData::Element { attrs, .. } => attrs
+            .borrow()
+            .iter()
+            .find(|attr| &*attr.name.local == attribute)
+            .map(|attr| attr.value.to_string()),
+        _ => None,
+    }
+}
+
+/// Value of a URL attribute, if it uses an allowed scheme.
+fn url_attribute(node: &Handle, name: &str) -> Option<String> {
+    let value = attribute(node, name)?;
+    let url = url::Url::parse(value.trim()).ok()?;
+    // Parentheses would end the markdown link early
+    ALLOWED_SCHEMES
+        .contains(&url.scheme())
+        .then(|| url.to_string().replace('(', "%28").replace(')', "%29"))
+}
+
+fn is_block(node: &Handle) -> bool {
+    element_name(node).is_some_and(|name| BLOCKS.contains(&name))
+}
+
+fn is_dropped(node: &Handle) -> bool {
+    match &node.data {
+        NodeData::Element { name, .. } => DROPPED.contains(&&*name.local),
+        NodeData::Text { .. } => false,
+        _ => true,
+    }
+}
+
+/// Renders a sequence of nodes as markdown blocks, grouping consecutive
+/// inline nodes into paragraphs.
+fn blocks(nodes: &[Handle]) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut paragraph = String::new();
+    for node in nodes.iter().filter(|node| !is_dropped(node)) {
+        if is_block(node) {
+            result.extend(paragraphs(&std::mem::take(&mut paragraph)));
+            result.extend(block(node));
+        } else if matches!(node.data, NodeData::Element { .. } | NodeData::Text { .. })
+            && !matches!(element_name(node), Some("html" | "body"))
+        {
+            paragraph.push_str(&inline(node));
+        } else {
+            // The document, `html` and `body` wrap the actual content
+            result.extend(paragraphs(&std::mem::take(&mut paragraph)));
+            result.extend(blocks(&node.children.borrow()));
+        }
+    }
+    result.extend(paragraphs(&paragraph));
+    result
+}
+
+/// Splits inline content on blank lines, which come from consecutive line
+/// breaks, and joins the remaining lines with hard breaks.
+fn paragraphs(inline: &str) -> Vec<String> {
+    let lines: Vec<String> = inline.split('\n').map(collapse).collect();
+    lines
+        .split(|line| line.is_empty())
+        .filter(|lines| !lines.is_empty())
+        .map(|lines| lines.join("\\\n"))
+        .collect()
+}
+
+fn collapse(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn block(node: &Handle) -> Vec<String> {
+    let children = node.children.borrow();
+    match element_name(node).unwrap_or_default() {
+        heading @ ("h1" | "h2" | "h3" | "h4" | "h5" | "h6") => {
+            let level = heading[1..].parse().unwrap_or(1);
+            let text = collapse(&inline_children(node).replace('\n', " "));
+            if text.is_empty() {
+                vec![]
+            } else {
+                vec![format!("{} {}", "#".repeat(level), text)]
+            }
+        }
+        "ul" => vec![list(node, None)],
+        "ol" => {
+            let start = attribute(node, "start")
+                .and_then(|start| start.parse().ok())
+                .unwrap_or(1);
+            vec![list(node, Some(start))]
+        }
+        "pre" => vec![code_block(node)],
+        "blockquote" => {
+            let content = blocks(&children).join("\n\n");
+            vec![content
+                .lines()
+                .map(|line| {
+                    if line.is_empty() {
+                        ">".to_string()
+                    } else {
+                        format!("> {}", line)
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("\n")]
+        }
+        "table" => vec![table(node)],
+        "hr" => vec!["---".to_string()],
+        _ => blocks(&children),
+    }
+    .into_iter()
+    .filter(|block| !block.is_empty())
+    .collect()
+}
+
+fn inline_children(node: &Handle) -> String {
+    node.children
+        .borrow()
+        .iter()
+        .filter(|child| !is_dropped(child))
+        .map(inline)
+        .collect()
+}
+
+/// Renders a node as inline markdown, line breaks are kept as `\n`.
+fn inline(node: &Handle) -> String {
+    let name = match &node.data {
+        NodeData::Text { contents } => return escape(&contents.borrow()),
+        NodeData::Element { name, .. } => &*name.local,
+        _ => return String::new(),
+    };
+    match name {
+        "br" => "\n".to_string(),
+        "strong" | "b" => emphasis(&inline_children(node), "**"),
+        "em" | "i" => emphasis(&inline_children(node), "*"),
+        "del" | "s" | "strike" => emphasis(&inline_children(node), "~~"),
+        "code" | "kbd" | "samp" | "tt" => code_span(&collapse(&text_content(node))),
+        "a" => {
+            let text = collapse(&inline_children(node).replace('\n', " "));
+            match url_attribute(node, "href") {
+                Some(href) if text.is_empty() => format!("<{}>", href),
+                Some(href) => format!("[{}]({})", text, href),
+                None => text,
+            }
+        }
+        "img" => match url_attribute(node, "src") {
+            Some(src) => {
+                let alt = escape(&collapse(&attribute(node, "alt").unwrap_or_default()));
+                format!("![{}]({})", alt, src)
+            }
+            None => String::new(),
+        },
+        _ if is_block(node) => format!("\n{}\n", inline_children(node)),
+        _ => inline_children(node),
+    }
+}
+
+/// Wraps text in emphasis markers, keeping the surrounding spaces outside
+/// since markdown ignores `** bold **`.
+fn emphasis(text: &str, marker: &str) -> String {
+    let trimmed = text.trim();
+    if trimmed.is_empty() {
+        return text.to_string();
+    }
+    let leading = &text[..text.len() - text.trim_start().len()];
+    let trailing = &text[text.trim_end().len()..];
+    format!("{}{}{}{}{}", leading, marker, trimmed, marker, trailing)
+}
+
+fn code_span(code: &str) -> String {
+    if code.is_empty() {
+        return String::new();
+    }
+    let fence = "`".repeat(longest_run(code, '`') + 1);
+    // Spaces keep backticks at the edges apart from the fence
+    if code.starts_with('`') || code.ends_with('`') {
+        format!("{} {} {}", fence, code, fence)
+    } else {
+        format!("{}{}{}", fence, code, fence)
+    }
+}
+
+fn longest_run(text: &str, c: char) -> usize {
+    text.split(|other| other != c)
+        .map(str::len)
+        .max()
+        .unwrap_or(0)
+}
+
+/// Raw text of a node, with line breaks as `\n`.
+fn text_content(node: &Handle) -> String {
+    match &node.data {
+        NodeData::Text { contents } => contents.borrow().replace('\u{a0}', " "),
+        NodeData::Element { name, .. } if &*name.local == "br" => "\n".to_string(),
+        NodeData::Element { .. } if is_dropped(node) => String::new(),
+        NodeData::Element { .. } => {
+            let text: String = node.children.borrow().iter().map(text_content).collect();
+            // Some editors wrap every line of a code block in its own element
+            match element_name(node) {
+                Some("div" | "p") if !text.ends_with('\n') => format!("{}\n", text),
+                _ => text,
+            }
+        }
+        _ => String::new(),
+    }
+}
+
+fn code_block(node: &Handle) -> String {
+    let language = std::iter::once(node.clone())
+        .chain(node.children.borrow().iter().cloned())
+        .filter_map(|node| attribute(&node, "class"))
+        .flat_map(|class| {
+            class
+                .split_whitespace()
+                .filter_map(|class| {
+                    class
+                        .strip_prefix("language-")
+                        .or_else(|| class.strip_prefix("lang-"))
+                })
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+        })
+        .next()
+        .unwrap_or_default();
+    let code = text_content(node);
+    let code = code.strip_prefix('\n').unwrap_or(&code).trim_end();
+    let fence = "`".repeat(3.max(longest_run(code, '`') + 1));
+    format!("{}{}\n{}\n{}", fence, language, code, fence)
+}
+
+fn list(node: &Handle, start: Option<u64>) -> String {
+    let items: Vec<Handle> = node
+        .children
+        .borrow()
+        .iter()
+        .filter(|child| element_name(child) == Some("li"))
+        .cloned()
+        .collect();
+    // Items with paragraphs make the whole list loose
+    let loose = items.iter().any(|item| {
+        item.children
+            .borrow()
+            .iter()
+            .any(|child| element_name(child) == Some("p"))
+    });
+    let separator = if loose { "\n\n" } else { "\n" };
+    items
+        .iter()
+        .enumerate()
+        .map(|(index, item)| {
+            let marker = match start {
+                Some(start) => format!("{}. ", start + index as u64),
+                None => "- ".to_string(),
+            };
+            let content = blocks(&item.children.borrow()).join(separator);
+            let indent = " ".repeat(marker.len());
+            let mut lines = content.lines();
+            let first = lines.next().unwrap_or_default();
+            std::iter::once(format!("{}{}", marker, first).trim_end().to_string())
+                .chain(lines.map(|line| {
+                    if line.is_empty() {
+                        String::new()
+                    } else {
+                        format!("{}{}", indent, line)
+                    }
+                }))
+                .collect::<Vec<_>>()
+                .join("\n")
+        })
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+fn table(node: &Handle) -> String {
+    let mut rows = Vec::new();
+    collect_rows(node, &mut rows);
+    let rows: Vec<Vec<String>> = rows
+        .iter()
+        .map(|row| {
+            row.children
+                .borrow()
+                .iter()
+                .filter(|cell| matches!(element_name(cell), Some("td" | "th")))
+                .map(|cell| {
+                    let lines: Vec<String> = inline_children(cell)
+                        .split('\n')
+                        .map(collapse)
+                        .filter(|line| !line.is_empty())
+                        .collect();
+                    lines.join("<br>").replace('|', "\\|")
+                })
+                .collect()
+        })
+        .filter(|row: &Vec<String>| !row.is_empty())
+        .collect();
+    let Some(width) = rows.iter().map(Vec::len).max() else {
+        return String::new();
+    };
+
+    let line = |cells: &[String]| {
+        let cells: Vec<&str> = (0..width)
+            .map(|i| cells.get(i).map_or("", String::as_str))
+            .collect();
+        format!("| {} |", cells.join(" | "))
+    };
+    let mut lines = vec![line(&rows[0]), line(&vec!["---".to_string(); width])];
+    lines.extend(rows[1..].iter().map(|row| line(row)));
+    lines.join("\n")
+}
+
+/// Rows of a table, without descending into nested tables.
+fn collect_rows(node: &Handle, rows: &mut Vec<Handle>) {
+    for child in node.children.borrow().iter() {
+        match element_name(child) {
+            Some("tr") => rows.push(child.clone()),
+            Some("thead" | "tbody" | "tfoot") => collect_rows(child, rows),
+            _ => {}
+        }
+    }
+}
+
+/// Escapes text so it renders literally: markdown syntax is escaped, as are
+/// `<` and `>`, and mentions are neutralized. Text may start a line, so the
+/// markers of headings, lists and block quotes at its start are escaped too.
+fn escape(text: &str) -> String {
+    let entity = regex::Regex::new(r"^&(#?[A-Za-z0-9]+);").unwrap();
+    let block_marker = regex::Regex::new(r"^\s*(?:[#+=-]|\d{1,9}[.)])").unwrap();
+    // Byte offset of the marker character to escape, if any
+    let marker = block_marker.find(text).map(|found| found.end() - 1);
+    let mut result = String::with_capacity(text.len());
+    for (index, c) in text.char_indices() {
+        match c {
+            _ if Some(index) == marker => {
+                result.push('\\');
+                result.push(c);
+            }
+            '\\' | '*' | '_' | '`' | '[' | ']' | '~' => {
+                result.push('\\');
+                result.push(c);
+            }
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '&' if entity.is_match(&text[index..]) => result.push_str("&amp;"),
+            '\u{a0}' | '\n' | '\r' | '\t' => result.push(' '),
+            _ => result.push(c),
+        }
+    }
+    neutralize(&result)
+}
+
+/// Shortens `markdown` to at most `limit` characters, cutting after the last
+/// block that fits. A block too long on its own is cut after its last line
+/// that fits, closing the code block it is in. Returns `None` if it fits.
+pub fn truncate(markdown: &str, limit: usize) -> Option<String> {
+    if markdown.chars().count() <= limit {
+        return None;
+    }
+
+    let mut fence: Option<String> = None;
+    // Byte offsets of the last block and line ending within the limit, with
+    // the fence open at that line
+    let mut block_end = None;
+    let mut line_end = (0, None);
+    let mut length = 0;
+    let mut offset = 0;
+    for line in markdown.split_inclusive('\n') {
+        let content = line.trim_end_matches(['\r', '\n']);
+        let trimmed = content.trim_start();
+        // Room to close the code block on a new line
+        let closing = fence.as_ref().map_or(0, |open| open.len() + 1);
+        if length + content.chars().count() + closing > limit {
+            break;
+        }
+        if fence.is_none() && trimmed.is_empty() && offset > 0 {
+            block_end = Some(offset);
+        }
+        match &fence {
+            Some(open) if trimmed.starts_with(open.as_str()) => fence = None,
+            None if trimmed.starts_with("```") || trimmed.starts_with("~~~") => {
+                let marker = trimmed.chars().next().unwrap_or('`');
+                fence = Some(trimmed.chars().take_while(|c| *c == marker).collect());
+            }
+            _ => {}
+        }
+        length += line.chars().count();
+        offset += line.len();
+        line_end = (offset, fence.clone());
+    }
+
+    let truncated = match (block_end, line_end) {
+        (Some(end), _) => markdown[..end].trim_end().to_string(),
+        // Not even the first line fits
+        (None, (0, _)) => markdown.chars().take(limit).collect(),
+        (None, (end, Some(open))) => format!("{}\n{}", markdown[..end].trim_end(), open),
+        (None, (end, None)) => markdown[..end].trim_end().to_string(),
+    };
+    Some(truncated)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Converts every `testdata/markdown/*.html` file and compares the result
+    /// with the `.md` file next to it. Run with `UPDATE_GOLDEN=1` to rewrite
+    /// the expected files after a deliberate change.
+    #[test]
+    fn test_golden_files() -> testresult::TestResult {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/markdown");
+        let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+        let mut checked = 0;
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("html") {
+                continue;
+            }
+            let actual = from_html(&std::fs::read_to_string(&path)?) + "\n";
+            let golden = path.with_extension("md");
+            if update {
+                std::fs::write(&golden, &actual)?;
+            }
+            assert_eq!(
+                actual,
+                std::fs::read_to_string(&golden)?,
+                "{} doesn't match its golden file",
+                path.display()
+            );
+            checked += 1;
+        }
+        assert!(checked > 0, "no golden files in {}", dir.display());
+        Ok(())
+    }
+
+    #[test]
+    fn test_escape_block_markers() {
+        assert_eq!(escape("# Title"), "\\# Title");
+        assert_eq!(escape("  - item"), "  \\- item");
+        assert_eq!(escape("+ item"), "\\+ item");
+        assert_eq!(escape("> quote"), "&gt; quote");
+        assert_eq!(escape("1. first"), "1\\. first");
+        assert_eq!(escape("2) second"), "2\\) second");
+        assert_eq!(escape("2024 was - good"), "2024 was - good");
+        assert_eq!(
+            from_html("<p>Title</p><p># not a heading<br>- not a list</p>"),
+            "Title\n\n\\# not a heading\\\n\\- not a list"
+        );
+    }
+
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(
+            from_html(
+                r#"<p onclick="x()">Hi<script>alert(1)</script><style>p{}</style> <a href="javascript:alert(1)">there</a></p>"#
+            ),
+            "Hi there"
+        );
+    }
+
+    #[test]
+    fn test_entities() {
+        assert_eq!(
+            from_html("<p>a &lt;b&gt; &amp;amp; 1&nbsp;*2*</p>"),
+            r"a &lt;b&gt; &amp;amp; 1 \*2\*"
+        );
+    }
+
+    #[test]
+    fn test_neutralize_mentions() {
+        assert_eq!(
+            neutralize_mentions("Ping @jane and @acme/team, mail jane@example.com\n`@code` stays\n```\n@block\n```"),
+            "Ping @&#8203;jane and @&#8203;acme/team, mail jane@example.com\n`@code` stays\n```\n@block\n```"
+        );
+    }
+
+    #[test]
+    fn test_truncate() {
+        let markdown = "First paragraph\n\nSecond paragraph\n\nThird paragraph";
+        assert_eq!(truncate(markdown, 100), None);
+        assert_eq!(
+            truncate(markdown, 40),
+            Some("First paragraph\n\nSecond paragraph".to_string())
+        );
+
+        // A long code block is cut between lines and closed
+        let log = format!("```\n{}```", "log line\n".repeat(10));
+        let truncated = truncate(&log, 40).unwrap();
+        assert_eq!(truncated, "```\nlog line\nlog line\nlog line\n```");
+        assert!(truncated.chars().count() <= 40);
+    }
+}
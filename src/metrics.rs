@@ -0,0 +1,582 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use prometheus::{
+    Encoder, Gauge, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, Opts,
+    Registry, TextEncoder,
+};
+use url::Url;
+
+use crate::{
+    error::status_of,
+    github::{Comment, CommitStatus, GithubApi, Issue, PullRequest, PullRequestUpdate},
+    kanbanize::{Card, KanbanizeApi, User},
+};
+
+type Error = Box<dyn std::error::Error>;
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Pull request counts since the process started.
+#[derive(Debug, PartialEq)]
+pub struct Totals {
+    pub scanned: u64,
+    pub updated: u64,
+    pub skipped: u64,
+    pub failed: u64,
+}
+
+pub struct Metrics {
+    registry: Registry,
+    cycle_duration: Histogram,
+    seconds_since_success: Gauge,
+    /// Reference for the time since success until a cycle succeeds.
+    started: Instant,
+    last_cycle: Mutex<Option<Instant>>,
+    last_success: Mutex<Option<Instant>>,
+    pulls_scanned: IntCounter,
+    pulls_skipped: IntCounterVec,
+    pulls_updated: IntCounter,
+    pulls_failed: IntCounter,
+    users_unmapped: IntCounter,
+    requests: HistogramVec,
+    cache_lookups: IntCounterVec,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        let registry = Registry::new_custom(Some("gitkban".to_string()), None).unwrap();
+        let cycle_duration = Histogram::with_opts(HistogramOpts::new(
+            "cycle_duration_seconds",
+            "Duration of a full processing cycle",
+        ))
+        .unwrap();
+        let seconds_since_success = Gauge::new(
+            "seconds_since_last_successful_cycle",
+            "Seconds since the last cycle that finished without error",
+        )
+        .unwrap();
+        let pulls_scanned =
+            IntCounter::new("pull_requests_scanned_total", "Pull requests scanned").unwrap();
+        let pulls_skipped = IntCounterVec::new(
+            Opts::new("pull_requests_skipped_total", "Pull requests skipped"),
+            &["reason"],
+        )
+        .unwrap();
+        let pulls_updated =
+            IntCounter::new("pull_requests_updated_total", "Pull requests updated").unwrap();
+        let pulls_failed = IntCounter::new(
+            "pull_requests_failed_total",
+            "Pull requests that failed to process",
+        )
+        .unwrap();
+        let users_unmapped =
+            IntCounter::new("users_unmapped_total", "Card users without a GitHub login").unwrap();
+        let requests = HistogramVec::new(
+            HistogramOpts::new("api_request_duration_seconds", "Latency of API requests"),
+            &["api", "operation", "status"],
+        )
+        .unwrap();
+
+        registry.register(Box::new(cycle_duration.clone())).unwrap();
+        registry
+            .register(Box::new(seconds_since_success.clone()))
+            .unwrap();
+        registry.register(Box::new(pulls_scanned.clone())).unwrap();
+        registry.register(Box::new(pulls_skipped.clone())).unwrap();
+        registry.register(Box::new(pulls_updated.clone())).unwrap();
+        registry.register(Box::new(pulls_failed.clone())).unwrap();
+        registry.register(Box::new(users_unmapped.clone())).unwrap();
+        let cache_lookups = IntCounterVec::new(
+            Opts::new(
+                "tracker_cache_lookups_total",
+                "Card lookups in the tracker cache",
+            ),
+            &["tracker", "result"],
+        )
+        .unwrap();
+
+        registry.register(Box::new(requests.clone())).unwrap();
+        registry.register(Box::new(cache_lookups.clone())).unwrap();
+
+        Self {
+            registry,
+            cycle_duration,
+            seconds_since_success,
+            started: Instant::now(),
+            last_cycle: Mutex::new(None),
+            last_success: Mutex::new(None),
+            pulls_scanned,
+            pulls_skipped,
+            pulls_updated,
+            pulls_failed,
+            users_unmapped,
+            requests,
+            cache_lookups,
+        }
+    }
+}
+
+impl Metrics {
+    pub fn cycle_finished(&self, duration: Duration, success: bool) {
+        self.cycle_duration.observe(duration.as_secs_f64());
+        *self.last_cycle.lock().unwrap() = Some(Instant::now());
+        if success {
+            *self.last_success.lock().unwrap() = Some(Instant::now());
+        }
+    }
+
+    /// Time elapsed since the last finished cycle, successful or not.
+    pub fn since_last_cycle(&self) -> Option<Duration> {
+        self.last_cycle.lock().unwrap().map(|at| at.elapsed())
+    }
+
+    /// Time elapsed since the last successful cycle, if there was one.
+    pub fn since_last_success(&self) -> Option<Duration> {
+        self.last_success.lock().unwrap().map(|at| at.elapsed())
+    }
+
+    pub fn pull_scanned(&self) {
+        self.pulls_scanned.inc();
+    }
+
+    pub fn pull_skipped(&self, reason: &str) {
+        self.pulls_skipped.with_label_values(&[reason]).inc();
+    }
+
+    pub fn pull_updated(&self) {
+        self.pulls_updated.inc();
+    }
+
+    pub fn pull_failed(&self) {
+        self.pulls_failed.inc();
+    }
+
+    pub fn user_unmapped(&self) {
+        self.users_unmapped.inc();
+    }
+
+    /// Counts a cache lookup, `result` is `hit`, `revalidated` or `miss`.
+    pub fn cache_lookup(&self, tracker: &str, result: &str) {
+        self.cache_lookups
+            .with_label_values(&[tracker, result])
+            .inc();
+    }
+
+    pub fn totals(&self) -> Totals {
+        let scanned = self.pulls_scanned.get();
+        let updated = self.pulls_updated.get();
+        let failed = self.pulls_failed.get();
+        // Every scanned pull request is either updated, skipped or failed
+        Totals {
+            scanned,
+            updated,
+            skipped: scanned.saturating_sub(updated + failed),
+            failed,
+        }
+    }
+
+    /// Times an API request, labelling it with the response status.
+    pub async fn observe<T>(
+        &self,
+        api: &str,
+        operation: &str,
+        request: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        let start = Instant::now();
+        let result = request.await;
+        let status = match &result {
+            Ok(_) => "ok".to_string(),
+            Err(e) => status_of(e.as_ref()).map_or("error".to_string(), |s| s.to_string()),
+        };
+        self.requests
+            .with_label_values(&[api, operation, status.as_str()])
+            .observe(start.elapsed().as_secs_f64());
+        result
+    }
+
+    /// Renders all metrics in the Prometheus text format.
+    pub fn render(&self) -> Result<String> {
+        // Counted from the start, so a process that never succeeds still alerts
+        let elapsed = self
+            .since_last_success()
+            .unwrap_or_else(|| self.started.elapsed());
+        self.seconds_since_success.set(elapsed.as_secs_f64());
+        let mut buffer = vec![];
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+}
+
+/// Records request metrics for a GitHub client.
+pub struct MeteredGithub {
+    inner: Box<dyn GithubApi>,
+    metrics: Arc<Metrics>,
+}
+
+impl MeteredGithub {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(inner: Box<dyn GithubApi>, metrics: Arc<Metrics>) -> Box<dyn GithubApi> {
+        Box::new(MeteredGithub { inner, metrics })
+    }
+}
+
+#[async_trait::async_trait]
+impl GithubApi for MeteredGithub {
+    async fn get_issues(&self) -> Result<Vec<Issue>> {
+        self.metrics
+            .observe("github", "get_issues", self.inner.get_issues())
+            .await
+    }
+
+    async fn get_pull_from_url(&self, url: &Url) -> Result<PullRequest> {
+        self.metrics
+            .observe("github", "get_pull", self.inner.get_pull_from_url(url))
+            .await
+    }
+
+    async fn update_pull_request(
+        &self,
+        url: &Url,
+        update: PullRequestUpdate,
+    ) -> Result<PullRequest> {
+        self.metrics
+            .observe(
+                "github",
+                "update_pull",
+                self.inner.update_pull_request(url, update),
+            )
+            .await
+    }
+
+    async fn list_labels(&self, repository: &str) -> Result<Vec<String>> {
+        self.metrics
+            .observe("github", "list_labels", self.inner.list_labels(repository))
+            .await
+    }
+
+    async fn create_label(&self, repository: &str, name: &str) -> Result<()> {
+        self.metrics
+            .observe(
+                "github",
+                "create_label",
+                self.inner.create_label(repository, name),
+            )
+            .await
+    }
+
+    async fn add_labels(&self, pull: &PullRequest, labels: &[String]) -> Result<()> {
+        self.metrics
+            .observe("github", "add_labels", self.inner.add_labels(pull, labels))
+            .await
+    }
+
+    async fn remove_label(&self, pull: &PullRequest, label: &str) -> Result<()> {
+        self.metrics
+            .observe(
+                "github",
+                "remove_label",
+                self.inner.remove_label(pull, label),
+            )
+            .await
+    }
+
+    async fn add_assignees(&self, pull: &PullRequest, assignees: &[String]) -> Result<()> {
+        self.metrics
+            .observe(
+                "github",
+                "add_assignees",
+                self.inner.add_assignees(pull, assignees),
+            )
+            .await
+    }
+
+    async fn request_reviewers(&self, pull: &PullRequest, reviewers: &[String]) -> Result<()> {
+        self.metrics
+            .observe(
+                "github",
+                "request_reviewers",
+                self.inner.request_reviewers(pull, reviewers),
+            )
+            .await
+    }
+
+    async fn get_commit_status(
+        &self,
+        pull: &PullRequest,
+        context: &str,
+    ) -> Result<Option<CommitStatus>> {
+        self.metrics
+            .observe(
+                "github",
+                "get_commit_status",
+                self.inner.get_commit_status(pull, context),
+            )
+            .await
+    }
+
+    async fn set_commit_status(&self, pull: &PullRequest, status: CommitStatus) -> Result<()> {
+        self.metrics
+            .observe(
+                "github",
+                "set_commit_status",
+                self.inner.set_commit_status(pull, status),
+            )
+            .await
+    }
+
+    async fn list_comments(&self, pull: &PullRequest) -> Result<Vec<Comment>> {
+        self.metrics
+            .observe("github", "list_comments", self.inner.list_comments(pull))
+            .await
+    }
+
+    async fn create_comment(&self, pull: &PullRequest, body: &str) -> Result<()> {
+        self.metrics
+            .observe(
+                "github",
+                "create_comment",
+                self.inner.create_comment(pull, body),
+            )
+            .await
+    }
+
+    async fn update_comment(&self, pull: &PullRequest, id: u64, body: &str) -> Result<()> {
+        self.metrics
+            .observe(
+                "github",
+                "update_comment",
+                self.inner.update_comment(pull, id, body),
+            )
+            .await
+    }
+
+    async fn delete_comment(&self, pull: &PullRequest, id: u64) -> Result<()> {
+        self.metrics
+            .observe(
+                "github",
+                "delete_comment",
+                self.inner.delete_comment(pull, id),
+            )
+            .await
+    }
+
+    async fn find_asset(&self, repository: &str, path: &str) -> Result<Option<Url>> {
+        self.metrics
+            .observe(
+                "github",
+                "find_asset",
+                self.inner.find_asset(repository, path),
+            )
+            .await
+    }
+
+    async fn upload_asset(&self, repository: &str, path: &str, content: &[u8]) -> Result<Url> {
+        self.metrics
+            .observe(
+                "github",
+                "upload_asset",
+                self.inner.upload_asset(repository, path, content),
+            )
+            .await
+    }
+
+    async fn find_login_by_email(&self, email: &str) -> Result<Option<String>> {
+        self.metrics
+            .observe(
+                "github",
+                "find_login_by_email",
+                self.inner.find_login_by_email(email),
+            )
+            .await
+    }
+
+    async fn check(&self) -> Result<()> {
+        self.metrics
+            .observe("github", "check", self.inner.check())
+            .await
+    }
+}
+
+/// Records request metrics for a tracker client, labelled with its name.
+pub struct MeteredTracker {
+    name: String,
+    inner: Box<dyn KanbanizeApi>,
+    metrics: Arc<Metrics>,
+}
+
+impl MeteredTracker {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(
+        name: &str,
+        inner: Box<dyn KanbanizeApi>,
+        metrics: Arc<Metrics>,
+    ) -> Box<dyn KanbanizeApi> {
+        Box::new(MeteredTracker {
+            name: name.to_string(),
+            inner,
+            metrics,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl KanbanizeApi for MeteredTracker {
+    fn card_id_from_branch(&self, branch: &str) -> Option<String> {
+        self.inner.card_id_from_branch(branch)
+    }
+
+    async fn find_by_id(&self, id: &str) -> Result<Card> {
+        self.metrics
+            .observe(&self.name, "find_by_id", self.inner.find_by_id(id))
+            .await
+    }
+
+    async fn find_many(&self, ids: &[String]) -> Result<HashMap<String, Card>> {
+        self.metrics
+            .observe(&self.name, "find_many", self.inner.find_many(ids))
+            .await
+    }
+
+    async fn revision(&self, id: &str) -> Result<Option<String>> {
+        self.metrics
+            .observe(&self.name, "revision", self.inner.revision(id))
+            .await
+    }
+
+    async fn revisions(&self, ids: &[String]) -> Result<HashMap<String, String>> {
+        self.metrics
+            .observe(&self.name, "revisions", self.inner.revisions(ids))
+            .await
+    }
+
+    fn branch_pattern(&self) -> &'static str {
+        self.inner.branch_pattern()
+    }
+
+    async fn search(&self, text: &str) -> Result<Vec<Card>> {
+        self.metrics
+            .observe(&self.name, "search", self.inner.search(text))
+            .await
+    }
+
+    async fn cards_in_column(&self, column: &str) -> Result<Vec<Card>> {
+        self.metrics
+            .observe(
+                &self.name,
+                "cards_in_column",
+                self.inner.cards_in_column(column),
+            )
+            .await
+    }
+
+    async fn move_card(&self, id: &str, column: &str) -> Result<()> {
+        self.metrics
+            .observe(&self.name, "move_card", self.inner.move_card(id, column))
+            .await
+    }
+
+    async fn complete_subtask(&self, card_id: &str, subtask_id: &str) -> Result<()> {
+        self.metrics
+            .observe(
+                &self.name,
+                "complete_subtask",
+                self.inner.complete_subtask(card_id, subtask_id),
+            )
+            .await
+    }
+
+    fn is_attachment(&self, url: &Url) -> bool {
+        self.inner.is_attachment(url)
+    }
+
+    async fn download(&self, url: &Url) -> Result<Vec<u8>> {
+        self.metrics
+            .observe(&self.name, "download", self.inner.download(url))
+            .await
+    }
+
+    async fn users(&self) -> Result<Vec<User>> {
+        self.metrics
+            .observe(&self.name, "users", self.inner.users())
+            .await
+    }
+
+    async fn check(&self) -> Result<()> {
+        self.metrics
+            .observe(&self.name, "check", self.inner.check())
+            .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{error::StatusError, kanbanize::MockKanbanizeApi};
+
+    #[tokio::test]
+    async fn test_metered_tracker() -> testresult::TestResult {
+        let metrics = Arc::new(Metrics::default());
+        let mut kanbanize = MockKanbanizeApi::new();
+        kanbanize.expect_find_by_id().returning(|_| {
+            Err(StatusError {
+                status: 404,
+                message: "not found".to_string(),
+            }
+            .into())
+        });
+
+        let tracker = MeteredTracker::new("main", Box::new(kanbanize), metrics.clone());
+        assert!(tracker.find_by_id("123").await.is_err());
+
+        let output = metrics.render()?;
+        assert!(output.contains(
+            r#"gitkban_api_request_duration_seconds_count{api="main",operation="find_by_id",status="404"} 1"#
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_render() -> testresult::TestResult {
+        let metrics = Metrics::default();
+        metrics.pull_scanned();
+        metrics.pull_skipped("has_body");
+        metrics.pull_skipped("has_body");
+        metrics.cycle_finished(Duration::from_millis(10), true);
+
+        let output = metrics.render()?;
+        assert!(output.contains("gitkban_pull_requests_scanned_total 1"));
+        assert!(output.contains(r#"gitkban_pull_requests_skipped_total{reason="has_body"} 2"#));
+        assert!(output.contains("gitkban_cycle_duration_seconds_count 1"));
+        assert!(output.contains("gitkban_seconds_since_last_successful_cycle"));
+        assert_eq!(
+            metrics.totals(),
+            Totals {
+                scanned: 1,
+                updated: 0,
+                skipped: 1,
+                failed: 0
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_before_success() -> testresult::TestResult {
+        let metrics = Metrics::default();
+        std::thread::sleep(Duration::from_millis(10));
+        metrics.cycle_finished(Duration::from_millis(10), false);
+
+        let output = metrics.render()?;
+        let seconds: f64 = output
+            .lines()
+            .find_map(|line| line.strip_prefix("gitkban_seconds_since_last_successful_cycle "))
+            .ok_or("gauge missing")?
+            .parse()?;
+        assert!(seconds >= 0.01);
+        Ok(())
+    }
+}
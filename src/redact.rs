@@ -0,0 +1,200 @@
+use std::{
+    fmt, io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock, RwLock,
+    },
+};
+
+use regex::Regex;
+use tracing_subscriber::fmt::MakeWriter;
+
+static LOG_SENSITIVE: AtomicBool = AtomicBool::new(false);
+static SECRETS: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+const REDACTED: &str = "[redacted]";
+
+/// Disables redaction, set by `--log-sensitive`.
+pub fn set_log_sensitive(enabled: bool) {
+    LOG_SENSITIVE.store(enabled, Ordering::Relaxed);
+}
+
+fn log_sensitive() -> bool {
+    LOG_SENSITIVE.load(Ordering::Relaxed)
+}
+
+/// Registers a credential so it is masked wherever it shows up in the logs.
+pub fn register_secret(secret: &str) {
+    if !secret.is_empty() {
+        SECRETS.write().unwrap().push(secret.to_string());
+    }
+}
+
+/// Wraps a value that must only be logged with `--log-sensitive`, such as
+/// card content or API responses.
+pub struct Sensitive<T>(pub T);
+
+impl<T: fmt::Debug> fmt::Debug for Sensitive<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if log_sensitive() {
+            self.0.fmt(f)
+        } else {
+            f.write_str(REDACTED)
+        }
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for Sensitive<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if log_sensitive() {
+            self.0.fmt(f)
+        } else {
+            f.write_str(REDACTED)
+        }
+    }
+}
+
+/// Masks registered secrets, tokens, API keys and emails in a log line.
+pub fn redact(line: &str) -> String {
+    static PATTERNS: OnceLock<Vec<(Regex, &str)>> = OnceLock::new();
+    let patterns = PATTERNS.get_or_init(|| {
+        vec![
+            // GitHub tokens
+            (
+                Regex::new(r"\b(gh[pousr]_[A-Za-z0-9]{16,}|github_pat_[A-Za-z0-9_]{16,})").unwrap(),
+                REDACTED,
+            ),
+            // Credentials in headers, query strings or debug output
+            (
+                Regex::new(r#"(?i)\b(api_?key|token|authorization|key)(\\?["']?\s*[:=]\s*\\?["']?)(?:bearer\s+)?[^\s"'\\,&}]+"#)
+                    .unwrap(),
+                "${1}${2}[redacted]",
+            ),
+            (
+                Regex::new(r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}").unwrap(),
+                "[email]",
+            ),
+        ]
+    });
+
+    let mut line = line.to_string();
+    for secret in SECRETS.read().unwrap().iter() {
+        line = line.replace(secret.as_str(), REDACTED);
+    }
+    for (pattern, replacement) in patterns {
+        line = pattern.replace_all(&line, *replacement).into_owned();
+    }
+    line
+}
+
+/// Writer factory that redacts every log line before it is written.
+pub struct Redacting<M>(pub M);
+
+impl<'a, M: MakeWriter<'a>> MakeWriter<'a> for Redacting<M> {
+    type Writer = RedactingWriter<M::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingWriter(self.0.make_writer())
+    }
+}
+
+pub struct RedactingWriter<W>(W);
+
+impl<W: io::Write> io::Write for RedactingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if log_sensitive() {
+            return self.0.write(buf);
+        }
+        self.0
+            .write_all(redact(&String::from_utf8_lossy(buf)).as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::kanbanize::Kanbanize;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Buffer {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    #[test]
+    fn test_redact() {
+        register_secret("s3cr3t-registered");
+
+        let line = redact(
+            r#"apikey: "abc123", url: https://api.trello.com/1/cards/x?key=k3y&token=t0k3n, owner: jane.doe@example.com, ghp_abcdefghijklmnopqrstuvwxyz, value s3cr3t-registered"#,
+        );
+        for leaked in [
+            "abc123",
+            "k3y",
+            "t0k3n",
+            "jane.doe@example.com",
+            "ghp_abcdefghijklmnopqrstuvwxyz",
+            "s3cr3t-registered",
+        ] {
+            assert!(!line.contains(leaked), "{} leaked in {}", leaked, line);
+        }
+        assert!(line.contains("https://api.trello.com/1/cards/x"));
+    }
+
+    #[test]
+    fn test_sensitive() {
+        let card = Sensitive("private card content");
+        assert_eq!(format!("{}", card), REDACTED);
+        assert_eq!(format!("{:?}", card), REDACTED);
+    }
+
+    #[tokio::test]
+    async fn test_find_card_logs_nothing_sensitive() -> testresult::TestResult {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::TRACE)
+            .with_writer(Redacting(move || writer.clone()))
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/cards/4321")
+            .with_status(200)
+            .with_body(
+                r#"{"data": {"card_id": 4321, "description": "<p>Private card content</p>"}}"#,
+            )
+            .create_async()
+            .await;
+
+        let client = Kanbanize::new(server.url().as_str(), "kanbanize-api-key");
+        client.find_by_id("4321").await?;
+        mock.assert_async().await;
+
+        let logs = buffer.contents();
+        assert!(logs.contains("Response"), "nothing logged: {}", logs);
+        assert!(!logs.contains("Private card content"), "{}", logs);
+        assert!(!logs.contains("kanbanize-api-key"), "{}", logs);
+        Ok(())
+    }
+}
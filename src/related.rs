@@ -0,0 +1,237 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    kanbanize::{Card, KanbanizeApi, Link, Relation},
+    markdown,
+};
+
+/// Card linked to the card of a pull request, with its parents.
+#[derive(Debug, Clone)]
+pub struct Related {
+    pub relation: Relation,
+    pub card: Card,
+    /// Parent of `card`, when fetched.
+    pub parent: Option<Box<Related>>,
+}
+
+/// Largest number of cards fetched per level, so cards with hundreds of
+/// links don't fetch all of them on every sync.
+const CARDS_PER_LEVEL: usize = 20;
+
+/// Fetches the cards linked to `card`, then the parents of parents up to
+/// `depth` levels, so epics are listed without pulling in whole boards.
+/// Each level is fetched together and holds at most [`CARDS_PER_LEVEL`]
+/// cards. Cards that can't be fetched are left out.
+pub async fn fetch(tracker: &dyn KanbanizeApi, card: &Card, depth: usize) -> Vec<Related> {
+    if depth == 0 {
+        return Vec::new();
+    }
+    let mut seen = HashSet::from([card.id.clone()]);
+    let links: Vec<&Link> = card
+        .links
+        .iter()
+        .filter(|link| seen.insert(link.card_id.clone()))
+        .take(CARDS_PER_LEVEL)
+        .collect();
+    let ids: Vec<String> = links.iter().map(|link| link.card_id.clone()).collect();
+    let mut found = find_many(tracker, &ids).await;
+    // Each linked card followed by its parents
+    let mut chains: Vec<Vec<(Relation, Card)>> = links
+        .iter()
+        .filter_map(|link| Some(vec![(link.relation, found.remove(&link.card_id)?)]))
+        .collect();
+
+    for _ in 1..depth {
+        let mut parents = Vec::new();
+        for (index, chain) in chains.iter().enumerate() {
+            if chain[0].0 != Relation::Parent || parents.len() == CARDS_PER_LEVEL {
+                continue;
+            }
+            let parent = chain.last().and_then(|(_, card)| {
+                card.links
+                    .iter()
+                    .find(|link| link.relation == Relation::Parent)
+            });
+            if let Some(parent) = parent.filter(|link| seen.insert(link.card_id.clone())) {
+                parents.push((index, parent.card_id.clone()));
+            }
+        }
+        if parents.is_empty() {
+            break;
+        }
+        let ids: Vec<String> = parents.iter().map(|(_, id)| id.clone()).collect();
+        let mut found = find_many(tracker, &ids).await;
+        for (index, id) in parents {
+            if let Some(parent) = found.remove(&id) {
+                chains[index].push((Relation::Parent, parent));
+            }
+        }
+    }
+
+    chains
+        .into_iter()
+        .filter_map(|chain| {
+            chain
+                .into_iter()
+                .rev()
+                .fold(None, |parent, (relation, card)| {
+                    Some(Box::new(Related {
+                        relation,
+                        card,
+                        parent,
+                    }))
+                })
+                .map(|related| *related)
+        })
+        .collect()
+}
+
+async fn find_many(tracker: &dyn KanbanizeApi, ids: &[String]) -> HashMap<String, Card> {
+    if ids.is_empty() {
+        return HashMap::new();
+    }
+    match tracker.find_many(ids).await {
+        Ok(cards) => cards,
+        Err(e) => {
+            tracing::warn!("Could not fetch linked cards {:?}: {}", ids, e);
+            HashMap::new()
+        }
+    }
+}
+
+/// Renders the related cards as a list, parents nested under their child.
+pub fn render(related: &[Related]) -> String {
+    let mut section = String::from("### Related cards\n");
+    for related in related {
+        let mut current = Some(related);
+        let mut indent = 0;
+        while let Some(related) = current {
+            section.push_str(&format!(
+                "{:indent$}- {}: {}\n",
+                "",
+                related.relation,
+                item(&related.card),
+                indent = indent
+            ));
+            current = related.parent.as_deref();
+            indent += 2;
+        }
+    }
+    section.trim_end().to_string()
+}
+
+/// Key, title, link and column of a card.
+fn item(card: &Card) -> String {
+    let key = card.custom_id.as_deref().unwrap_or(card.id.as_str());
+    let title = markdown::neutralize_mentions(&card.title.replace(['\r', '\n'], " "))
+        .replace('[', "\\[")
+        .replace(']', "\\]");
+    let mut item = match &card.url {
+        Some(url) => format!("[{} {}]({})", key, title, url),
+        None => format!("{} {}", key, title),
+    };
+    if let Some(column) = card.column_name.as_ref().or(card.column.as_ref()) {
+        item.push_str(&format!(" · {}", column));
+    }
+    item
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::kanbanize::MockKanbanizeApi;
+
+    fn card(id: &str, links: Vec<Link>) -> Card {
+        Card {
+            id: id.to_string(),
+            title: format!("Card {}", id),
+            column_name: Some(String::from("Doing")),
+            url: Some(format!("https://example.com/{}", id)),
+            links,
+            ..Default::default()
+        }
+    }
+
+    fn link(id: &str, relation: Relation) -> Link {
+        Link {
+            card_id: id.to_string(),
+            relation,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetch() {
+        let mut tracker = MockKanbanizeApi::new();
+        tracker.expect_find_many().returning(|ids| {
+            Ok(ids
+                .iter()
+                .filter_map(|id| {
+                    let card = match id.as_str() {
+                        "2" => card("2", vec![link("3", Relation::Parent)]),
+                        "3" => card(
+                            "3",
+                            vec![link("4", Relation::Parent), link("1", Relation::Child)],
+                        ),
+                        "5" => card("5", vec![link("1", Relation::Relative)]),
+                        _ => return None,
+                    };
+                    Some((id.clone(), card))
+                })
+                .collect())
+        });
+        let root = card(
+            "1",
+            vec![
+                link("2", Relation::Parent),
+                link("5", Relation::Relative),
+                link("6", Relation::Child),
+            ],
+        );
+
+        let related = fetch(&tracker, &root, 2).await;
+        assert_eq!(
+            render(&related),
+            "### Related cards\n- Parent: [2 Card 2](https://example.com/2) · Doing\n  - Parent: [3 Card 3](https://example.com/3) · Doing\n- Related: [5 Card 5](https://example.com/5) · Doing"
+        );
+
+        let related = fetch(&tracker, &root, 1).await;
+        assert!(related.iter().all(|related| related.parent.is_none()));
+        assert!(fetch(&tracker, &root, 0).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_many_links() {
+        let mut tracker = MockKanbanizeApi::new();
+        // Only the first cards are fetched, all at once
+        tracker
+            .expect_find_many()
+            .withf(|ids| ids.len() == CARDS_PER_LEVEL && ids[0] == "100")
+            .times(1)
+            .returning(|ids| {
+                Ok(ids
+                    .iter()
+                    .map(|id| (id.clone(), card(id, vec![])))
+                    .collect())
+            });
+        let links = (100..200)
+            .map(|id| link(&id.to_string(), Relation::Child))
+            .collect();
+
+        let related = fetch(&tracker, &card("1", links), 2).await;
+        assert_eq!(related.len(), CARDS_PER_LEVEL);
+    }
+
+    #[test]
+    fn test_render_item() {
+        let mut card = card("7", vec![]);
+        card.custom_id = Some(String::from("REQ-7"));
+        card.title = String::from("Ask @jane about [beta]");
+        card.url = None;
+        card.column_name = None;
+        card.column = Some(String::from("In Review"));
+        assert_eq!(
+            item(&card),
+            "REQ-7 Ask @&#8203;jane about \\[beta\\] · In Review"
+        );
+    }
+}
@@ -0,0 +1,167 @@
+use serde::Serialize;
+
+use crate::kanbanize::Card;
+
+type Error = Box<dyn std::error::Error>;
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Card under review without an open pull request.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Orphan {
+    pub tracker: String,
+    pub id: String,
+    /// Custom ID, or the ID when the card has none.
+    pub key: String,
+    pub title: String,
+    /// Column name, or the configured column when the tracker doesn't name it.
+    pub column: String,
+    pub url: Option<String>,
+}
+
+impl Orphan {
+    pub fn new(tracker: &str, card: Card) -> Self {
+        Self {
+            tracker: tracker.to_string(),
+            key: card.custom_id.unwrap_or_else(|| card.id.clone()),
+            id: card.id,
+            title: card.title,
+            column: card.column_name.or(card.column).unwrap_or_default(),
+            url: card.url,
+        }
+    }
+}
+
+/// Output format of a report.
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum)]
+pub enum Format {
+    /// Aligned columns, for people.
+    #[default]
+    Table,
+    /// Array of objects, for scripts.
+    Json,
+    /// Comma-separated values with a header row, for spreadsheets.
+    Csv,
+}
+
+const HEADERS: [&str; 5] = ["TRACKER", "KEY", "TITLE", "COLUMN", "URL"];
+
+/// Renders the orphaned cards in `format`.
+pub fn render(orphans: &[Orphan], format: Format) -> Result<String> {
+    let rows: Vec<[&str; 5]> = orphans
+        .iter()
+        .map(|orphan| {
+            [
+                orphan.tracker.as_str(),
+                orphan.key.as_str(),
+                orphan.title.as_str(),
+                orphan.column.as_str(),
+                orphan.url.as_deref().unwrap_or_default(),
+            ]
+        })
+        .collect();
+    Ok(match format {
+        Format::Table => table(&rows),
+        Format::Json => serde_json::to_string_pretty(orphans)?,
+        Format::Csv => std::iter::once(HEADERS.map(str::to_lowercase).join(","))
+            .chain(rows.iter().map(|row| row.map(csv_field).join(",")))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    })
+}
+
+/// Columns padded to their widest value, titles on a single line.
+fn table(rows: &[[&str; 5]]) -> String {
+    let rows: Vec<[String; 5]> = std::iter::once(HEADERS)
+        .chain(rows.iter().copied())
+        .map(|row| row.map(|value| value.replace(['\r', '\n'], " ")))
+        .collect();
+    let mut widths = [0; 5];
+    for row in &rows {
+        for (width, value) in widths.iter_mut().zip(row) {
+            *width = (*width).max(value.chars().count());
+        }
+    }
+    rows.iter()
+        .map(|row| {
+            row.iter()
+                .zip(widths)
+                .map(|(value, width)| format!("{:width$}", value, width = width))
+                .collect::<Vec<_>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Quotes a CSV field when it holds a separator, quote or line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn orphans() -> Vec<Orphan> {
+        vec![
+            Orphan::new(
+                "main",
+                Card {
+                    id: "4321".to_string(),
+                    custom_id: Some("REQ-1".to_string()),
+                    title: "Fix login, \"again\"".to_string(),
+                    column: Some("7".to_string()),
+                    column_name: Some("In Review".to_string()),
+                    url: Some("https://example.com/4321".to_string()),
+                    ..Default::default()
+                },
+            ),
+            Orphan::new(
+                "trello",
+                Card {
+                    id: "AbCd1234".to_string(),
+                    title: "Logo".to_string(),
+                    column: Some("5f1e".to_string()),
+                    ..Default::default()
+                },
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_render_table() -> testresult::TestResult {
+        assert_eq!(
+            render(&orphans(), Format::Table)?,
+            "TRACKER  KEY       TITLE               COLUMN     URL\n\
+             main     REQ-1     Fix login, \"again\"  In Review  https://example.com/4321\n\
+             trello   AbCd1234  Logo                5f1e"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_csv() -> testresult::TestResult {
+        assert_eq!(
+            render(&orphans(), Format::Csv)?,
+            "tracker,key,title,column,url\n\
+             main,REQ-1,\"Fix login, \"\"again\"\"\",In Review,https://example.com/4321\n\
+             trello,AbCd1234,Logo,5f1e,"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_json() -> testresult::TestResult {
+        let json: serde_json::Value = serde_json::from_str(&render(&orphans(), Format::Json)?)?;
+        assert_eq!(json[0]["id"], "4321");
+        assert_eq!(json[0]["key"], "REQ-1");
+        assert_eq!(json[1]["url"], serde_json::Value::Null);
+        Ok(())
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::kanbanize::KanbanizeApi;
+
+/// Sends pull requests of matching repositories or branches to a named tracker.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct Route {
+    /// `owner/repo`, or `owner/*` to match every repository of an owner.
+    pub repository: Option<String>,
+    pub branch_prefix: Option<String>,
+    pub tracker: String,
+}
+
+impl Route {
+    fn matches(&self, repository: &str, branch: &str) -> bool {
+        let repository_matches = self
+            .repository
+            .as_deref()
+            .is_none_or(|pattern| repository_matches(pattern, repository));
+        let branch_matches = self
+            .branch_prefix
+            .as_deref()
+            .is_none_or(|prefix| branch.starts_with(prefix));
+        repository_matches && branch_matches
+    }
+}
+
+/// Matches `owner/repo` against `owner/repo`, or `owner/*` for every
+/// repository of an owner.
+pub fn repository_matches(pattern: &str, repository: &str) -> bool {
+    match pattern.strip_suffix("/*") {
+        Some(owner) => repository
+            .split_once('/')
+            .is_some_and(|(o, _)| o.eq_ignore_ascii_case(owner)),
+        None => pattern.eq_ignore_ascii_case(repository),
+    }
+}
+
+/// Registry of tracker clients, resolved per pull request.
+#[derive(Default)]
+pub struct Router {
+    trackers: HashMap<String, Box<dyn KanbanizeApi>>,
+    routes: Vec<Route>,
+    default_tracker: Option<String>,
+}
+
+impl Router {
+    pub fn new(default_tracker: Option<String>) -> Self {
+        Self {
+            default_tracker,
+            ..Default::default()
+        }
+    }
+
+    /// Router that sends every pull request to a single tracker.
+    pub fn single(tracker: Box<dyn KanbanizeApi>) -> Self {
+        let mut router = Self::new(Some("default".to_string()));
+        router.add_tracker("default", tracker);
+        router
+    }
+
+    pub fn add_tracker(&mut self, name: &str, tracker: Box<dyn KanbanizeApi>) {
+        self.trackers.insert(name.to_string(), tracker);
+    }
+
+    pub fn add_route(&mut self, route: Route) {
+        self.routes.push(route);
+    }
+
+    /// Checks that every route and the default point to a registered tracker.
+    pub fn validate(&self) -> Result<(), String> {
+        self.routes
+            .iter()
+            .map(|route| &route.tracker)
+            .chain(self.default_tracker.iter())
+            .find(|name| !self.trackers.contains_key(*name))
+            .map_or(Ok(()), |name| Err(format!("Unknown tracker `{}`", name)))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &dyn KanbanizeApi)> {
+        self.trackers
+            .iter()
+            .map(|(name, tracker)| (name.as_str(), tracker.as_ref()))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn KanbanizeApi> {
+        self.trackers.get(name).map(|tracker| tracker.as_ref())
+    }
+
+    /// Returns the name and client of the tracker for a pull request.
+    /// The first matching route wins, falling back to the default tracker.
+    pub fn resolve(&self, repository: &str, branch: &str) -> Option<(&str, &dyn KanbanizeApi)> {
+        let name = self
+            .routes
+            .iter()
+            .find(|route| route.matches(repository, branch))
+            .map(|route| &route.tracker)
+            .or(self.default_tracker.as_ref())?;
+        self.trackers
+            .get_key_value(name)
+            .map(|(name, tracker)| (name.as_str(), tracker.as_ref()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::kanbanize::MockKanbanizeApi;
+
+    fn router() -> Router {
+        let mut router = Router::new(Some("main".to_string()));
+        router.add_tracker("main", Box::new(MockKanbanizeApi::new()));
+        router.add_tracker("eu", Box::new(MockKanbanizeApi::new()));
+        router.add_tracker("linear", Box::new(MockKanbanizeApi::new()));
+        router.add_route(Route {
+            repository: Some("acme/payments".to_string()),
+            branch_prefix: None,
+            tracker: "eu".to_string(),
+        });
+        router.add_route(Route {
+            repository: Some("acme/*".to_string()),
+            branch_prefix: Some("eng-".to_string()),
+            tracker: "linear".to_string(),
+        });
+        router
+    }
+
+    #[test]
+    fn test_resolve() {
+        let router = router();
+        let name = |repository, branch| router.resolve(repository, branch).map(|(n, _)| n);
+
+        assert_eq!(name("acme/payments", "eng-42"), Some("eu"));
+        assert_eq!(name("Acme/Web", "eng-42"), Some("linear"));
+        assert_eq!(name("acme/web", "request-123"), Some("main"));
+        assert_eq!(name("other/web", "eng-42"), Some("main"));
+    }
+
+    #[test]
+    fn test_resolve_without_default() {
+        let mut router = Router::new(None);
+        router.add_tracker("eu", Box::new(MockKanbanizeApi::new()));
+        router.add_route(Route {
+            repository: Some("acme/payments".to_string()),
+            branch_prefix: None,
+            tracker: "eu".to_string(),
+        });
+
+        assert!(router.resolve("acme/payments", "request-123").is_some());
+        assert!(router.resolve("acme/web", "request-123").is_none());
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut router = router();
+        assert_eq!(router.validate(), Ok(()));
+
+        router.add_route(Route {
+            repository: None,
+            branch_prefix: Some("jira-".to_string()),
+            tracker: "jira".to_string(),
+        });
+        assert_eq!(router.validate(), Err("Unknown tracker `jira`".to_string()));
+    }
+}
@@ -0,0 +1,119 @@
+use std::{convert::Infallible, net::SocketAddr, sync::Arc};
+
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+
+use crate::{health::Health, metrics::Metrics};
+
+/// Serves the operational endpoints until the process exits.
+pub async fn serve(
+    addr: SocketAddr,
+    metrics: Arc<Metrics>,
+    health: Arc<Health>,
+) -> Result<(), hyper::Error> {
+    let make_service = make_service_fn(move |_| {
+        let metrics = metrics.clone();
+        let health = health.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let metrics = metrics.clone();
+                let health = health.clone();
+                async move { Ok::<_, Infallible>(handle(request, &metrics, &health)) }
+            }))
+        }
+    });
+
+    tracing::info!("Listening on {}", addr);
+    Server::bind(&addr).serve(make_service).await
+}
+
+fn handle(request: Request<Body>, metrics: &Metrics, health: &Health) -> Response<Body> {
+    match (request.method(), request.uri().path()) {
+        (&Method::GET, "/healthz") => check(health.live()),
+        (&Method::GET, "/readyz") => check(health.ready()),
+        (&Method::GET, "/metrics") => match metrics.render() {
+            Ok(output) => Response::new(Body::from(output)),
+            Err(e) => {
+                tracing::error!("Error rendering metrics: {}", e);
+                status(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        },
+        _ => status(StatusCode::NOT_FOUND),
+    }
+}
+
+fn check(result: Result<(), String>) -> Response<Body> {
+    match result {
+        Ok(()) => Response::new(Body::from("ok")),
+        Err(reason) => {
+            let mut response = Response::new(Body::from(reason));
+            *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+            response
+        }
+    }
+}
+
+fn status(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::from(status.to_string()));
+    *response.status_mut() = status;
+    response
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    async fn get(path: &str, metrics: &Arc<Metrics>) -> (StatusCode, String) {
+        let health = Health::new(metrics.clone(), Duration::from_secs(60));
+        get_with_health(path, metrics, &health).await
+    }
+
+    async fn get_with_health(
+        path: &str,
+        metrics: &Metrics,
+        health: &Health,
+    ) -> (StatusCode, String) {
+        let request = Request::get(path).body(Body::empty()).unwrap();
+        let response = handle(request, metrics, health);
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_metrics() {
+        let metrics = Arc::new(Metrics::default());
+        metrics.pull_updated();
+
+        let (status, body) = get("/metrics", &metrics).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("gitkban_pull_requests_updated_total 1"));
+    }
+
+    #[tokio::test]
+    async fn test_not_found() {
+        let (status, _) = get("/unknown", &Arc::new(Metrics::default())).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_health() {
+        let metrics = Arc::new(Metrics::default());
+        let health = Health::new(metrics.clone(), Duration::from_secs(60));
+
+        let (status, _) = get_with_health("/healthz", &metrics, &health).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, body) = get_with_health("/readyz", &metrics, &health).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body, "startup checks pending");
+
+        health.set_ready(Ok(()));
+        let (status, body) = get_with_health("/readyz", &metrics, &health).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "ok");
+    }
+}
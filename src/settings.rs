@@ -0,0 +1,197 @@
+use std::{
+    collections::HashMap, fmt, net::SocketAddr, path::PathBuf, str::FromStr, sync::Arc,
+    time::Duration,
+};
+
+use crate::{
+    config::{Config, RepositoryConfig},
+    credentials::Credential,
+    metrics::Metrics,
+    router::Router,
+    telemetry::LogFormat,
+    users::UserMapping,
+};
+
+/// Settings read at startup from the environment and `GITKBAN_CONFIG`.
+pub struct Settings {
+    pub log_format: LogFormat,
+    pub trackers: Router,
+    pub repositories: Vec<RepositoryConfig>,
+    pub users: UserMapping,
+    pub review_columns: HashMap<String, Vec<String>>,
+    pub audit_log: Option<PathBuf>,
+    pub github_token: String,
+    pub track_user: String,
+    pub owner_filter: String,
+    pub http_addr: Option<SocketAddr>,
+    pub health_max_missed_cycles: u32,
+    pub shutdown_timeout: Duration,
+}
+
+/// Every missing or invalid setting found while loading [`Settings`].
+#[derive(Debug, Default)]
+pub struct Problems(Vec<String>);
+
+impl fmt::Display for Problems {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Found {} configuration problem(s):", self.0.len())?;
+        for problem in self.0.iter() {
+            writeln!(f, "  - {}", problem)?;
+        }
+        write!(f, "See the README for every supported setting.")
+    }
+}
+
+impl std::error::Error for Problems {}
+
+impl Problems {
+    fn push(&mut self, problem: impl ToString) {
+        // Multi-line errors, such as the tracker ones, become one entry per line
+        self.0
+            .extend(problem.to_string().lines().map(ToString::to_string));
+    }
+
+    /// Reads a required variable.
+    fn required(&mut self, name: &str) -> Option<String> {
+        match std::env::var(name) {
+            Ok(value) if !value.is_empty() => Some(value),
+            _ => {
+                self.push(format!("{} must be set", name));
+                None
+            }
+        }
+    }
+
+    /// Parses an optional variable, falling back to `default` when unset.
+    fn parsed<T>(&mut self, name: &str, default: T) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        match std::env::var(name) {
+            Err(_) => Some(default),
+            Ok(value) => match value.parse() {
+                Ok(value) => Some(value),
+                Err(e) => {
+                    self.push(format!("{} is invalid (`{}`): {}", name, value, e));
+                    None
+                }
+            },
+        }
+    }
+}
+
+impl Settings {
+    /// Loads every setting, collecting all the problems instead of stopping at
+    /// the first one.
+    pub fn from_env(metrics: &Arc<Metrics>) -> Result<Self, Problems> {
+        let mut problems = Problems::default();
+
+        let log_format = problems.parsed("LOG_FORMAT", LogFormat::Text);
+        let config = match std::env::var("GITKBAN_CONFIG") {
+            Ok(path) => Config::load(&path)
+                .map_err(|e| problems.push(format!("GITKBAN_CONFIG: {}", e)))
+                .ok(),
+            Err(_) => Some(Config::default()),
+        };
+        let trackers = config
+            .as_ref()
+            .and_then(|config| config.router(metrics).map_err(|e| problems.push(e)).ok());
+        let (repositories, users, review_columns, audit_log) = config
+            .map(|config| {
+                (
+                    config.repositories,
+                    config.users,
+                    config.review_columns,
+                    config.audit_log,
+                )
+            })
+            .unwrap_or_default();
+        let github_token = Credential::env("GITHUB_PERSONAL_TOKEN")
+            .resolve()
+            .map_err(|e| problems.push(e))
+            .ok();
+        let track_user = problems.required("GITHUB_TRACK_USER");
+        let owner_filter = problems.required("GITHUB_OWNER_FILTER");
+        let http_addr = match std::env::var("HTTP_ADDR") {
+            Ok(addr) => addr
+                .parse()
+                .map(Some)
+                .map_err(|e| problems.push(format!("HTTP_ADDR is invalid (`{}`): {}", addr, e)))
+                .ok(),
+            Err(_) => Some(None),
+        };
+        let health_max_missed_cycles = problems.parsed("HEALTH_MAX_MISSED_CYCLES", 3);
+        let shutdown_timeout = problems.parsed("SHUTDOWN_TIMEOUT_SECS", 30);
+
+        match (
+            log_format,
+            trackers,
+            github_token,
+            track_user,
+            owner_filter,
+            http_addr,
+            health_max_missed_cycles,
+            shutdown_timeout,
+        ) {
+            (
+                Some(log_format),
+                Some(trackers),
+                Some(github_token),
+                Some(track_user),
+                Some(owner_filter),
+                Some(http_addr),
+                Some(health_max_missed_cycles),
+                Some(shutdown_timeout),
+            ) if problems.0.is_empty() => Ok(Settings {
+                log_format,
+                trackers,
+                repositories,
+                users,
+                review_columns,
+                audit_log,
+                github_token,
+                track_user,
+                owner_filter,
+                http_addr,
+                health_max_missed_cycles,
+                shutdown_timeout: Duration::from_secs(shutdown_timeout),
+            }),
+            _ => Err(problems),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_problems_are_collected() {
+        std::env::set_var("GITKBAN_TEST_SETTINGS_NUMBER", "many");
+        std::env::set_var("GITKBAN_TEST_SETTINGS_PRESENT", "value");
+
+        let mut problems = Problems::default();
+        assert_eq!(problems.required("GITKBAN_TEST_SETTINGS_MISSING"), None);
+        assert_eq!(
+            problems.required("GITKBAN_TEST_SETTINGS_PRESENT"),
+            Some("value".to_string())
+        );
+        assert_eq!(problems.parsed("GITKBAN_TEST_SETTINGS_NUMBER", 3u32), None);
+        assert_eq!(
+            problems.parsed("GITKBAN_TEST_SETTINGS_UNSET", 3u32),
+            Some(3)
+        );
+        problems.push("tracker `a`: api_key: missing\ntracker `b`: token: missing");
+
+        assert_eq!(
+            problems.to_string(),
+            "Found 4 configuration problem(s):
+  - GITKBAN_TEST_SETTINGS_MISSING must be set
+  - GITKBAN_TEST_SETTINGS_NUMBER is invalid (`many`): invalid digit found in string
+  - tracker `a`: api_key: missing
+  - tracker `b`: token: missing
+See the README for every supported setting."
+        );
+    }
+}
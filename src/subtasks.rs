@@ -0,0 +1,110 @@
+use crate::{kanbanize::Subtask, markdown};
+
+/// Delimits the task list gitkban manages in a pull request body.
+const START_MARKER: &str = "<!-- gitkban:subtasks -->";
+const END_MARKER: &str = "<!-- /gitkban:subtasks -->";
+
+/// Renders subtasks as a GitHub task list. Every item keeps the subtask ID in
+/// a comment, so boxes ticked on GitHub can be traced back to the card.
+pub fn render(subtasks: &[Subtask]) -> String {
+    let mut section = format!("{}\n### Subtasks\n", START_MARKER);
+    for subtask in subtasks {
+        section.push_str(&format!(
+            "- [{}] {} <!-- subtask:{} -->\n",
+            if subtask.finished { "x" } else { " " },
+            markdown::neutralize_mentions(&subtask.description.replace(['\r', '\n'], " ")),
+            subtask.id
+        ));
+    }
+    section.push_str(END_MARKER);
+    section
+}
+
+/// Range of the managed section in `body`, markers included.
+fn section(body: &str) -> Option<std::ops::Range<usize>> {
+    let start = body.find(START_MARKER)?;
+    let end = start + body[start..].find(END_MARKER)? + END_MARKER.len();
+    Some(start..end)
+}
+
+/// IDs of the subtasks ticked in the task list of `body`, or `None` when the
+/// body has no task list managed by gitkban.
+pub fn checked(body: &str) -> Option<Vec<String>> {
+    let re = regex::Regex::new(r"^\s*[-*] \[[xX]\] .*<!-- subtask:(\S+) -->\s*$").unwrap();
+    Some(
+        body[section(body)?]
+            .lines()
+            .filter_map(|line| re.captures(line))
+            .map(|cap| cap[1].to_string())
+            .collect(),
+    )
+}
+
+/// Replaces the task list of `body` with `subtasks`, keeping the rest of the
+/// body untouched. Bodies without a task list are returned as is.
+pub fn replace(body: &str, subtasks: &[Subtask]) -> String {
+    match section(body) {
+        Some(range) => format!(
+            "{}{}{}",
+            &body[..range.start],
+            render(subtasks),
+            &body[range.end..]
+        ),
+        None => body.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn subtasks() -> Vec<Subtask> {
+        vec![
+            Subtask {
+                id: "1".to_string(),
+                description: "Write tests".to_string(),
+                finished: false,
+            },
+            Subtask {
+                id: "2".to_string(),
+                description: "Design\nthe page".to_string(),
+                finished: true,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_render() {
+        assert_eq!(
+            render(&subtasks()),
+            "<!-- gitkban:subtasks -->\n### Subtasks\n- [ ] Write tests <!-- subtask:1 -->\n- [x] Design the page <!-- subtask:2 -->\n<!-- /gitkban:subtasks -->"
+        );
+    }
+
+    #[test]
+    fn test_checked() {
+        let body = format!("Description\n\n{}", render(&subtasks()));
+        assert_eq!(checked(&body), Some(vec!["2".to_string()]));
+
+        let ticked = body.replace("- [ ] Write", "- [x] Write");
+        assert_eq!(
+            checked(&ticked),
+            Some(vec!["1".to_string(), "2".to_string()])
+        );
+
+        // Task lists outside the section are not gitkban's
+        assert_eq!(checked("- [x] Done <!-- subtask:1 -->"), None);
+    }
+
+    #[test]
+    fn test_replace() {
+        let body = format!("Description\n\n{}\n\nNotes", render(&subtasks()));
+        let mut finished = subtasks();
+        finished[0].finished = true;
+        assert_eq!(
+            replace(&body, &finished),
+            format!("Description\n\n{}\n\nNotes", render(&finished))
+        );
+        assert_eq!(replace("Description", &finished), "Description");
+    }
+}
@@ -0,0 +1,101 @@
+use std::str::FromStr;
+
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
+
+use crate::redact::Redacting;
+
+type Error = Box<dyn std::error::Error>;
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Output format of the logs written to stdout.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!("Unknown log format `{}`", other)),
+        }
+    }
+}
+
+/// Installs the global subscriber. Logs are filtered with `RUST_LOG` and
+/// masked by [`Redacting`]. When built with the `otlp` feature, spans are
+/// exported to the collector in `OTEL_EXPORTER_OTLP_ENDPOINT`.
+pub fn init(format: LogFormat) -> Result<()> {
+    let writer = Redacting(std::io::stdout);
+    let fmt = match format {
+        LogFormat::Text => tracing_subscriber::fmt::layer().with_writer(writer).boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_writer(writer)
+            .boxed(),
+    };
+    let registry = tracing_subscriber::registry()
+        .with(EnvFilter::from_default_env())
+        .with(fmt);
+
+    #[cfg(feature = "otlp")]
+    let registry = registry.with(match std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT") {
+        Ok(endpoint) => Some(otlp::layer(&endpoint)?),
+        Err(_) => None,
+    });
+
+    registry.try_init()?;
+    Ok(())
+}
+
+/// Flushes pending spans before the process exits.
+pub fn shutdown() {
+    #[cfg(feature = "otlp")]
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+#[cfg(feature = "otlp")]
+mod otlp {
+    use opentelemetry_otlp::WithExportConfig;
+    use tracing_subscriber::{registry::LookupSpan, Layer};
+
+    pub fn layer<S>(endpoint: &str) -> super::Result<impl Layer<S>>
+    where
+        S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+    {
+        let tracer = opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(
+                opentelemetry_otlp::new_exporter()
+                    .tonic()
+                    .with_endpoint(endpoint),
+            )
+            .with_trace_config(opentelemetry_sdk::trace::config().with_resource(
+                opentelemetry_sdk::Resource::new(vec![opentelemetry::KeyValue::new(
+                    "service.name",
+                    "gitkban",
+                )]),
+            ))
+            .install_batch(opentelemetry_sdk::runtime::TokioCurrentThread)?;
+        Ok(tracing_opentelemetry::layer().with_tracer(tracer))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_log_format() {
+        assert_eq!("json".parse(), Ok(LogFormat::Json));
+        assert_eq!("Text".parse(), Ok(LogFormat::Text));
+        assert_eq!(
+            "xml".parse::<LogFormat>(),
+            Err("Unknown log format `xml`".to_string())
+        );
+    }
+}
@@ -0,0 +1,433 @@
+use std::collections::HashMap;
+
+use url::Url;
+
+use crate::{
+    kanbanize::{self, Card, KanbanizeApi, Markup, Subtask, User},
+    redact::Sensitive,
+};
+
+type Error = Box<dyn std::error::Error>;
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug)]
+pub struct Trello {
+    client: reqwest::Client,
+    base_path: String,
+    api_key: String,
+    token: String,
+}
+
+impl Trello {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(base_path: &str, api_key: &str, token: &str) -> Box<dyn KanbanizeApi> {
+        Box::new(Trello {
+            client: reqwest::Client::new(),
+            base_path: base_path.trim_end_matches('/').to_string(),
+            api_key: api_key.to_string(),
+            token: token.to_string(),
+        })
+    }
+
+    async fn get(&self, path: &str, query: &[(&str, &str)]) -> Result<serde_json::Value> {
+        self.send(reqwest::Method::GET, path, query).await
+    }
+
+    async fn send(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<serde_json::Value> {
+        let result: serde_json::Value = self
+            .client
+            .request(method, format!("{}{}", self.base_path, path))
+            .query(&[
+                ("key", self.api_key.as_str()),
+                ("token", self.token.as_str()),
+            ])
+            .query(query)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        tracing::debug!("Response: {:?}", Sensitive(&result));
+        Ok(result)
+    }
+}
+
+#[async_trait::async_trait]
+impl KanbanizeApi for Trello {
+    fn card_id_from_branch(&self, branch: &str) -> Option<String> {
+        // Short links are 8 alphanumeric characters, require both letters and digits
+        // so plain words like `refactor` are not taken as a card
+        branch
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .find(|part| {
+                part.len() == 8
+                    && part.chars().any(|c| c.is_ascii_digit())
+                    && part.chars().any(|c| c.is_ascii_alphabetic())
+            })
+            .map(|part| part.to_string())
+    }
+
+    fn branch_pattern(&self) -> &'static str {
+        "the card short link from the card URL, such as `AbCd1234-fix-login`"
+    }
+
+    async fn find_by_id(&self, id: &str) -> Result<Card> {
+        let result = self
+            .get(
+                &format!("/cards/{}", id),
+                &[
+                    (
+                        "fields",
+                        "name,desc,labels,idMembers,idList,shortUrl,dateLastActivity",
+                    ),
+                    ("checklists", "all"),
+                    ("list", "true"),
+                ],
+            )
+            .await?;
+
+        Ok(Card {
+            id: id.to_string(),
+            title: result["name"].as_str().unwrap_or_default().to_string(),
+            description: result["desc"].as_str().unwrap_or_default().to_string(),
+            markup: Markup::Markdown,
+            tags: result["labels"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|label| label["name"].as_str())
+                .filter(|name| !name.is_empty())
+                .map(ToString::to_string)
+                .collect(),
+            column: result["idList"].as_str().map(ToString::to_string),
+            column_name: result["list"]["name"].as_str().map(ToString::to_string),
+            url: result["shortUrl"].as_str().map(ToString::to_string),
+            revision: result["dateLastActivity"].as_str().map(ToString::to_string),
+            co_owners: result["idMembers"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|id| id.as_str())
+                .map(User::new)
+                .collect(),
+            subtasks: result["checklists"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|checklist| checklist["checkItems"].as_array())
+                .flatten()
+                .filter_map(|item| {
+                    Some(Subtask {
+                        id: item["id"].as_str()?.to_string(),
+                        description: item["name"].as_str().unwrap_or_default().to_string(),
+                        finished: item["state"].as_str() == Some("complete"),
+                    })
+                })
+                .collect(),
+            ..Default::default()
+        })
+    }
+
+    async fn find_many(&self, ids: &[String]) -> Result<HashMap<String, Card>> {
+        kanbanize::find_each(self, ids).await
+    }
+
+    async fn revision(&self, id: &str) -> Result<Option<String>> {
+        let result = self
+            .get(&format!("/cards/{}", id), &[("fields", "dateLastActivity")])
+            .await?;
+        Ok(result["dateLastActivity"].as_str().map(ToString::to_string))
+    }
+
+    async fn revisions(&self, ids: &[String]) -> Result<HashMap<String, String>> {
+        kanbanize::revision_each(self, ids).await
+    }
+
+    async fn search(&self, text: &str) -> Result<Vec<Card>> {
+        let result = self
+            .get(
+                "/search",
+                &[
+                    ("query", text),
+                    ("modelTypes", "cards"),
+                    ("cards_limit", "5"),
+                    ("card_fields", "name,shortLink"),
+                ],
+            )
+            .await?;
+        Ok(result["cards"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|card| {
+                Some(Card {
+                    id: card["shortLink"].as_str()?.to_string(),
+                    title: card["name"].as_str().unwrap_or_default().to_string(),
+                    ..Default::default()
+                })
+            })
+            .collect())
+    }
+
+    async fn cards_in_column(&self, column: &str) -> Result<Vec<Card>> {
+        let result = self
+            .get(
+                &format!("/lists/{}/cards", column),
+                &[("fields", "name,shortLink,shortUrl")],
+            )
+            .await?;
+        Ok(result
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|card| {
+                Some(Card {
+                    id: card["shortLink"].as_str()?.to_string(),
+                    title: card["name"].as_str().unwrap_or_default().to_string(),
+                    url: card["shortUrl"].as_str().map(ToString::to_string),
+                    column: Some(column.to_string()),
+                    ..Default::default()
+                })
+            })
+            .collect())
+    }
+
+    async fn move_card(&self, id: &str, column: &str) -> Result<()> {
+        let is_id = column.len() == 24 && column.chars().all(|c| c.is_ascii_hexdigit());
+        let list = if is_id {
+            column.to_string()
+        } else {
+            // List names are looked up on the board of the card
+            let card = self
+                .get(&format!("/cards/{}", id), &[("fields", "idBoard")])
+                .await?;
+            let board = card["idBoard"]
+                .as_str()
+                .ok_or_else(|| format!("card {} has no board", id))?;
+            let lists = self
+                .get(&format!("/boards/{}/lists", board), &[("fields", "name")])
+                .await?;
+            lists
+                .as_array()
+                .into_iter()
+                .flatten()
+                .find(|list| list["name"] == column)
+                .and_then(|list| list["id"].as_str())
+                .ok_or_else(|| format!("no list `{}` on board {}", column, board))?
+                .to_string()
+        };
+        self.send(
+            reqwest::Method::PUT,
+            &format!("/cards/{}", id),
+            &[("idList", list.as_str())],
+        )
+        .await
+        .map(|_| ())
+    }
+
+    async fn complete_subtask(&self, card_id: &str, subtask_id: &str) -> Result<()> {
+        self.send(
+            reqwest::Method::PUT,
+            &format!("/cards/{}/checkItem/{}", card_id, subtask_id),
+            &[("state", "complete")],
+        )
+        .await
+        .map(|_| ())
+    }
+
+    fn is_attachment(&self, url: &Url) -> bool {
+        url.host_str() == Some("trello.com") && url.path().contains("/attachments/")
+    }
+
+    async fn download(&self, url: &Url) -> Result<Vec<u8>> {
+        // Attachments only accept the credentials in the OAuth header
+        let response = self
+            .client
+            .get(url.clone())
+            .header(
+                "Authorization",
+                format!(
+                    "OAuth oauth_consumer_key=\"{}\", oauth_token=\"{}\"",
+                    self.api_key, self.token
+                ),
+            )
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(response.bytes().await?.to_vec())
+    }
+
+    async fn users(&self) -> Result<Vec<User>> {
+        // Trello doesn't expose member emails
+        Ok(Vec::new())
+    }
+
+    async fn check(&self) -> Result<()> {
+        self.get("/members/me", &[("fields", "id")])
+            .await
+            .map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mockito::Matcher;
+
+    #[tokio::test]
+    async fn test_find_card() -> testresult::TestResult {
+        let mut server = mockito::Server::new_async().await;
+
+        let mock = server
+            .mock("GET", "/cards/AbCd1234")
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("key".into(), "api_key".into()),
+                Matcher::UrlEncoded("token".into(), "token".into()),
+            ]))
+            .with_status(200)
+            .with_body(r#"{"id": "5f1e", "name": "Login page", "desc": "Card *description*", "dateLastActivity": "2023-10-01T10:00:00.000Z", "list": {"id": "5f1d", "name": "Doing"}, "labels": [{"name": "Bug", "color": "red"}], "checklists": [{"id": "c1", "checkItems": [{"id": "i1", "name": "Write tests", "state": "complete"}]}]}"#)
+            .create_async()
+            .await;
+
+        let client = Trello::new(server.url().as_str(), "api_key", "token");
+        let card = client.find_by_id("AbCd1234").await?;
+
+        assert_eq!(card.title, "Login page");
+        assert_eq!(card.column_name, Some("Doing".to_string()));
+        assert_eq!(card.revision, Some("2023-10-01T10:00:00.000Z".to_string()));
+        assert_eq!(card.tags, vec!["Bug".to_string()]);
+        assert_eq!(card.description, "Card *description*".to_string());
+        assert_eq!(card.markup, Markup::Markdown);
+        assert_eq!(
+            card.subtasks,
+            vec![Subtask {
+                id: "i1".to_string(),
+                description: "Write tests".to_string(),
+                finished: true,
+            }]
+        );
+
+        mock.assert_async().await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_find_card_not_found() -> testresult::TestResult {
+        let mut server = mockito::Server::new_async().await;
+
+        let mock = server
+            .mock("GET", "/cards/AbCd1234")
+            .match_query(Matcher::Any)
+            .with_status(404)
+            .create_async()
+            .await;
+
+        let client = Trello::new(server.url().as_str(), "api_key", "token");
+        let card = client.find_by_id("AbCd1234").await;
+        assert!(card.is_err());
+
+        mock.assert_async().await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_search() -> testresult::TestResult {
+        let mut server = mockito::Server::new_async().await;
+
+        let mock = server
+            .mock("GET", "/search")
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("query".into(), "Login page".into()),
+                Matcher::UrlEncoded("modelTypes".into(), "cards".into()),
+            ]))
+            .with_status(200)
+            .with_body(
+                r#"{"cards": [{"id": "5f1e", "name": "Login page", "shortLink": "AbCd1234"}]}"#,
+            )
+            .create_async()
+            .await;
+
+        let client = Trello::new(server.url().as_str(), "api_key", "token");
+        let cards = client.search("Login page").await?;
+        assert_eq!(cards.len(), 1);
+        assert_eq!(cards[0].id, "AbCd1234");
+        assert_eq!(cards[0].title, "Login page");
+
+        mock.assert_async().await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_move_card() -> testresult::TestResult {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/cards/AbCd1234")
+            .match_query(Matcher::UrlEncoded("fields".into(), "idBoard".into()))
+            .with_status(200)
+            .with_body(r#"{"id": "5f1e", "idBoard": "b1"}"#)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/boards/b1/lists")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(r#"[{"id": "5f1e00000000000000000001", "name": "Doing"}, {"id": "5f1e00000000000000000002", "name": "Review"}]"#)
+            .create_async()
+            .await;
+        let mock = server
+            .mock("PUT", "/cards/AbCd1234")
+            .match_query(Matcher::UrlEncoded(
+                "idList".into(),
+                "5f1e00000000000000000002".into(),
+            ))
+            .with_status(200)
+            .with_body("{}")
+            .expect(2)
+            .create_async()
+            .await;
+
+        let client = Trello::new(server.url().as_str(), "api_key", "token");
+        client.move_card("AbCd1234", "Review").await?;
+        client
+            .move_card("AbCd1234", "5f1e00000000000000000002")
+            .await?;
+        assert!(client.move_card("AbCd1234", "Done").await.is_err());
+
+        mock.assert_async().await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_check() -> testresult::TestResult {
+        let mut server = mockito::Server::new_async().await;
+
+        let mock = server
+            .mock("GET", "/members/me")
+            .match_query(Matcher::UrlEncoded("token".into(), "token".into()))
+            .with_status(401)
+            .create_async()
+            .await;
+
+        let client = Trello::new(server.url().as_str(), "api_key", "token");
+        let error = client.check().await.err().unwrap();
+        assert_eq!(crate::error::status_of(error.as_ref()), Some(401));
+
+        mock.assert_async().await;
+        Ok(())
+    }
+
+    #[test]
+    fn test_card_id_from_branch() {
+        let client = Trello::new("http://localhost", "api_key", "token");
+        assert_eq!(
+            client.card_id_from_branch("feature/AbCd1234-login"),
+            Some("AbCd1234".to_string())
+        );
+        assert_eq!(client.card_id_from_branch("refactor/parser"), None);
+    }
+}
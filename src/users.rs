@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::{
+    github::GithubApi,
+    kanbanize::{KanbanizeApi, User},
+};
+
+type Error = Box<dyn std::error::Error>;
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Maps tracker users to GitHub logins.
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+pub struct UserMapping {
+    /// GitHub login by tracker user ID or email.
+    #[serde(default)]
+    pub logins: HashMap<String, String>,
+    /// Searches GitHub for users with the same public email.
+    #[serde(default)]
+    pub discover: bool,
+}
+
+/// What a card user becomes on the pull request.
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    None,
+    Assignee,
+    Reviewer,
+}
+
+/// Roles of the card users on the pull requests of a repository.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct PeopleConfig {
+    #[serde(default = "PeopleConfig::default_owner")]
+    pub owner: Role,
+    #[serde(default = "PeopleConfig::default_co_owners")]
+    pub co_owners: Role,
+    #[serde(default)]
+    pub watchers: Role,
+}
+
+impl PeopleConfig {
+    fn default_owner() -> Role {
+        Role::Assignee
+    }
+
+    fn default_co_owners() -> Role {
+        Role::Reviewer
+    }
+}
+
+impl Default for PeopleConfig {
+    fn default() -> Self {
+        Self {
+            owner: Self::default_owner(),
+            co_owners: Self::default_co_owners(),
+            watchers: Role::None,
+        }
+    }
+}
+
+/// Resolves tracker users to GitHub logins, fetching the tracker users at
+/// most once when an email is needed. Users stay unmapped when the tracker
+/// users can't be fetched.
+pub struct Resolver<'a> {
+    mapping: &'a UserMapping,
+    github: &'a dyn GithubApi,
+    tracker: &'a dyn KanbanizeApi,
+    tracker_users: Option<Vec<User>>,
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new(
+        mapping: &'a UserMapping,
+        github: &'a dyn GithubApi,
+        tracker: &'a dyn KanbanizeApi,
+    ) -> Self {
+        Self {
+            mapping,
+            github,
+            tracker,
+            tracker_users: None,
+        }
+    }
+
+    pub async fn login(&mut self, user: &User) -> Result<Option<String>> {
+        if let Some(login) = self.mapping.logins.get(&user.id) {
+            return Ok(Some(login.clone()));
+        }
+
+        let Some(email) = self.email(user).await? else {
+            return Ok(None);
+        };
+        let mapped = self
+            .mapping
+            .logins
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(&email))
+            .map(|(_, login)| login.clone());
+        if mapped.is_some() || !self.mapping.discover {
+            return Ok(mapped);
+        }
+        self.github.find_login_by_email(&email).await
+    }
+
+    async fn email(&mut self, user: &User) -> Result<Option<String>> {
+        if user.email.is_some() {
+            return Ok(user.email.clone());
+        }
+        if self.tracker_users.is_none() {
+            let users = self.tracker.users().await.unwrap_or_else(|e| {
+                tracing::warn!("Could not fetch the tracker users: {}", e);
+                Vec::new()
+            });
+            self.tracker_users = Some(users);
+        }
+        Ok(self
+            .tracker_users
+            .iter()
+            .flatten()
+            .find(|candidate| candidate.id == user.id)
+            .and_then(|candidate| candidate.email.clone()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{github::MockGithubApi, kanbanize::MockKanbanizeApi};
+
+    #[tokio::test]
+    async fn test_login() -> testresult::TestResult {
+        let mapping: UserMapping = toml::from_str(
+            r#"
+            discover = true
+
+            [logins]
+            "17" = "jane-gh"
+            "john@example.com" = "john-gh"
+            "#,
+        )?;
+        let mut github = MockGithubApi::new();
+        github
+            .expect_find_login_by_email()
+            .with(mockall::predicate::eq("ana@example.com"))
+            .return_once(|_| Ok(Some("ana-gh".to_string())));
+        let mut tracker = MockKanbanizeApi::new();
+        tracker.expect_users().times(1).return_once(|| {
+            Ok(vec![
+                User {
+                    id: "18".to_string(),
+                    email: Some("John@example.com".to_string()),
+                },
+                User {
+                    id: "19".to_string(),
+                    email: Some("ana@example.com".to_string()),
+                },
+            ])
+        });
+
+        let mut resolver = Resolver::new(&mapping, &github, &tracker);
+        assert_eq!(
+            resolver.login(&User::new(17)).await?,
+            Some("jane-gh".to_string())
+        );
+        assert_eq!(
+            resolver.login(&User::new(18)).await?,
+            Some("john-gh".to_string())
+        );
+        assert_eq!(
+            resolver.login(&User::new(19)).await?,
+            Some("ana-gh".to_string())
+        );
+        assert_eq!(resolver.login(&User::new(20)).await?, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_login_tracker_users_failed() -> testresult::TestResult {
+        let mapping = UserMapping::default();
+        let github = MockGithubApi::new();
+        let mut tracker = MockKanbanizeApi::new();
+        tracker
+            .expect_users()
+            .times(1)
+            .return_once(|| Err("unavailable".into()));
+
+        let mut resolver = Resolver::new(&mapping, &github, &tracker);
+        assert_eq!(resolver.login(&User::new(18)).await?, None);
+        assert_eq!(resolver.login(&User::new(19)).await?, None);
+        Ok(())
+    }
+}